@@ -1,16 +1,114 @@
 use pyo3::prelude::*;
 use pyo3::wrap_pyfunction;
 use std::fs::File;
-use std::io::{BufReader, BufWriter, Write, BufRead, Result};
+use std::io::{BufReader, BufWriter, Write, BufRead, Error, ErrorKind, Result};
+use std::str::FromStr;
 
 const HEADER_LINES: usize = 15;
 const EXPECTED_TOKENS: usize = 13;
 
+/// Right-aligned column widths of a CLIGEN daily record.
+const FIELD_WIDTHS: [usize; EXPECTED_TOKENS] = [3, 3, 5, 6, 6, 5, 7, 6, 6, 5, 5, 6, 6];
+const FIELD_NAMES: [&str; EXPECTED_TOKENS] = [
+    "da", "mo", "year", "prcp", "dur", "tp", "ip", "tmax", "tmin", "rad", "w-vl", "w-dir", "tdew"
+];
+
+/// Writes one daily record using the fixed CLIGEN column widths.
+///
+/// Every field must be strictly narrower than its column so that at least one
+/// space separates it from its neighbour; otherwise the columns would run
+/// together and WEPP would misread the record, so an `InvalidData` error
+/// naming the offending field is returned instead.
+fn write_record<W: Write, S: AsRef<str>>(w: &mut W, fields: &[S]) -> Result<()> {
+    let mut record = String::with_capacity(64);
+    for ((field, &width), name) in fields.iter().zip(FIELD_WIDTHS.iter()).zip(FIELD_NAMES.iter()) {
+        let field = field.as_ref();
+        if field.len() >= width {
+            return Err(Error::new(ErrorKind::InvalidData, format!(
+                "{} value {} does not fit in a {} character column", name, field, width)));
+        }
+        record.push_str(&format!("{:>width$}", field, width = width));
+    }
+    record.push('\n');
+    w.write_all(record.as_bytes())
+}
+
+
+/// How the hillslope monthly values are applied to the daily records.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RevisionMode {
+    /// Scale precipitation by `hill / ws` and shift temperatures by `hill - ws`.
+    Relative,
+    /// Ignore the watershed values: scale precipitation by `hill` and shift
+    /// temperatures by `hill` directly.
+    Absolute,
+}
+
+impl FromStr for RevisionMode {
+    type Err = ();
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            "relative" => Ok(RevisionMode::Relative),
+            "absolute" => Ok(RevisionMode::Absolute),
+            _ => Err(()),
+        }
+    }
+}
+
 
 pub fn rust_cli_revision(src_fn: &str, dst_fn: &str, 
     ws_ppts: [f64; 12], ws_tmaxs: [f64; 12], ws_tmins:  [f64; 12],
     hill_ppts: [f64; 12], hill_tmaxs: [f64; 12], hill_tmins:  [f64; 12],
+    mode: RevisionMode,
+    precision: usize,
 ) -> Result<()> {
+    // "0." plus the decimals must leave room for a separating space in the
+    // prcp, tmax, and tmin columns
+    if precision + 2 >= FIELD_WIDTHS[3].min(FIELD_WIDTHS[7]).min(FIELD_WIDTHS[8]) {
+        return Err(Error::new(ErrorKind::InvalidInput, format!(
+            "precision {} is too large for the CLIGEN column widths", precision)));
+    }
+
+    transform_cli(src_fn, dst_fn, |tokens| {
+        let mo: i32 = parse_field(tokens, 1)?;
+        let mut prcp_f: f64 = parse_field(tokens, 3)?;
+        let mut tmax_f: f64 = parse_field(tokens, 7)?;
+        let mut tmin_f: f64 = parse_field(tokens, 8)?;
+
+        let indx = (mo - 1) as usize;
+        match mode {
+            RevisionMode::Relative => {
+                prcp_f = prcp_f * hill_ppts[indx] / ws_ppts[indx];
+                tmax_f = tmax_f - ws_tmaxs[indx] + hill_tmaxs[indx];
+                tmin_f = tmin_f - ws_tmins[indx] + hill_tmins[indx];
+            },
+            RevisionMode::Absolute => {
+                prcp_f = prcp_f * hill_ppts[indx];
+                tmax_f = tmax_f + hill_tmaxs[indx];
+                tmin_f = tmin_f + hill_tmins[indx];
+            },
+        }
+
+        let mut fields: Vec<String> = tokens.iter().map(|t| t.to_string()).collect();
+        fields[1] = mo.to_string();
+        fields[3] = format!("{:.*}", precision, prcp_f);
+        fields[7] = format!("{:.*}", precision, tmax_f);
+        fields[8] = format!("{:.*}", precision, tmin_f);
+        Ok(fields)
+    })
+}
+
+
+/// Copies the CLIGEN file `src_fn` to `dst_fn`, passing the 13 whitespace separated
+/// tokens of every daily record through `row_fn` and writing the fields it returns
+/// with the fixed CLIGEN column widths (see `write_record`).
+///
+/// The header is copied verbatim and lines that are not 13 token records are dropped.
+pub fn transform_cli<F>(src_fn: &str, dst_fn: &str, mut row_fn: F) -> Result<()>
+where
+    F: FnMut(&[&str]) -> Result<Vec<String>>,
+{
     let src_f = File::open(src_fn)?;
     let mut src_r = BufReader::new(src_f);
 
@@ -27,37 +125,33 @@ pub fn rust_cli_revision(src_fn: &str, dst_fn: &str,
     while src_r.read_line(&mut line)? > 0 {
         let tokens: Vec<&str> = line.split_whitespace().collect();
         if tokens.len() == EXPECTED_TOKENS {
-            let da = tokens[0];
-            let mo: i32 = tokens[1].parse().unwrap();
-            let year = tokens[2];
-            let mut prcp_f: f64 = tokens[3].parse().unwrap();
-            let dur = tokens[4];
-            let tp = tokens[5];
-            let ip = tokens[6];
-            let mut tmax_f: f64 = tokens[7].parse().unwrap();
-            let mut tmin_f: f64 = tokens[8].parse().unwrap();
-            let rad = tokens[9];
-            let w_vl = tokens[10];
-            let w_dir = tokens[11];
-            let tdew = tokens[12];
-        
-            let indx = (mo - 1) as usize;
-            prcp_f = prcp_f * hill_ppts[indx] / ws_ppts[indx];
-            tmax_f = tmax_f - ws_tmaxs[indx] + hill_tmaxs[indx];
-            tmin_f = tmin_f - ws_tmins[indx] + hill_tmins[indx];
-
-            let prcp = format!("{:.1}", prcp_f);
-            let tmax = format!("{:.1}", tmax_f);
-            let tmin = format!("{:.1}", tmin_f);
-
-            dst_w.write_all(format!(
-                "{:>3}{:>3}{:>5}{:>6}{:>6}{:>5}{:>7}{:>6}{:>6}{:>5}{:>5}{:>6}{:>6}\n",
-                da, mo, year, prcp, dur, tp, ip, tmax, tmin, rad, w_vl, w_dir, tdew
-            ).as_bytes())?;
+            let fields = row_fn(&tokens)?;
+            if fields.len() != EXPECTED_TOKENS {
+                return Err(Error::new(ErrorKind::InvalidData, format!(
+                    "expected {} fields per record, got {}", EXPECTED_TOKENS, fields.len())));
+            }
+            write_record(&mut dst_w, &fields)?;
         }
         line.clear();
     }
-    Ok(())
+    dst_w.flush()
+}
+
+/// Parses token `indx` of a daily record, naming the column on failure.
+fn parse_field<V: FromStr>(tokens: &[&str], indx: usize) -> Result<V> {
+    tokens[indx].parse().map_err(|_| Error::new(ErrorKind::InvalidData, format!(
+        "could not parse {} value {}", FIELD_NAMES[indx], tokens[indx])))
+}
+
+/// Maps an error from the CLIGEN routines to a Python exception: invalid
+/// arguments and records that do not fit the format raise `ValueError`,
+/// anything else `OSError`.
+fn io_to_py_err(e: Error) -> PyErr {
+    match e.kind() {
+        ErrorKind::InvalidInput | ErrorKind::InvalidData =>
+            pyo3::exceptions::PyValueError::new_err(format!("{}", e)),
+        _ => pyo3::exceptions::PyOSError::new_err(format!("{}", e)),
+    }
 }
 
 
@@ -81,10 +175,19 @@ pub fn rust_cli_revision(src_fn: &str, dst_fn: &str,
 ///       list of hill monthly tmax values
 ///   hill_tmins: list of floats
 ///       list of hill monthly tmin values
+///   mode: str
+///       "relative" (default) scales precip by hill/ws and shifts temperatures
+///       by hill - ws; "absolute" ignores the ws values and applies the hill
+///       values directly as precip factors and temperature offsets
+///   precision: int
+///       decimal places written for prcp, tmax, and tmin (default 1)
+/// 
+/// raises:
+///   ValueError if any revised field no longer fits its fixed-width column
 /// 
 /// returns:
 ///  None
-#[pyfunction]
+#[pyfunction(mode = "\"relative\"", precision = "1")]
 fn cli_revision(
     src_fn: &str, 
     dst_fn: &str, 
@@ -93,11 +196,16 @@ fn cli_revision(
     ws_tmins: Vec<f64>, 
     hill_ppts: Vec<f64>, 
     hill_tmaxs: Vec<f64>, 
-    hill_tmins: Vec<f64>
+    hill_tmins: Vec<f64>,
+    mode: &str,
+    precision: usize
 ) -> PyResult<()> {
     println!("{}", src_fn);
     println!("{}", dst_fn);
 
+    let mode = RevisionMode::from_str(mode).map_err(|_| pyo3::exceptions::PyValueError::new_err(
+        format!("Unknown mode '{}', expected \"relative\" or \"absolute\"", mode)))?;
+
     // Convert Vec<f64> to [f64; 12]
     let convert_array = |v: Vec<f64>| -> PyResult<[f64; 12]> {
         if v.len() == 12 {
@@ -120,17 +228,83 @@ fn cli_revision(
         convert_array(ws_tmins)?, 
         convert_array(hill_ppts)?, 
         convert_array(hill_tmaxs)?, 
-        convert_array(hill_tmins)?
-    ).map_err(|e| pyo3::exceptions::PyOSError::new_err(format!("{}", e)))?;
+        convert_array(hill_tmins)?,
+        mode,
+        precision
+    ).map_err(io_to_py_err)?;
 
     Ok(())
 }
 
+/// Decimal places used when writing a value returned by a `cli_transform` callback.
+const FIELD_PRECISIONS: [usize; EXPECTED_TOKENS] = [0, 0, 0, 1, 2, 2, 2, 1, 1, 0, 1, 0, 1];
+
+/// applies an arbitrary python function to every daily record of a climate file
+/// 
+/// inputs:
+///   src_fn: str
+///       path to climate file to transform
+///   dst_fn: str
+///       path to output climate file
+///   row_fn: callable
+///       called with a list of the 13 record values as floats
+///       (da, mo, year, prcp, dur, tp, ip, tmax, tmin, rad, w-vl, w-dir, tdew)
+///       and returning 13 values. values that are unchanged are written exactly
+///       as read, changed values are rounded to the column's usual precision
+/// 
+/// raises:
+///   ValueError if row_fn returns the wrong number of values or a value that
+///   does not fit its fixed-width column. exceptions raised by row_fn propagate
+/// 
+/// returns:
+///  None
+#[pyfunction]
+fn cli_transform(py: Python, src_fn: &str, dst_fn: &str, row_fn: PyObject) -> PyResult<()> {
+    // the callback runs with the GIL held; the first python exception it raises
+    // is kept here and returned once the transform has stopped
+    let mut callback_err: Option<PyErr> = None;
+
+    let result = transform_cli(src_fn, dst_fn, |tokens| {
+        let values: Vec<f64> = (0..EXPECTED_TOKENS)
+            .map(|i| parse_field(tokens, i))
+            .collect::<Result<_>>()?;
+
+        let revised: Vec<f64> = match row_fn.call1(py, (values.clone(),)).and_then(|r| r.extract(py)) {
+            Ok(revised) => revised,
+            Err(e) => {
+                callback_err = Some(e);
+                return Err(Error::new(ErrorKind::Other, "row_fn raised an exception"));
+            }
+        };
+        if revised.len() != EXPECTED_TOKENS {
+            return Err(Error::new(ErrorKind::InvalidData, format!(
+                "row_fn returned {} values, expected {}", revised.len(), EXPECTED_TOKENS)));
+        }
+
+        Ok(revised.iter().zip(values.iter()).enumerate()
+            .map(|(i, (&new, &old))| {
+                if new == old {
+                    tokens[i].to_string()
+                } else {
+                    format!("{:.*}", FIELD_PRECISIONS[i], new)
+                }
+            })
+            .collect())
+    });
+
+    match (result, callback_err) {
+        (_, Some(e)) => Err(e),
+        (Err(e), None) => Err(io_to_py_err(e)),
+        (Ok(()), None) => Ok(()),
+    }
+}
+
 /// A PyO3 module
 /// This module is a container for the Python-callable functions we define
 #[pymodule]
 fn cli_revision_rust(_py: Python, m: &PyModule) -> PyResult<()> {
     m.add_function(wrap_pyfunction!(cli_revision, m)?)?;
+    m.add_function(wrap_pyfunction!(cli_transform, m)?)?;
     Ok(())
 }
 
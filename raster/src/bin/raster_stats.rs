@@ -0,0 +1,139 @@
+//! Per-key raster statistics from the command line.
+//!
+//! Runs the same zonal statistics as the `raster_characteristics_rust` Python
+//! module and prints one row per key, e.g.
+//!
+//! ```text
+//! raster_stats --key SUBWTA.ARC --parameter landuse.tif --stat mode --ignore-channels
+//! ```
+
+use std::collections::HashSet;
+use std::fmt::Display;
+use std::process;
+
+use clap::{ArgEnum, Parser};
+
+use raster::error::RasterError;
+use raster::raster::Raster;
+use raster::zonal;
+
+
+#[derive(Debug, Clone, Copy, ArgEnum)]
+enum Stat {
+    Mode,
+    Median,
+}
+
+#[derive(Debug, Clone, Copy, ArgEnum)]
+enum Format {
+    Csv,
+    Json,
+}
+
+/// Print per-key statistics of a parameter raster
+#[derive(Parser, Debug)]
+#[clap(name = "raster_stats")]
+struct Args {
+    /// Key raster, e.g. a TOPAZ SUBWTA map
+    #[clap(long)]
+    key: String,
+
+    /// Parameter raster summarized per key
+    #[clap(long)]
+    parameter: String,
+
+    /// Statistic computed per key
+    #[clap(long, arg_enum, default_value = "mode")]
+    stat: Stat,
+
+    /// Skip keys that end in 4 (channels)
+    #[clap(long)]
+    ignore_channels: bool,
+
+    /// Skip key 0 (the TOPAZ background)
+    #[clap(long)]
+    exclude_zero: bool,
+
+    /// Band of the parameter raster to read
+    #[clap(long, default_value = "1")]
+    band: isize,
+
+    /// Output format
+    #[clap(long, arg_enum, default_value = "csv")]
+    format: Format,
+}
+
+/// Prints the stats as CSV or as a JSON object keyed by key. JSON has no NaN
+/// or infinity, so non-finite values are written as `null`.
+fn print_stats<V: Display + Copy + Into<f64>>(stats: Vec<(i32, V)>, format: Format) {
+    match format {
+        Format::Csv => {
+            println!("key,value");
+            for (key, value) in stats {
+                println!("{},{}", key, value);
+            }
+        },
+        Format::Json => {
+            let entries: Vec<String> = stats.iter()
+                .map(|&(key, value)| if value.into().is_finite() {
+                    format!("\"{}\": {}", key, value)
+                } else {
+                    format!("\"{}\": null", key)
+                })
+                .collect();
+            println!("{{{}}}", entries.join(", "));
+        },
+    }
+}
+
+fn run(args: &Args) -> Result<(), String> {
+    let read_err = |path: &str, e: RasterError| format!("Failed to read raster {}: {}", path, e);
+
+    let key_map: Raster<i32> = Raster::read(&args.key).map_err(|e| read_err(&args.key, e))?;
+    let mut ignore_keys: HashSet<i32> = HashSet::new();
+    if args.exclude_zero {
+        ignore_keys.insert(zonal::BACKGROUND_KEY);
+    }
+    let channel_modulus = if args.ignore_channels { Some(zonal::CHANNEL_MODULUS) } else { None };
+
+    match args.stat {
+        Stat::Mode => {
+            let parameter_map: Raster<i32> = Raster::read_band(&args.parameter, args.band)
+                .map_err(|e| read_err(&args.parameter, e))?;
+            check_dimensions(args, &key_map, &parameter_map)?;
+
+            let mut stats: Vec<(i32, i32)> = zonal::mode_by_key(
+                &key_map, &parameter_map, None, channel_modulus, &ignore_keys).into_iter().collect();
+            stats.sort_by_key(|&(key, _)| key);
+            print_stats(stats, args.format);
+        },
+        Stat::Median => {
+            let parameter_map: Raster<f64> = Raster::read_band(&args.parameter, args.band)
+                .map_err(|e| read_err(&args.parameter, e))?;
+            check_dimensions(args, &key_map, &parameter_map)?;
+
+            let mut stats: Vec<(i32, f64)> = zonal::median_by_key(
+                &key_map, &parameter_map, None, channel_modulus, &ignore_keys).into_iter().collect();
+            stats.sort_by_key(|&(key, _)| key);
+            print_stats(stats, args.format);
+        },
+    }
+    Ok(())
+}
+
+fn check_dimensions<V>(args: &Args, key_map: &Raster<i32>, parameter_map: &Raster<V>) -> Result<(), String> {
+    if key_map.width != parameter_map.width || key_map.height != parameter_map.height {
+        return Err(format!(
+            "Raster dimensions differ: {} is {} x {} but {} is {} x {}",
+            args.key, key_map.width, key_map.height, args.parameter, parameter_map.width, parameter_map.height));
+    }
+    Ok(())
+}
+
+fn main() {
+    let args = Args::parse();
+    if let Err(msg) = run(&args) {
+        eprintln!("{}", msg);
+        process::exit(1);
+    }
+}
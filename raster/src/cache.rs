@@ -0,0 +1,136 @@
+//! Opt-in, process-wide LRU cache for rasters read from disk.
+//!
+//! Entries are keyed by path, band, and cell type and remember the file's
+//! modification time; a cached raster is discarded as soon as the file on
+//! disk has been modified. The cache starts disabled (capacity 0).
+
+use std::any::{Any, TypeId};
+use std::sync::Mutex;
+use std::time::SystemTime;
+
+
+struct CacheEntry {
+    path: String,
+    band_indx: isize,
+    type_id: TypeId,
+    modified: SystemTime,
+    raster: Box<dyn Any + Send>,
+}
+
+struct RasterCache {
+    capacity: usize,
+    // least recently used first
+    entries: Vec<CacheEntry>,
+}
+
+static RASTER_CACHE: Mutex<RasterCache> = Mutex::new(RasterCache::new(0));
+
+fn modified_time(path: &str) -> Option<SystemTime> {
+    std::fs::metadata(path).and_then(|m| m.modified()).ok()
+}
+
+impl RasterCache {
+    const fn new(capacity: usize) -> RasterCache {
+        RasterCache { capacity, entries: Vec::new() }
+    }
+
+    fn set_capacity(&mut self, capacity: usize) {
+        self.capacity = capacity;
+        let excess = self.entries.len().saturating_sub(capacity);
+        self.entries.drain(..excess);
+    }
+
+    fn get<R: Any + Clone>(&mut self, path: &str, band_indx: isize) -> Option<R> {
+        let type_id = TypeId::of::<R>();
+        let position = self.entries.iter()
+            .position(|e| e.path == path && e.band_indx == band_indx && e.type_id == type_id)?;
+
+        if modified_time(path) != Some(self.entries[position].modified) {
+            self.entries.remove(position);
+            return None;
+        }
+
+        let entry = self.entries.remove(position);
+        let value = entry.raster.downcast_ref::<R>().cloned();
+        self.entries.push(entry);
+        value
+    }
+
+    fn insert<R: Any + Send>(&mut self, path: &str, band_indx: isize, raster: R) {
+        if self.capacity == 0 {
+            return;
+        }
+        let modified = match modified_time(path) {
+            Some(modified) => modified,
+            None => return,
+        };
+
+        let type_id = TypeId::of::<R>();
+        self.entries.retain(|e| !(e.path == path && e.band_indx == band_indx && e.type_id == type_id));
+        if self.entries.len() >= self.capacity {
+            self.entries.remove(0);
+        }
+        self.entries.push(CacheEntry {
+            path: path.to_string(),
+            band_indx,
+            type_id,
+            modified,
+            raster: Box::new(raster),
+        });
+    }
+}
+
+/// Sets the maximum number of cached rasters, evicting the least recently
+/// used entries if needed. A size of 0 disables caching.
+pub fn set_cache_size(capacity: usize) {
+    RASTER_CACHE.lock().unwrap().set_capacity(capacity);
+}
+
+/// Drops every cached raster. The cache size is left unchanged.
+pub fn clear_cache() {
+    RASTER_CACHE.lock().unwrap().entries.clear();
+}
+
+/// Returns `true` when the cache has a non-zero capacity.
+pub fn is_enabled() -> bool {
+    RASTER_CACHE.lock().unwrap().capacity > 0
+}
+
+/// Looks up a clone of the cached value for `path`/`band_indx`, invalidating
+/// the entry if the file has been modified since it was cached.
+pub(crate) fn get<R: Any + Clone>(path: &str, band_indx: isize) -> Option<R> {
+    RASTER_CACHE.lock().unwrap().get(path, band_indx)
+}
+
+/// Caches `raster` for `path`/`band_indx`. Does nothing when the cache is
+/// disabled or the file's modification time cannot be determined.
+pub(crate) fn insert<R: Any + Send>(path: &str, band_indx: isize, raster: R) {
+    RASTER_CACHE.lock().unwrap().insert(path, band_indx, raster);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_cache_roundtrip_and_eviction() {
+        let dir = std::env::temp_dir();
+        let a = dir.join("wepppyo3_cache_a.txt");
+        let b = dir.join("wepppyo3_cache_b.txt");
+        std::fs::write(&a, "a").unwrap();
+        std::fs::write(&b, "b").unwrap();
+        let (a, b) = (a.to_str().unwrap(), b.to_str().unwrap());
+
+        let mut cache = RasterCache::new(1);
+        cache.insert(a, 1, vec![1, 2, 3]);
+        assert_eq!(cache.get::<Vec<i32>>(a, 1), Some(vec![1, 2, 3]));
+        assert_eq!(cache.get::<Vec<f64>>(a, 1), None);
+
+        cache.insert(b, 1, vec![4]);
+        assert_eq!(cache.get::<Vec<i32>>(a, 1), None);
+        assert_eq!(cache.get::<Vec<i32>>(b, 1), Some(vec![4]));
+
+        cache.set_capacity(0);
+        assert_eq!(cache.get::<Vec<i32>>(b, 1), None);
+    }
+}
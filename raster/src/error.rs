@@ -0,0 +1,76 @@
+use std::error::Error;
+use std::fmt;
+use std::io;
+
+use gdal::errors::GdalError;
+use proj::{ProjCreateError, ProjError};
+
+
+/// The error type returned by the public `raster` API.
+#[derive(Debug)]
+pub enum RasterError {
+    /// A GDAL call failed (opening, reading, or writing a dataset).
+    Gdal(GdalError),
+    /// A PROJ transformer could not be created from the given CRS definitions.
+    ProjCreate(ProjCreateError),
+    /// A PROJ coordinate transformation failed.
+    Proj(ProjError),
+    /// Reading a file outside of GDAL failed.
+    Io(io::Error),
+    /// A value could not be parsed, or is not representable in the cell type.
+    Parse(String),
+    /// Rasters that must share a grid differ in size, cell size, origin, or projection.
+    Dimension(String),
+    /// An argument is unusable, e.g. an empty input list or a raster without a projection.
+    InvalidArgument(String),
+}
+
+impl fmt::Display for RasterError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            RasterError::Gdal(err) => write!(f, "GDAL error: {}", err),
+            RasterError::ProjCreate(err) => write!(f, "PROJ error: {}", err),
+            RasterError::Proj(err) => write!(f, "PROJ error: {}", err),
+            RasterError::Io(err) => write!(f, "I/O error: {}", err),
+            RasterError::Parse(msg) => write!(f, "parse error: {}", msg),
+            RasterError::Dimension(msg) => write!(f, "dimension mismatch: {}", msg),
+            RasterError::InvalidArgument(msg) => write!(f, "invalid argument: {}", msg),
+        }
+    }
+}
+
+impl Error for RasterError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            RasterError::Gdal(err) => Some(err),
+            RasterError::ProjCreate(err) => Some(err),
+            RasterError::Proj(err) => Some(err),
+            RasterError::Io(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<GdalError> for RasterError {
+    fn from(err: GdalError) -> RasterError {
+        RasterError::Gdal(err)
+    }
+}
+
+impl From<ProjCreateError> for RasterError {
+    fn from(err: ProjCreateError) -> RasterError {
+        RasterError::ProjCreate(err)
+    }
+}
+
+impl From<ProjError> for RasterError {
+    fn from(err: ProjError) -> RasterError {
+        RasterError::Proj(err)
+    }
+}
+
+impl From<io::Error> for RasterError {
+    fn from(err: io::Error) -> RasterError {
+        RasterError::Io(err)
+    }
+}
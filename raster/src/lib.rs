@@ -1 +1,5 @@
+pub mod cache;
+pub mod error;
 pub mod raster;
+pub mod source;
+pub mod zonal;
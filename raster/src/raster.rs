@@ -1,19 +1,27 @@
 use std::fmt;
-use std::error::Error;
-use std::collections::HashSet;
-
-use core::any::Any;
+use std::cell::RefCell;
+use std::collections::{BinaryHeap, HashMap, HashSet};
+use std::collections::hash_map::Entry;
+use std::cmp::Reverse;
 
 use gdal::raster::Buffer;
-use gdal::errors::GdalError;
 use gdal::raster::GdalType;
+use gdal::raster::RasterCreationOption;
 use gdal::spatial_ref::SpatialRef;
+use gdal::Metadata;
 
 use std::str::FromStr;
 
 
 use proj::Proj;
 
+use rayon::prelude::*;
+
+use crate::cache;
+use crate::error::RasterError;
+use crate::source::RasterSource;
+use crate::zonal::{is_channel, BACKGROUND_KEY};
+
 
 /// Computes the circular mean of a slice of angles in radians.
 ///
@@ -44,9 +52,38 @@ pub fn circmean(angles: &[f64]) -> f64 {
     sum_sin.atan2(sum_cos)
 }
 
-fn transform_coords(x: f64, y: f64, s_srs: &str, t_srs: &str) -> Result<(f64, f64), Box<dyn Error>> {
-    let transformer: Proj= Proj::new_known_crs(&s_srs, &t_srs, None)?;
-    Ok(transformer.convert((x, y))?)
+/// The eight compass classes used by `aspect_class`, clockwise from north.
+pub const ASPECT_CLASSES: [&str; 8] = ["N", "NE", "E", "SE", "S", "SW", "W", "NW"];
+
+/// Bins an aspect in degrees (clockwise from north) into one of the eight
+/// 45° compass classes of `ASPECT_CLASSES`, returning the class index.
+///
+/// Each class is centered on its direction, so `N` covers `[337.5, 360]`
+/// and `[0, 22.5)`. Values outside `[0, 360]` (e.g. `-1` for flat cells)
+/// and NaN have no class.
+#[allow(dead_code)]
+pub fn aspect_class(degrees: f64) -> Option<usize> {
+    if !(0.0..=360.0).contains(&degrees) {
+        return None;
+    }
+    Some((((degrees + 22.5) / 45.0).floor() as usize) % 8)
+}
+
+thread_local! {
+    // PROJ transformers keyed by (source, target) CRS definition; creating one is far
+    // more expensive than converting a point, and `Proj` cannot be shared across threads
+    static TRANSFORMERS: RefCell<HashMap<(String, String), Proj>> = RefCell::new(HashMap::new());
+}
+
+fn transform_coords(x: f64, y: f64, s_srs: &str, t_srs: &str) -> Result<(f64, f64), RasterError> {
+    TRANSFORMERS.with(|transformers| {
+        let mut transformers = transformers.borrow_mut();
+        let transformer: &Proj = match transformers.entry((s_srs.to_string(), t_srs.to_string())) {
+            Entry::Occupied(entry) => entry.into_mut(),
+            Entry::Vacant(entry) => entry.insert(Proj::new_known_crs(s_srs, t_srs, None)?),
+        };
+        Ok(transformer.convert((x, y))?)
+    })
 }
 
 #[derive(Debug, Clone, PartialEq)]
@@ -96,6 +133,17 @@ impl FromStr for MapType {
     }
 }
 
+impl MapType {
+    /// The no-data value implied by the map type when the file does not set one,
+    /// e.g. `0` for the TOPAZ background of `SUBWTA`.
+    pub fn default_no_data(&self) -> Option<f64> {
+        match self {
+            MapType::SUBWTA => Some(0.0),
+            _ => None,
+        }
+    }
+}
+
 
 #[derive(Debug)]
 pub struct Raster<T> {
@@ -115,6 +163,12 @@ pub struct Raster<T> {
 // impl new for Raster<T> without wgs_transform
 impl<T> Raster<T> {
 
+    /// Builds a raster, deriving `wgs_transform` from `proj4`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `data` does not hold `width * height` cells or `proj4` cannot be
+    /// transformed to WGS84; use `try_new` to handle those cases.
     #[allow(dead_code)]
     pub fn new(
         width: usize,
@@ -128,32 +182,34 @@ impl<T> Raster<T> {
         name: String,
         map_type: MapType,
     ) -> Raster<T> {
-        // check if proj4 is not None and build Proj transformer to wgs 84 epsg:4326
-        let wgs_transform = match &proj4 {  // Borrow here instead of moving
-            Some(proj_str) => {
-                // find easting and northing of bottom left corner using geo_transform
-                let ll_x: f64 = geo_transform[0];
-                let ll_y: f64 = geo_transform[3] + height as f64 * geo_transform[5];
-
-                // find easting and northing of top right corner using geo_transform
-                let ur_x: f64 = geo_transform[0] + width as f64 * geo_transform[1];
-                let ur_y: f64 = geo_transform[3];
-
-                // transform ll_x, ll_y, ur_x, ur_y to wgs 84 epsg:4326
-                let ll_wgs: (f64, f64) = transform_coords(ll_x, ll_y, &proj_str, "+proj=longlat +datum=WGS84 +no_defs").unwrap();
-                let ur_wgs: (f64, f64) = transform_coords(ur_x, ur_y, &proj_str, "+proj=longlat +datum=WGS84 +no_defs").unwrap();
-
-                // build wgs_transform to approximate wgs coords from px coords (x, y)
-                // (0, 0) is upper left corner
-                // lon = ll_wgs.0 + x * (ur_wgs.0 - ll_wgs.0) / width
-                // lat = ur_wgs.1 - y * (ur_wgs.1 - ll_wgs.1) / height
-                [ll_wgs.0, ur_wgs.1, (ur_wgs.0 - ll_wgs.0) / width as f64, (ur_wgs.1 - ll_wgs.1) / height as f64]
+        Self::try_new(width, height, cellsize, data, no_data, geo_transform, proj4, path, name, map_type)
+            .unwrap()
+    }
 
-            },
-            None => [0.0, 0.0, 0.0, 0.0],
-        };
+    /// Builds a raster like `new`, returning `RasterError::Dimension` when `data` does
+    /// not hold `width * height` cells and a `RasterError` when `proj4` cannot be
+    /// transformed to WGS84.
+    #[allow(dead_code)]
+    pub fn try_new(
+        width: usize,
+        height: usize,
+        cellsize: f64,
+        data: Vec<T>,
+        no_data: Option<T>,
+        geo_transform: [f64; 6],
+        proj4: Option<String>,
+        path: String,
+        name: String,
+        map_type: MapType,
+    ) -> Result<Raster<T>, RasterError> {
+        // catch a mismatch here rather than as an out-of-bounds index far from the cause
+        if data.len() != width * height {
+            return Err(RasterError::Dimension(format!(
+                "{}: {} cells given for a {} x {} raster", path, data.len(), width, height)));
+        }
+        let wgs_transform = compute_wgs_transform(width, height, &geo_transform, proj4.as_deref())?;
 
-        Raster {
+        Ok(Raster {
             width: width,
             height: height,
             cellsize: cellsize,
@@ -165,10 +221,204 @@ impl<T> Raster<T> {
             name: name,
             map_type: map_type,
             wgs_transform: wgs_transform,
+        })
+    }
+
+    /// Assigns a CRS to the raster, e.g. the known UTM zone of a projection-less
+    /// TOPAZ `.ARC`, and recomputes `wgs_transform` so the coordinate methods
+    /// (`px_to_lnglat`, `coordinates_of`, ...) become usable.
+    ///
+    /// # Errors
+    ///
+    /// Returns a `RasterError` when `proj4` cannot be transformed to WGS84; the
+    /// raster is left unchanged.
+    #[allow(dead_code)]
+    pub fn set_proj4(&mut self, proj4: &str) -> Result<(), RasterError> {
+        self.wgs_transform = compute_wgs_transform(self.width, self.height, &self.geo_transform, Some(proj4))?;
+        self.proj4 = Some(proj4.to_string());
+        Ok(())
+    }
+}
+
+/// Builds a `Raster` from named fields instead of the ten positional
+/// arguments of `Raster::new`, e.g. when synthesizing rasters in tests.
+///
+/// ```ignore
+/// let raster = RasterBuilder::new()
+///     .width(3)
+///     .height(2)
+///     .cellsize(30.0)
+///     .data(vec![1, 2, 3, 4, 5, 6])
+///     .nodata(0)
+///     .build()?;
+/// ```
+///
+/// Unset fields default to an empty grid at the origin with a cell size of
+/// 1, no no-data value or projection, empty path and name, and `MapType::OTHER`.
+/// Unless set, the geo_transform is north-up at the origin with the cell size.
+#[derive(Debug, Clone)]
+pub struct RasterBuilder<T> {
+    width: usize,
+    height: usize,
+    cellsize: f64,
+    data: Vec<T>,
+    no_data: Option<T>,
+    geo_transform: Option<[f64; 6]>,
+    proj4: Option<String>,
+    path: String,
+    name: String,
+    map_type: MapType,
+}
+
+impl<T> Default for RasterBuilder<T> {
+    fn default() -> Self {
+        RasterBuilder {
+            width: 0,
+            height: 0,
+            cellsize: 1.0,
+            data: Vec::new(),
+            no_data: None,
+            geo_transform: None,
+            proj4: None,
+            path: String::new(),
+            name: String::new(),
+            map_type: MapType::OTHER,
+        }
+    }
+}
+
+impl<T> RasterBuilder<T> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn width(mut self, width: usize) -> Self {
+        self.width = width;
+        self
+    }
+
+    pub fn height(mut self, height: usize) -> Self {
+        self.height = height;
+        self
+    }
+
+    pub fn cellsize(mut self, cellsize: f64) -> Self {
+        self.cellsize = cellsize;
+        self
+    }
+
+    /// Sets the cells in row-major order; there must be `width * height` of them.
+    pub fn data(mut self, data: Vec<T>) -> Self {
+        self.data = data;
+        self
+    }
+
+    pub fn nodata(mut self, no_data: T) -> Self {
+        self.no_data = Some(no_data);
+        self
+    }
+
+    pub fn geo_transform(mut self, geo_transform: [f64; 6]) -> Self {
+        self.geo_transform = Some(geo_transform);
+        self
+    }
+
+    pub fn proj4(mut self, proj4: &str) -> Self {
+        self.proj4 = Some(proj4.to_string());
+        self
+    }
+
+    pub fn path(mut self, path: &str) -> Self {
+        self.path = path.to_string();
+        self
+    }
+
+    pub fn name(mut self, name: &str) -> Self {
+        self.name = name.to_string();
+        self
+    }
+
+    pub fn map_type(mut self, map_type: MapType) -> Self {
+        self.map_type = map_type;
+        self
+    }
+
+    /// Builds the raster like `Raster::try_new`.
+    ///
+    /// # Errors
+    ///
+    /// Returns `RasterError::Dimension` when `data` does not hold `width * height`
+    /// cells, and a `RasterError` when `proj4` cannot be transformed to WGS84.
+    pub fn build(self) -> Result<Raster<T>, RasterError> {
+        let geo_transform = self.geo_transform
+            .unwrap_or([0.0, self.cellsize, 0.0, 0.0, 0.0, -self.cellsize]);
+        Raster::try_new(
+            self.width,
+            self.height,
+            self.cellsize,
+            self.data,
+            self.no_data,
+            geo_transform,
+            self.proj4,
+            self.path,
+            self.name,
+            self.map_type,
+        )
+    }
+}
+
+impl<T: Clone> Raster<T> {
+    /// Returns a builder preset with this raster's fields, e.g. to derive a
+    /// raster on the same grid with a few fields changed.
+    pub fn to_builder(&self) -> RasterBuilder<T> {
+        RasterBuilder {
+            width: self.width,
+            height: self.height,
+            cellsize: self.cellsize,
+            data: self.data.clone(),
+            no_data: self.no_data.clone(),
+            geo_transform: Some(self.geo_transform),
+            proj4: self.proj4.clone(),
+            path: self.path.clone(),
+            name: self.name.clone(),
+            map_type: self.map_type.clone(),
         }
     }
 }
 
+/// Approximates WGS84 coordinates from pixel coordinates for a raster in `proj4`,
+/// as `[lon0, lat0, dlon, dlat]`; all zeros when there is no projection.
+fn compute_wgs_transform(
+    width: usize,
+    height: usize,
+    geo_transform: &[f64; 6],
+    proj4: Option<&str>
+) -> Result<[f64; 4], RasterError> {
+    // check if proj4 is not None and build Proj transformer to wgs 84 epsg:4326
+    let proj_str = match proj4 {
+        Some(proj_str) => proj_str,
+        None => return Ok([0.0, 0.0, 0.0, 0.0]),
+    };
+
+    // find easting and northing of bottom left corner using geo_transform
+    let ll_x: f64 = geo_transform[0];
+    let ll_y: f64 = geo_transform[3] + height as f64 * geo_transform[5];
+
+    // find easting and northing of top right corner using geo_transform
+    let ur_x: f64 = geo_transform[0] + width as f64 * geo_transform[1];
+    let ur_y: f64 = geo_transform[3];
+
+    // transform ll_x, ll_y, ur_x, ur_y to wgs 84 epsg:4326
+    let ll_wgs: (f64, f64) = transform_coords(ll_x, ll_y, proj_str, "+proj=longlat +datum=WGS84 +no_defs")?;
+    let ur_wgs: (f64, f64) = transform_coords(ur_x, ur_y, proj_str, "+proj=longlat +datum=WGS84 +no_defs")?;
+
+    // build wgs_transform to approximate wgs coords from px coords (x, y)
+    // (0, 0) is upper left corner
+    // lon = ll_wgs.0 + x * (ur_wgs.0 - ll_wgs.0) / width
+    // lat = ur_wgs.1 - y * (ur_wgs.1 - ll_wgs.1) / height
+    Ok([ll_wgs.0, ur_wgs.1, (ur_wgs.0 - ll_wgs.0) / width as f64, (ur_wgs.1 - ll_wgs.1) / height as f64])
+}
+
 impl<T: Clone> Clone for Raster<T> {
     fn clone(&self) -> Self {
         Raster {
@@ -205,6 +455,68 @@ impl<T: Default + Clone> Raster<T> {
     }
 }
 
+impl<T: Default + Copy> Raster<T> {
+    /// Enlarges the raster by `margin` cells on every side.
+    ///
+    /// The border is filled with the no-data value (`T::default()` when the
+    /// raster has none) and the geo_transform origin is moved so the original
+    /// cells keep their map coordinates.
+    #[allow(dead_code)]
+    pub fn pad(&self, margin: usize) -> Raster<T> {
+        let fill = self.no_data.unwrap_or_default();
+        let width = self.width + 2 * margin;
+        let height = self.height + 2 * margin;
+
+        let mut data = vec![fill; width * height];
+        for y in 0..self.height {
+            let src = y * self.width;
+            let dst = (y + margin) * width + margin;
+            data[dst..dst + self.width].copy_from_slice(&self.data[src..src + self.width]);
+        }
+
+        let mut geo_transform = self.geo_transform;
+        geo_transform[0] -= margin as f64 * (self.geo_transform[1] + self.geo_transform[2]);
+        geo_transform[3] -= margin as f64 * (self.geo_transform[4] + self.geo_transform[5]);
+
+        Raster::new(
+            width,
+            height,
+            self.cellsize,
+            data,
+            self.no_data,
+            geo_transform,
+            self.proj4.clone(),
+            self.path.clone(),
+            self.name.clone(),
+            self.map_type.clone(),
+        )
+    }
+}
+
+impl<T> Raster<T> {
+    /// Builds a raster on the same grid as `self` carrying new cell values.
+    ///
+    /// The geo_transform, projection, and metadata are copied; only the data
+    /// and no-data value are replaced. `data` must hold `width * height` cells.
+    pub fn with_data<U>(&self, data: Vec<U>, no_data: Option<U>) -> Raster<U> {
+        debug_assert_eq!(data.len(), self.width * self.height,
+            "{}: with_data given {} cells for a {} x {} raster", self.path, data.len(), self.width, self.height);
+        Raster {
+            width: self.width,
+            height: self.height,
+            cellsize: self.cellsize,
+            data: data,
+            no_data: no_data,
+            geo_transform: self.geo_transform,
+            proj4: self.proj4.clone(),
+            path: self.path.clone(),
+            name: self.name.clone(),
+            map_type: self.map_type.clone(),
+            wgs_transform: self.wgs_transform.clone(),
+        }
+    }
+}
+
 pub fn px_to_wgs(wgs_transform: &[f64; 4], px: i32, py: i32) -> (f64, f64) {
     let lon: f64 = wgs_transform[0] + px as f64 * wgs_transform[2];
     let lat: f64 = wgs_transform[1] - py as f64 * wgs_transform[3];
@@ -221,6 +533,12 @@ impl FromF64 for i32 {
     }
 }
 
+impl FromF64 for u32 {
+    fn from_f64(value: f64) -> Self {
+        value as u32
+    }
+}
+
 impl FromF64 for f64 {
     fn from_f64(value: f64) -> Self {
         value
@@ -228,6 +546,41 @@ impl FromF64 for f64 {
 }
 
 
+/// Fallible counterpart of `FromF64`, used for values such as GDAL's f64
+/// no-data that may not be representable in the cell type.
+pub trait TryFromF64: Sized {
+    /// Returns `None` if `value` is NaN, out of range, or (for integer types)
+    /// not a whole number, rather than silently saturating or truncating.
+    fn try_from_f64(value: f64) -> Option<Self>;
+}
+
+impl TryFromF64 for i32 {
+    fn try_from_f64(value: f64) -> Option<Self> {
+        if value.fract() == 0.0 && value >= i32::MIN as f64 && value <= i32::MAX as f64 {
+            Some(value as i32)
+        } else {
+            None
+        }
+    }
+}
+
+impl TryFromF64 for u32 {
+    fn try_from_f64(value: f64) -> Option<Self> {
+        if value.fract() == 0.0 && value >= 0.0 && value <= u32::MAX as f64 {
+            Some(value as u32)
+        } else {
+            None
+        }
+    }
+}
+
+impl TryFromF64 for f64 {
+    fn try_from_f64(value: f64) -> Option<Self> {
+        Some(value)
+    }
+}
+
+
 pub trait ToF64 {
     fn to_f64(&self) -> f64;
 }
@@ -238,6 +591,12 @@ impl ToF64 for i32 {
     }
 }
 
+impl ToF64 for u32 {
+    fn to_f64(&self) -> f64 {
+        *self as f64
+    }
+}
+
 impl ToF64 for f64 {
     fn to_f64(&self) -> f64 {
         *self
@@ -245,97 +604,366 @@ impl ToF64 for f64 {
 }
 
 
-impl<T> Raster<T>
-where
-    T: ToF64, // Constraint for types that can be converted to f64
-{
+/// Computes the `pct` percentile (0 to 100) of an ascending-sorted slice.
+///
+/// Uses linear interpolation between the closest ranks, matching numpy's
+/// default ("linear") method. The slice must not be empty.
+pub fn percentile(sorted: &[f64], pct: f64) -> f64 {
+    let rank = pct / 100.0 * (sorted.len() - 1) as f64;
+    let lo = rank.floor() as usize;
+    let hi = rank.ceil() as usize;
+    sorted[lo] + (sorted[hi] - sorted[lo]) * (rank - lo as f64)
+}
+
+impl<T: ToF64> Raster<T> {
+    /// Contrast-stretches the raster by clamping values to the
+    /// `low_pct`..`high_pct` percentile range of the valid cells.
+    ///
+    /// No-data and NaN cells are preserved and do not count toward the
+    /// percentiles. A raster without valid cells is returned unchanged (as f64).
+    ///
+    /// # Errors
+    ///
+    /// Returns `RasterError::InvalidArgument` unless
+    /// `0 <= low_pct <= high_pct <= 100`.
+    #[allow(dead_code)]
+    pub fn percentile_clip(&self, low_pct: f64, high_pct: f64) -> Result<Raster<f64>, RasterError> {
+        if !(0.0 <= low_pct && low_pct <= high_pct && high_pct <= 100.0) {
+            return Err(RasterError::InvalidArgument(format!(
+                "percentiles {} and {} must satisfy 0 <= low_pct <= high_pct <= 100", low_pct, high_pct)));
+        }
+
+        let no_data: Option<f64> = self.no_data.as_ref().map(|v| v.to_f64());
+        let is_valid = |v: f64| !is_no_data_value(v, no_data);
+
+        let mut valid: Vec<f64> = self.data.iter()
+            .map(|v| v.to_f64())
+            .filter(|&v| is_valid(v) && !v.is_nan())
+            .collect();
+        valid.sort_by(|a, b| a.total_cmp(b));
+
+        if valid.is_empty() {
+            return Ok(self.clamp(None, None));
+        }
+        Ok(self.clamp(Some(percentile(&valid, low_pct)), Some(percentile(&valid, high_pct))))
+    }
+
+    /// Clamps the valid cells to `min`..`max`; either bound may be omitted.
+    ///
+    /// No-data cells are preserved and NaN cells are left as NaN.
+    ///
+    /// # Panics
+    ///
+    /// Panics if both bounds are given and `min > max`.
+    #[allow(dead_code)]
+    pub fn clamp(&self, min: Option<f64>, max: Option<f64>) -> Raster<f64> {
+        if let (Some(lo), Some(hi)) = (min, max) {
+            assert!(lo <= hi, "clamp requires min <= max");
+        }
+
+        let no_data: Option<f64> = self.no_data.as_ref().map(|v| v.to_f64());
+        let lo = min.unwrap_or(f64::NEG_INFINITY);
+        let hi = max.unwrap_or(f64::INFINITY);
+
+        let data: Vec<f64> = self.data.iter()
+            .map(|v| v.to_f64())
+            .map(|v| if Some(v) == no_data || v.is_nan() { v } else { v.max(lo).min(hi) })
+            .collect();
+
+        self.with_data(data, no_data)
+    }
+
+    /// Replaces NaN and infinite cells with the no-data value (-9999 when the
+    /// raster has none), e.g. to clean up stray values from upstream tools
+    /// before computing statistics. See `sanitize_with`.
+    #[allow(dead_code)]
+    pub fn sanitize(&self) -> Raster<f64> {
+        let sentinel = self.no_data.as_ref().map(|v| v.to_f64()).unwrap_or(-9999.0);
+        self.sanitize_with(sentinel)
+    }
+
+    /// Replaces NaN, infinite, and no-data cells with `sentinel`, which becomes
+    /// the no-data value of the result.
+    #[allow(dead_code)]
+    pub fn sanitize_with(&self, sentinel: f64) -> Raster<f64> {
+        let no_data: Option<f64> = self.no_data.as_ref().map(|v| v.to_f64());
+        let data: Vec<f64> = self.data.iter()
+            .map(|v| v.to_f64())
+            .map(|v| if !v.is_finite() || is_no_data_value(v, no_data) { sentinel } else { v })
+            .collect();
+
+        self.with_data(data, Some(sentinel))
+    }
+}
+
+
+impl<T: TryFromF64> Raster<T> {
+    /// Sets `no_data` to the map type's default (see `MapType::default_no_data`)
+    /// when none is set. An explicit no-data value is never replaced.
     #[allow(dead_code)]
-    fn convert_data_to_f64(&self) -> Vec<f64> {
-        self.data.iter().map(|value| value.to_f64()).collect()
+    pub fn with_inferred_no_data(mut self) -> Raster<T> {
+        if self.no_data.is_none() {
+            self.no_data = self.map_type.default_no_data().and_then(T::try_from_f64);
+        }
+        self
+    }
+}
+
+
+/// Returns the no-data value GDAL reports for band `band_indx` of the raster
+/// at `path`, or `None` when the band has no no-data value set. Only the
+/// dataset header is read.
+#[allow(dead_code)]
+pub fn band_no_data(path: &str, band_indx: isize) -> Result<Option<f64>, RasterError> {
+    let dataset = gdal::Dataset::open(path)?;
+    let band = dataset.rasterband(band_indx)?;
+    Ok(band.no_data_value())
+}
+
+/// Bytes per cell of a GDAL data type code, from `GDT_Byte` (1) to
+/// `GDT_CFloat64` (11). Unknown codes are counted as 8 bytes.
+fn gdal_type_size(band_type: u32) -> u64 {
+    match band_type {
+        1 => 1,              // Byte
+        2 | 3 => 2,          // UInt16, Int16
+        4 | 5 | 6 | 8 => 4,  // UInt32, Int32, Float32, CInt16
+        7 | 9 | 10 => 8,     // Float64, CInt32, CFloat32
+        11 => 16,            // CFloat64
+        _ => 8,
     }
 }
 
+/// Estimates the bytes needed to hold band `band_indx` of the raster at
+/// `path` in its native data type, or all of its bands when `None`. Only the
+/// dataset header is read.
+#[allow(dead_code)]
+pub fn band_footprint(path: &str, band_indx: Option<isize>) -> Result<u64, RasterError> {
+    let dataset = gdal::Dataset::open(path)?;
+    let (width, height) = dataset.raster_size();
+    let cells = width as u64 * height as u64;
+
+    let bands: Vec<isize> = match band_indx {
+        Some(band_indx) => vec![band_indx],
+        None => (1..=dataset.raster_count()).collect(),
+    };
+    let mut bytes: u64 = 0;
+    for band_indx in bands {
+        bytes += cells * gdal_type_size(dataset.rasterband(band_indx)?.band_type());
+    }
+    Ok(bytes)
+}
 
-impl<T: GdalType + Default + Copy + FromF64> Raster<T> {
+impl<T: GdalType + Default + Copy + TryFromF64 + Send + 'static> Raster<T> {
 
     #[allow(dead_code)]
-    pub fn read(path: &str) -> Result<Raster<T>, GdalError> {
-        let dataset = gdal::Dataset::open(path)?;
-        let (width, height) = dataset.raster_size();
-        let geo_transform = dataset.geo_transform()?;
-        let cellsize = geo_transform[1];
+    pub fn read(path: &str) -> Result<Raster<T>, RasterError> {
+        Self::read_band(path, 1)
+    }
 
-        let wkt = dataset.projection();
-        let spatial_ref = SpatialRef::from_wkt(&wkt).unwrap();
-        let proj4 = spatial_ref.to_proj4().ok();
+    /// Reads band `band_indx` of the raster at `path`.
+    ///
+    /// When the raster cache is enabled (see `cache::set_cache_size`) a clone
+    /// of a previously read, unmodified raster is returned without touching GDAL.
+    /// Fails if the band's no-data value is not representable in `T` (see `TryFromF64`).
+    #[allow(dead_code)]
+    pub fn read_band(path: &str, band_indx: isize) -> Result<Raster<T>, RasterError> {
+        // check the cache before opening the file
+        if let Some(raster) = cache::get::<Raster<T>>(path, band_indx) {
+            return Ok(raster);
+        }
+        RasterSource::open(path)?.read_band(band_indx)
+    }
 
-        //let spatial_ref_result = dataset.spatial_ref();
-        //let proj4 = match spatial_ref_result {
-        //    Ok(sr) => sr.to_proj4().ok(),
-        //    Err(_) => None,
-        //};
+    /// Reads the bands whose GDAL descriptions (e.g. `ppt_01`) match `names`,
+    /// returned in the order of `names`.
+    ///
+    /// Fails with `RasterError::InvalidArgument` naming the first name that no
+    /// band of the raster carries. The file is opened once (see `RasterSource`).
+    #[allow(dead_code)]
+    pub fn read_bands_by_name(path: &str, names: &[String]) -> Result<Vec<Raster<T>>, RasterError> {
+        let source = RasterSource::open(path)?;
+        let mut band_indices: HashMap<String, isize> = HashMap::new();
+        for band_indx in 1..=source.band_count() {
+            // keep the first band when descriptions repeat
+            band_indices.entry(source.band_description(band_indx)?).or_insert(band_indx);
+        }
 
-        let band = dataset.rasterband(1)?;
-        let buffer = band.read_as::<T>((0, 0), (width, height), (width, height), None)?;
-        let data = buffer.data;
+        names.iter()
+            .map(|name| {
+                let band_indx = *band_indices.get(name).ok_or_else(|| RasterError::InvalidArgument(
+                    format!("{}: no band is named '{}'", path, name)))?;
+                source.read_band(band_indx)
+            })
+            .collect()
+    }
 
-        let no_data_value: Option<f64> = band.no_data_value();
-        let no_data = no_data_value.map(|v| T::from_f64(v));
+    /// Reads every band of the raster, in band order.
+    ///
+    /// Each band is its own `Raster` carrying that band's no-data value, so a
+    /// stack whose bands use different sentinels is never read with one band's
+    /// no-data applied to another. The file is opened once (see `RasterSource`).
+    #[allow(dead_code)]
+    pub fn read_all_bands(path: &str) -> Result<Vec<Raster<T>>, RasterError> {
+        let source = RasterSource::open(path)?;
+        (1..=source.band_count())
+            .map(|band_indx| source.read_band(band_indx))
+            .collect()
+    }
 
-        // find the name by spliting the path and removing the extension from the filename of the file
-        let name = path.split("/").last().unwrap().split(".").next().unwrap().to_string();
+    /// Reads band `band_indx` like `read_band`. When `infer_nodata_by_map_type` is set
+    /// and the band has no no-data value, the map type's default is applied (see
+    /// `MapType::default_no_data` and `with_inferred_no_data`).
+    #[allow(dead_code)]
+    pub fn read_band_with_options(path: &str, band_indx: isize, infer_nodata_by_map_type: bool) -> Result<Raster<T>, RasterError> {
+        let raster = Self::read_band(path, band_indx)?;
+        if infer_nodata_by_map_type {
+            Ok(raster.with_inferred_no_data())
+        } else {
+            Ok(raster)
+        }
+    }
 
-        // find the map type from the name using from_str
-        let map_type = MapType::from_str(&name).unwrap();
+    /// Reads every band of the raster into one flat, band-major buffer (see
+    /// `RasterCube`), opening the file once like `read_all_bands`.
+    #[allow(dead_code)]
+    pub fn read_cube(path: &str) -> Result<RasterCube<T>, RasterError> {
+        RasterCube::from_bands(Self::read_all_bands(path)?)
+    }
+}
 
-        // refactor to use Raster::new
+/// The bands of a multi-band raster (e.g. a monthly climate stack) in one flat
+/// buffer: band `b` (0-based) occupies `data[b * width * height..(b + 1) * width * height]`
+/// in row-major order. `no_data` holds each band's own no-data value, since GDAL
+/// allows them to differ.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RasterCube<T> {
+    pub width: usize,
+    pub height: usize,
+    pub n_bands: usize,
+    pub data: Vec<T>,
+    pub no_data: Vec<Option<T>>,
+    pub geo_transform: [f64; 6],
+    pub proj4: Option<String>,
+}
 
-        Ok(Raster::new(
-            width,
-            height,
-            cellsize,
-            data,
-            no_data,
-            geo_transform,
-            proj4,
-            path.to_string(),
-            name,
-            map_type,
-        ))
+impl<T: Copy> RasterCube<T> {
+    /// Stacks `bands`, which must share a width and height, in order.
+    ///
+    /// Fails with `InvalidArgument` for no bands and `Dimension` when a band
+    /// differs in size from the first.
+    pub fn from_bands(bands: Vec<Raster<T>>) -> Result<RasterCube<T>, RasterError> {
+        let first = bands.first().ok_or_else(|| RasterError::InvalidArgument(
+            "a raster cube needs at least one band".to_string()))?;
+        let (width, height) = (first.width, first.height);
+        let (geo_transform, proj4) = (first.geo_transform, first.proj4.clone());
+
+        let mut data: Vec<T> = Vec::with_capacity(width * height * bands.len());
+        let mut no_data: Vec<Option<T>> = Vec::with_capacity(bands.len());
+        for (indx, band) in bands.iter().enumerate() {
+            if band.width != width || band.height != height {
+                return Err(RasterError::Dimension(format!(
+                    "{}: band {} is {} x {} but band 1 is {} x {}",
+                    band.path, indx + 1, band.width, band.height, width, height)));
+            }
+            data.extend_from_slice(&band.data);
+            no_data.push(band.no_data);
+        }
+
+        Ok(RasterCube { width, height, n_bands: bands.len(), data, no_data, geo_transform, proj4 })
+    }
+
+    /// The cells of band `band` (0-based), row-major.
+    pub fn band(&self, band: usize) -> &[T] {
+        let size = self.width * self.height;
+        &self.data[band * size..(band + 1) * size]
     }
+}
+
+/// Parses a single ESRI ASCII grid token with `FromStr`.
+///
+/// `FromStr` is locale-independent: it only accepts `.` as the decimal
+/// separator (with optional scientific notation such as `1.0e3`), so a
+/// comma-decimal grid is rejected rather than silently misread.
+fn parse_ascii_grid_token<V: FromStr>(path: &str, what: &str, token: &str) -> Result<V, RasterError> {
+    token.parse::<V>().map_err(|_| {
+        let hint = if token.contains(',') {
+            " (only '.' is accepted as the decimal separator)"
+        } else {
+            ""
+        };
+        RasterError::Parse(format!("{}: invalid {} value '{}'{}", path, what, token, hint))
+    })
+}
+
+impl<T: FromStr + Copy> Raster<T> {
 
+    /// Reads an ESRI ASCII grid (e.g. a TOPAZ `.ARC` file) without GDAL.
+    ///
+    /// Header keys are matched case-insensitively and both the `xllcorner`
+    /// and `xllcenter` conventions are supported. All numbers are parsed with
+    /// `FromStr` (see `parse_ascii_grid_token`), so the result does not depend
+    /// on the process locale. The grid has no projection.
     #[allow(dead_code)]
-    pub fn read_band(path: &str, band_indx: isize) -> Result<Raster<T>, GdalError> {
-        let dataset = gdal::Dataset::open(path)?;
-        let (width, height) = dataset.raster_size();
-        let geo_transform = dataset.geo_transform()?;
-        let cellsize = geo_transform[1];
+    pub fn from_ascii_grid(path: &str) -> Result<Raster<T>, RasterError> {
+        let contents = std::fs::read_to_string(path)?;
+        let mut tokens = contents.split_whitespace().peekable();
+
+        let mut ncols: Option<usize> = None;
+        let mut nrows: Option<usize> = None;
+        let mut xll: f64 = 0.0;
+        let mut yll: f64 = 0.0;
+        let mut is_center = false;
+        let mut cellsize: Option<f64> = None;
+        let mut no_data: Option<T> = None;
+
+        while let Some(key) = tokens.peek().map(|k| k.to_lowercase()) {
+            let known = matches!(key.as_str(),
+                "ncols" | "nrows" | "xllcorner" | "yllcorner" | "xllcenter" | "yllcenter" | "cellsize" | "nodata_value");
+            if !known {
+                break;
+            }
+            tokens.next();
+
+            let value = tokens.next().ok_or_else(|| RasterError::Parse(
+                format!("{}: missing value for header key '{}'", path, key)))?;
+
+            match key.as_str() {
+                "ncols" => ncols = Some(parse_ascii_grid_token(path, &key, value)?),
+                "nrows" => nrows = Some(parse_ascii_grid_token(path, &key, value)?),
+                "xllcorner" => xll = parse_ascii_grid_token(path, &key, value)?,
+                "yllcorner" => yll = parse_ascii_grid_token(path, &key, value)?,
+                "xllcenter" => { xll = parse_ascii_grid_token(path, &key, value)?; is_center = true; },
+                "yllcenter" => { yll = parse_ascii_grid_token(path, &key, value)?; is_center = true; },
+                "cellsize" => cellsize = Some(parse_ascii_grid_token(path, &key, value)?),
+                _ => no_data = Some(parse_ascii_grid_token(path, &key, value)?),
+            }
+        }
 
-        let wkt = dataset.projection();
-        let spatial_ref = SpatialRef::from_wkt(&wkt).unwrap();
-        let proj4 = spatial_ref.to_proj4().ok();
+        let missing = |key: &str| RasterError::Parse(format!("{}: missing header key '{}'", path, key));
+        let width = ncols.ok_or_else(|| missing("ncols"))?;
+        let height = nrows.ok_or_else(|| missing("nrows"))?;
+        let cellsize = cellsize.ok_or_else(|| missing("cellsize"))?;
 
-        //let spatial_ref_result = dataset.spatial_ref();
-        //let proj4 = match spatial_ref_result {
-        //    Ok(sr) => sr.to_proj4().ok(),
-        //    Err(_) => None,
-        //};
+        let mut data: Vec<T> = Vec::with_capacity(width * height);
+        for token in tokens {
+            data.push(parse_ascii_grid_token(path, "cell", token)?);
+        }
 
-        let band = dataset.rasterband(band_indx)?;
-        let buffer = band.read_as::<T>((0, 0), (width, height), (width, height), None)?;
-        let data = buffer.data;
+        if data.len() != width * height {
+            return Err(RasterError::Dimension(format!(
+                "{}: expected {} x {} = {} cells but found {}", path, width, height, width * height, data.len())));
+        }
 
-        let no_data_value: Option<f64> = band.no_data_value();
-        let no_data = no_data_value.map(|v| T::from_f64(v));
+        // the header locates the lower left corner; GDAL transforms are anchored upper left
+        if is_center {
+            xll -= cellsize / 2.0;
+            yll -= cellsize / 2.0;
+        }
+        let geo_transform = [xll, cellsize, 0.0, yll + height as f64 * cellsize, 0.0, -cellsize];
 
-        // find the name by spliting the path and removing the extension from the filename of the file
         let name = path.split("/").last().unwrap().split(".").next().unwrap().to_string();
-
-        // find the map type from the name using from_str
         let map_type = MapType::from_str(&name).unwrap();
 
-        // refactor to use Raster::new
-
         Ok(Raster::new(
             width,
             height,
@@ -343,35 +971,160 @@ impl<T: GdalType + Default + Copy + FromF64> Raster<T> {
             data,
             no_data,
             geo_transform,
-            proj4,
+            None,
             path.to_string(),
             name,
             map_type,
         ))
     }
+}
 
+/// Picks the GDAL driver for an output path from its extension: `.asc` is
+/// AAIGrid, `.img` is HFA, and anything else (including `.tif`) is GTiff.
+pub fn driver_for_path(path: &str) -> &'static str {
+    let extension = std::path::Path::new(path).extension()
+        .and_then(|e| e.to_str())
+        .map(|e| e.to_ascii_lowercase());
+    match extension.as_deref() {
+        Some("asc") => "AAIGrid",
+        Some("img") => "HFA",
+        _ => "GTiff",
+    }
 }
 
-impl<T: GdalType + Default + Copy  + ToF64> Raster<T> {
-    pub fn write(&self, path: &str) -> Result<(), GdalError> {
-        // Create a new GDAL dataset
-        let driver = gdal::Driver::get("GTiff")?;
-        let mut dataset = driver.create_with_band_type::<T, &str>(path, self.width as isize, self.height as isize, 1)?;
+/// GeoTIFF compression codec for `Raster::write_compressed`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Compression {
+    None,
+    Lzw,
+    Deflate,
+    Zstd,
+}
 
-        // Set the geotransform and projection
-        dataset.set_geo_transform(&self.geo_transform)?;
-        if let Some(proj) = &self.proj4 {
-            let spatial_ref = SpatialRef::from_proj4(&proj)?;
-            let wkt = spatial_ref.to_wkt()?;
-            dataset.set_projection(&wkt)?;
-        }
+impl FromStr for Compression {
+    type Err = ();
 
-        // Write the raster data
-        let mut band = dataset.rasterband(1)?;
-        let buffer = Buffer::new((self.width, self.height), self.data.clone());
-        band.write((0, 0), (self.width, self.height), &buffer)?;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "none" => Ok(Compression::None),
+            "lzw" => Ok(Compression::Lzw),
+            "deflate" => Ok(Compression::Deflate),
+            "zstd" => Ok(Compression::Zstd),
+            _ => Err(()),
+        }
+    }
+}
 
-        // Set the NoData value if it exists
+/// GeoTIFF creation options for `compression` and, when `tiled`, 256 x 256
+/// tiles. Compressed floats use the floating point predictor (3) and other
+/// types horizontal differencing (2).
+fn geotiff_creation_options(compression: Compression, tiled: bool, is_float: bool) -> Vec<(&'static str, &'static str)> {
+    let mut options = Vec::new();
+    let codec = match compression {
+        Compression::None => None,
+        Compression::Lzw => Some("LZW"),
+        Compression::Deflate => Some("DEFLATE"),
+        Compression::Zstd => Some("ZSTD"),
+    };
+    if let Some(codec) = codec {
+        options.push(("COMPRESS", codec));
+        options.push(("PREDICTOR", if is_float { "3" } else { "2" }));
+    }
+    if tiled {
+        options.push(("TILED", "YES"));
+        options.push(("BLOCKXSIZE", "256"));
+        options.push(("BLOCKYSIZE", "256"));
+    }
+    options
+}
+
+impl<T: GdalType + Default + Copy  + ToF64> Raster<T> {
+    /// Writes the raster with the driver inferred from the extension of `path`
+    /// (see `driver_for_path`).
+    pub fn write(&self, path: &str) -> Result<(), RasterError> {
+        self.write_with_options(path, None, &[])
+    }
+
+    /// Writes the raster like `write` and sets each `(key, value)` pair as a
+    /// dataset metadata item in the default domain, e.g. `("units", "t/ha")`.
+    pub fn write_with_metadata(&self, path: &str, metadata: &[(&str, &str)]) -> Result<(), RasterError> {
+        self.write_with_options(path, None, metadata)
+    }
+
+    /// Writes the raster with the GDAL `driver` (e.g. `"GTiff"`), or with the
+    /// driver inferred from `path` when `None`, setting `metadata` as in
+    /// `write_with_metadata`.
+    pub fn write_with_options(&self, path: &str, driver: Option<&str>, metadata: &[(&str, &str)]) -> Result<(), RasterError> {
+        self.write_dataset(path, driver, metadata, &[])
+    }
+
+    /// Writes the raster as a GeoTIFF compressed with `compression` (`"none"`,
+    /// `"lzw"`, `"deflate"`, or `"zstd"`), in 256 x 256 tiles when `tiled`.
+    ///
+    /// The data is unchanged; only the storage layout differs. Returns
+    /// `InvalidArgument` for an unknown compression.
+    #[allow(dead_code)]
+    pub fn write_compressed(&self, path: &str, compression: &str, tiled: bool) -> Result<(), RasterError> {
+        let compression = Compression::from_str(compression).map_err(|_| RasterError::InvalidArgument(format!(
+            "unknown compression {:?}: expected none, lzw, deflate, or zstd", compression)))?;
+
+        // Float32, Float64
+        let is_float = matches!(T::gdal_type(), 6 | 7);
+        let options: Vec<RasterCreationOption> = geotiff_creation_options(compression, tiled, is_float)
+            .into_iter()
+            .map(|(key, value)| RasterCreationOption { key, value })
+            .collect();
+        self.write_dataset(path, Some("GTiff"), &[], &options)
+    }
+
+    /// Creates `path` with `driver` (inferred from `path` when `None`) and the
+    /// creation `options`, and fills it (see `fill_dataset`).
+    ///
+    /// Drivers that cannot create datasets directly, such as AAIGrid, are
+    /// written by copying an in-memory dataset.
+    fn write_dataset(
+        &self,
+        path: &str,
+        driver: Option<&str>,
+        metadata: &[(&str, &str)],
+        options: &[RasterCreationOption]
+    ) -> Result<(), RasterError> {
+        let driver = gdal::Driver::get(driver.unwrap_or_else(|| driver_for_path(path)))?;
+        let (width, height) = (self.width as isize, self.height as isize);
+
+        if driver.metadata_item("DCAP_CREATE", "").is_some() {
+            let mut dataset = driver.create_with_band_type_with_options::<T, &str>(path, width, height, 1, options)?;
+            self.fill_dataset(&mut dataset, metadata)?;
+        } else {
+            let mut dataset = gdal::Driver::get("MEM")?.create_with_band_type::<T, &str>("", width, height, 1)?;
+            self.fill_dataset(&mut dataset, metadata)?;
+            dataset.create_copy(&driver, path, options)?;
+        }
+
+        Ok(())
+    }
+
+    /// Copies the georeferencing, metadata, data, and no-data value into a
+    /// freshly created single band dataset.
+    fn fill_dataset(&self, dataset: &mut gdal::Dataset, metadata: &[(&str, &str)]) -> Result<(), RasterError> {
+        // Set the geotransform and projection
+        dataset.set_geo_transform(&self.geo_transform)?;
+        if let Some(proj) = &self.proj4 {
+            let spatial_ref = SpatialRef::from_proj4(&proj)?;
+            let wkt = spatial_ref.to_wkt()?;
+            dataset.set_projection(&wkt)?;
+        }
+
+        for (key, value) in metadata {
+            dataset.set_metadata_item(key, value, "")?;
+        }
+
+        // Write the raster data
+        let mut band = dataset.rasterband(1)?;
+        let buffer = Buffer::new((self.width, self.height), self.data.clone());
+        band.write((0, 0), (self.width, self.height), &buffer)?;
+
+        // Set the NoData value if it exists
         if let Some(no_data_val) = self.no_data {
             let no_data_f64: f64 = no_data_val.to_f64();
             band.set_no_data_value(no_data_f64)?;
@@ -426,8 +1179,547 @@ impl<T> Raster<T> {
 }
 
 
-impl<T: std::hash::Hash + Eq + Copy> Raster<T> {
+/// Kernel used when sampling a raster between cell centers.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ResampleMethod {
+    /// Value of the cell containing the point; suited to categorical grids.
+    Nearest,
+    /// Distance-weighted blend of the four surrounding cell centers; suited to continuous grids.
+    Bilinear,
+    /// Cubic convolution over the sixteen surrounding cell centers; smoother than bilinear
+    /// for continuous grids such as elevation.
+    Cubic,
+}
+
+impl FromStr for ResampleMethod {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "nearest" => Ok(ResampleMethod::Nearest),
+            "bilinear" => Ok(ResampleMethod::Bilinear),
+            "cubic" => Ok(ResampleMethod::Cubic),
+            _ => Err(()),
+        }
+    }
+}
+
+/// Compares geo_transform terms to within floating point noise.
+fn geo_transform_term_eq(a: f64, b: f64) -> bool {
+    (a - b).abs() <= 1e-9 * a.abs().max(b.abs()).max(1.0)
+}
+
+/// How the grid of one raster (`b`) differs from another (`a`); see
+/// `Raster::alignment_report`. Each field is `None` when that property matches
+/// and otherwise describes the mismatch.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct AlignmentReport {
+    pub width: Option<String>,
+    pub height: Option<String>,
+    /// Pixel size, and the rotation terms of the geo_transform.
+    pub cellsize: Option<String>,
+    pub crs: Option<String>,
+    /// Offset of the upper left corner, in pixels of `a`.
+    pub origin: Option<String>,
+}
+
+impl AlignmentReport {
+    /// Returns `true` when the cells of the two grids line up one-to-one.
+    pub fn is_aligned(&self) -> bool {
+        *self == AlignmentReport::default()
+    }
+
+    /// Suggests how to bring `b` onto the grid of `a`, or `None` when already aligned.
+    pub fn suggestion(&self) -> Option<String> {
+        if self.is_aligned() {
+            None
+        } else if self.crs.is_some() {
+            Some("reproject b to the CRS of a (e.g. gdalwarp -t_srs) and resample it onto the grid of a".to_string())
+        } else {
+            Some("resample b onto the grid of a, e.g. with resample_if_needed=True".to_string())
+        }
+    }
+}
+
+impl<T> Raster<T> {
+    /// Returns `true` when `other` has the same dimensions and (to within
+    /// floating point noise) the same geo_transform, i.e. its cells line up
+    /// one-to-one with the cells of `self`.
+    pub fn is_aligned_with<U>(&self, other: &Raster<U>) -> bool {
+        self.width == other.width
+            && self.height == other.height
+            && self.geo_transform.iter().zip(other.geo_transform.iter())
+                .all(|(&a, &b)| geo_transform_term_eq(a, b))
+    }
+
+    /// Like `is_aligned_with`, but fails with `RasterError::Dimension` naming
+    /// both rasters when they are not aligned.
+    pub fn check_aligned_with<U>(&self, other: &Raster<U>) -> Result<(), RasterError> {
+        if !self.is_aligned_with(other) {
+            return Err(RasterError::Dimension(format!(
+                "{} ({} x {}) and {} ({} x {}) are not on the same grid",
+                self.path, self.width, self.height, other.path, other.width, other.height)));
+        }
+        Ok(())
+    }
+
+    /// Describes how the grid of `other` (`b`) differs from that of `self` (`a`).
+    ///
+    /// Unlike `is_aligned_with` this also compares the projections, so two
+    /// grids with identical numbers in different CRSs are reported.
+    pub fn alignment_report<U>(&self, other: &Raster<U>) -> AlignmentReport {
+        let (a, b) = (&self.geo_transform, &other.geo_transform);
+        let mut report = AlignmentReport::default();
+
+        if self.width != other.width {
+            report.width = Some(format!("widths differ {} vs {}", self.width, other.width));
+        }
+        if self.height != other.height {
+            report.height = Some(format!("heights differ {} vs {}", self.height, other.height));
+        }
+
+        if !geo_transform_term_eq(a[1], b[1]) || !geo_transform_term_eq(a[5], b[5]) {
+            report.cellsize = if a[1] == -a[5] && b[1] == -b[5] {
+                Some(format!("cellsizes differ {} vs {}", a[1], b[1]))
+            } else {
+                Some(format!("cellsizes differ {} x {} vs {} x {}", a[1], -a[5], b[1], -b[5]))
+            };
+        } else if !geo_transform_term_eq(a[2], b[2]) || !geo_transform_term_eq(a[4], b[4]) {
+            report.cellsize = Some(format!("rotation terms differ ({}, {}) vs ({}, {})", a[2], a[4], b[2], b[4]));
+        }
+
+        let proj4_a = self.proj4.as_deref().map(str::trim);
+        let proj4_b = other.proj4.as_deref().map(str::trim);
+        if proj4_a != proj4_b {
+            report.crs = Some(format!("CRSs differ: a is {} but b is {}",
+                proj4_a.unwrap_or("undefined"), proj4_b.unwrap_or("undefined")));
+        }
+
+        if !geo_transform_term_eq(a[0], b[0]) || !geo_transform_term_eq(a[3], b[3]) {
+            let round = |v: f64| (v * 1000.0).round() / 1000.0;
+            let dx = round((b[0] - a[0]) / a[1]);
+            let dy = round((b[3] - a[3]) / a[5]);
+            let mut parts: Vec<String> = Vec::new();
+            if dx != 0.0 {
+                parts.push(format!("{} px in x", dx));
+            }
+            if dy != 0.0 {
+                parts.push(format!("{} px in y", dy));
+            }
+            if parts.is_empty() {
+                parts.push("less than 0.001 px".to_string());
+            }
+            report.origin = Some(format!("b is offset by {}", parts.join(" and ")));
+        }
+
+        report
+    }
+
+    /// Maps an easting/northing to a fractional pixel coordinate by inverting
+    /// the geo_transform. `(0.0, 0.0)` is the upper left corner of the grid
+    /// and the center of the upper left cell is `(0.5, 0.5)`.
+    pub fn map_to_px(&self, e: f64, n: f64) -> (f64, f64) {
+        let gt = &self.geo_transform;
+        let det = gt[1] * gt[5] - gt[2] * gt[4];
+        let de = e - gt[0];
+        let dn = n - gt[3];
+        let px = (gt[5] * de - gt[2] * dn) / det;
+        let py = (gt[1] * dn - gt[4] * de) / det;
+        (px, py)
+    }
+
+    /// Maps a fractional pixel coordinate to an easting/northing.
+    pub fn px_to_map(&self, px: f64, py: f64) -> (f64, f64) {
+        let e: f64 = self.geo_transform[0] + px * self.geo_transform[1] + py * self.geo_transform[2];
+        let n: f64 = self.geo_transform[3] + px * self.geo_transform[4] + py * self.geo_transform[5];
+        (e, n)
+    }
+}
+
+/// A polygon as rings of `(x, y)` vertices: the exterior ring first, then its holes.
+pub type Polygon = Vec<Vec<(f64, f64)>>;
+
+/// Even-odd ray casting test of whether `(x, y)` lies inside a closed ring.
+fn ring_contains(ring: &[(f64, f64)], x: f64, y: f64) -> bool {
+    let mut inside = false;
+    let mut j = ring.len().wrapping_sub(1);
+    for i in 0..ring.len() {
+        let (xi, yi) = ring[i];
+        let (xj, yj) = ring[j];
+        if (yi > y) != (yj > y) && x < xj + (y - yj) * (xi - xj) / (yi - yj) {
+            inside = !inside;
+        }
+        j = i;
+    }
+    inside
+}
+
+/// Returns `true` when `(x, y)` lies inside the exterior ring of `polygon` and
+/// outside all of its holes.
+pub fn polygon_contains(polygon: &[Vec<(f64, f64)>], x: f64, y: f64) -> bool {
+    match polygon.split_first() {
+        Some((exterior, holes)) => ring_contains(exterior, x, y)
+            && !holes.iter().any(|hole| ring_contains(hole, x, y)),
+        None => false,
+    }
+}
+
+impl<T> Raster<T> {
+    /// Returns one flag per cell, in `data` order, that is `true` where the cell
+    /// center lies inside any of `polygons` (in the raster's map coordinates),
+    /// e.g. the `include` mask of the zonal statistics.
+    pub fn polygon_mask(&self, polygons: &[Polygon]) -> Vec<bool> {
+        (0..self.data.len())
+            .map(|indx| {
+                let (x, y) = self.index_to_xy(indx);
+                let (e, n) = self.px_to_map(x as f64 + 0.5, y as f64 + 0.5);
+                polygons.iter().any(|polygon| polygon_contains(polygon, e, n))
+            })
+            .collect()
+    }
+
+    /// Like `polygon_mask` for polygons with WGS84 longitude/latitude vertices,
+    /// e.g. a burn perimeter from GeoJSON, which are reprojected to the raster's CRS.
+    ///
+    /// # Errors
+    ///
+    /// Returns `RasterError::InvalidArgument` when the raster has no projection
+    /// and a `RasterError` when a vertex cannot be reprojected.
+    pub fn polygon_mask_lnglat(&self, polygons: &[Polygon]) -> Result<Vec<bool>, RasterError> {
+        let proj4 = self.proj4.as_ref().ok_or_else(|| RasterError::InvalidArgument(
+            format!("{}: raster has no projection", self.path)))?;
+
+        let projected: Vec<Polygon> = polygons.iter()
+            .map(|polygon| polygon.iter()
+                .map(|ring| ring.iter()
+                    .map(|&(lng, lat)| transform_coords(lng, lat, "+proj=longlat +datum=WGS84 +no_defs", proj4))
+                    .collect::<Result<Vec<(f64, f64)>, RasterError>>())
+                .collect::<Result<Polygon, RasterError>>())
+            .collect::<Result<_, _>>()?;
+
+        Ok(self.polygon_mask(&projected))
+    }
+}
+
+/// Returns `true` when `value` is the no-data value `no_data`.
+///
+/// A NaN no-data matches every NaN cell: NaN never compares equal to itself,
+/// so a plain `==` would treat NaN-as-no-data float rasters as all valid.
+#[allow(clippy::eq_op)]
+pub fn is_no_data_value<T: PartialEq>(value: T, no_data: Option<T>) -> bool {
+    match no_data {
+        // `x != x` holds only for NaN
+        Some(no_data_value) => value == no_data_value || (no_data_value != no_data_value && value != value),
+        None => false,
+    }
+}
+
+impl<T: Copy + PartialEq> Raster<T> {
+    /// Returns `true` when `value` is this raster's no-data value (see `is_no_data_value`).
+    pub fn is_no_data(&self, value: T) -> bool {
+        is_no_data_value(value, self.no_data)
+    }
+
+    /// Returns the value at column `x`, row `y`, or `None` outside the grid or on no-data.
+    fn valid_value_at(&self, x: isize, y: isize) -> Option<T> {
+        if x < 0 || y < 0 || x as usize >= self.width || y as usize >= self.height {
+            return None;
+        }
+        let value = self.data[self.xy_to_index(x as usize, y as usize)];
+        if self.is_no_data(value) { None } else { Some(value) }
+    }
+
+    /// Nearest-neighbour sample at a fractional pixel coordinate.
+    fn nearest_at_px(&self, px: f64, py: f64) -> Option<T> {
+        if px.is_nan() || py.is_nan() {
+            return None;
+        }
+        self.valid_value_at(px.floor() as isize, py.floor() as isize)
+    }
+
+    /// Returns the value of the cell containing the easting/northing, or
+    /// `None` outside the grid or on no-data.
+    pub fn sample_nearest(&self, e: f64, n: f64) -> Option<T> {
+        let (px, py) = self.map_to_px(e, n);
+        self.nearest_at_px(px, py)
+    }
+
+    /// Returns the `(x0, y0, width, height)` pixel window enclosing every valid
+    /// (non no-data) cell, or `None` when the raster has no valid cells.
     #[allow(dead_code)]
+    pub fn valid_window(&self) -> Option<(usize, usize, usize, usize)> {
+        let indices: Vec<usize> = (0..self.data.len())
+            .filter(|&i| !self.is_no_data(self.data[i]))
+            .collect();
+        self.bounding_window(&indices)
+    }
+
+    /// Returns the WGS84 `(west, south, east, north)` bounds of the valid cells
+    /// (see `valid_window`), e.g. to fit a web map to the watershed rather than
+    /// the padded raster extent.
+    ///
+    /// The four corners of the valid window are transformed and their extremes
+    /// taken. Returns `None` when there are no valid cells, the raster has no
+    /// projection, or the transformation fails.
+    #[allow(dead_code)]
+    pub fn valid_bounds_lnglat(&self) -> Option<(f64, f64, f64, f64)> {
+        let proj4 = self.proj4.as_ref()?;
+        let (x0, y0, w, h) = self.valid_window()?;
+        let (x0, y0, x1, y1) = (x0 as f64, y0 as f64, (x0 + w) as f64, (y0 + h) as f64);
+
+        let mut bounds = (f64::INFINITY, f64::INFINITY, f64::NEG_INFINITY, f64::NEG_INFINITY);
+        for &(px, py) in &[(x0, y0), (x1, y0), (x0, y1), (x1, y1)] {
+            let (e, n) = self.px_to_map(px, py);
+            let (lng, lat) = transform_coords(e, n, proj4, "+proj=longlat +datum=WGS84 +no_defs").ok()?;
+            bounds = (bounds.0.min(lng), bounds.1.min(lat), bounds.2.max(lng), bounds.3.max(lat));
+        }
+        Some(bounds)
+    }
+}
+
+impl<T: Copy + PartialEq + ToF64> Raster<T> {
+    /// Bilinear sample at a fractional pixel coordinate.
+    ///
+    /// Interpolates between the four surrounding cell centers; within half a
+    /// cell of the border the edge cells are reused. Returns `None` outside
+    /// the grid or when any contributing cell is no-data.
+    fn bilinear_at_px(&self, px: f64, py: f64) -> Option<f64> {
+        if !(px >= 0.0 && py >= 0.0 && px <= self.width as f64 && py <= self.height as f64) {
+            return None;
+        }
+
+        // shift so integer coordinates fall on cell centers
+        let fx = px - 0.5;
+        let fy = py - 0.5;
+        let x0 = fx.floor();
+        let y0 = fy.floor();
+        let tx = fx - x0;
+        let ty = fy - y0;
+
+        let clamp_x = |x: f64| (x.max(0.0) as isize).min(self.width as isize - 1);
+        let clamp_y = |y: f64| (y.max(0.0) as isize).min(self.height as isize - 1);
+        let (xa, xb) = (clamp_x(x0), clamp_x(x0 + 1.0));
+        let (ya, yb) = (clamp_y(y0), clamp_y(y0 + 1.0));
+
+        let v00 = self.valid_value_at(xa, ya)?.to_f64();
+        let v10 = self.valid_value_at(xb, ya)?.to_f64();
+        let v01 = self.valid_value_at(xa, yb)?.to_f64();
+        let v11 = self.valid_value_at(xb, yb)?.to_f64();
+
+        let top = v00 + (v10 - v00) * tx;
+        let bottom = v01 + (v11 - v01) * tx;
+        Some(top + (bottom - top) * ty)
+    }
+
+    /// Cubic convolution sample at a fractional pixel coordinate.
+    ///
+    /// Weighs the 4 x 4 surrounding cell centers with the Keys kernel
+    /// (a = -0.5, as GDAL's cubic), reusing the edge cells near the border like
+    /// `bilinear_at_px`. Returns `None` outside the grid or when any
+    /// contributing cell is no-data.
+    fn cubic_at_px(&self, px: f64, py: f64) -> Option<f64> {
+        if !(px >= 0.0 && py >= 0.0 && px <= self.width as f64 && py <= self.height as f64) {
+            return None;
+        }
+
+        fn weight(d: f64) -> f64 {
+            const A: f64 = -0.5;
+            let d = d.abs();
+            if d <= 1.0 {
+                ((A + 2.0) * d - (A + 3.0)) * d * d + 1.0
+            } else if d < 2.0 {
+                ((A * d - 5.0 * A) * d + 8.0 * A) * d - 4.0 * A
+            } else {
+                0.0
+            }
+        }
+
+        // shift so integer coordinates fall on cell centers
+        let fx = px - 0.5;
+        let fy = py - 0.5;
+        let x0 = fx.floor();
+        let y0 = fy.floor();
+        let tx = fx - x0;
+        let ty = fy - y0;
+
+        let clamp_x = |x: f64| (x.max(0.0) as isize).min(self.width as isize - 1);
+        let clamp_y = |y: f64| (y.max(0.0) as isize).min(self.height as isize - 1);
+
+        let mut value = 0.0;
+        for j in -1..=2 {
+            let wy = weight(ty - j as f64);
+            let y = clamp_y(y0 + j as f64);
+            for i in -1..=2 {
+                let wx = weight(tx - i as f64);
+                let x = clamp_x(x0 + i as f64);
+                value += wx * wy * self.valid_value_at(x, y)?.to_f64();
+            }
+        }
+        Some(value)
+    }
+
+    /// Returns the bilinearly interpolated value at the easting/northing, or
+    /// `None` outside the grid or when any of the four surrounding cells is
+    /// no-data. Smoother than `sample_nearest` for continuous surfaces such as
+    /// elevation.
+    pub fn sample_bilinear(&self, e: f64, n: f64) -> Option<f64> {
+        let (px, py) = self.map_to_px(e, n);
+        self.bilinear_at_px(px, py)
+    }
+
+    /// Samples the raster at a projected easting/northing with `method`,
+    /// e.g. the value at a gauge: `sample_nearest`, `sample_bilinear`, or cubic
+    /// convolution (see `cubic_at_px`).
+    #[allow(dead_code)]
+    pub fn sample_at(&self, e: f64, n: f64, method: ResampleMethod) -> Option<f64> {
+        match method {
+            ResampleMethod::Nearest => self.sample_nearest(e, n).map(|v| v.to_f64()),
+            ResampleMethod::Bilinear => self.sample_bilinear(e, n),
+            ResampleMethod::Cubic => {
+                let (px, py) = self.map_to_px(e, n);
+                self.cubic_at_px(px, py)
+            },
+        }
+    }
+}
+
+impl<T: Copy + PartialEq + ToF64> Raster<T> {
+    /// Samples the raster at `n` evenly spaced points from `start` to `end`
+    /// (both easting/northing in map units, inclusive) with bilinear
+    /// interpolation. Points outside the grid or next to no-data are `NaN`.
+    #[allow(dead_code)]
+    pub fn extract_profile(&self, start: (f64, f64), end: (f64, f64), n: usize) -> Vec<f64> {
+        (0..n)
+            .map(|i| {
+                let t = if n > 1 { i as f64 / (n - 1) as f64 } else { 0.0 };
+                let east = start.0 + t * (end.0 - start.0);
+                let north = start.1 + t * (end.1 - start.1);
+                self.sample_bilinear(east, north).unwrap_or(f64::NAN)
+            })
+            .collect()
+    }
+}
+
+impl<T: Copy + PartialEq + ToF64 + FromF64> Raster<T> {
+    /// Resamples the raster onto the grid of `target`.
+    ///
+    /// Each target cell center is mapped into this raster and sampled with
+    /// `method`. Both rasters are assumed to share a CRS. Target cells that
+    /// fall outside this raster or on no-data take this raster's no-data value
+    /// (-9999 when it has none).
+    #[allow(dead_code)]
+    pub fn resample_to_match<U>(&self, target: &Raster<U>, method: ResampleMethod) -> Raster<T> {
+        let fill: T = self.no_data.unwrap_or_else(|| T::from_f64(-9999.0));
+
+        let mut data: Vec<T> = Vec::with_capacity(target.width * target.height);
+        for y in 0..target.height {
+            for x in 0..target.width {
+                let (e, n) = target.px_to_map(x as f64 + 0.5, y as f64 + 0.5);
+                let (px, py) = self.map_to_px(e, n);
+                let value = match method {
+                    ResampleMethod::Nearest => self.nearest_at_px(px, py),
+                    ResampleMethod::Bilinear => self.bilinear_at_px(px, py).map(T::from_f64),
+                    ResampleMethod::Cubic => self.cubic_at_px(px, py).map(T::from_f64),
+                };
+                data.push(value.unwrap_or(fill));
+            }
+        }
+
+        Raster {
+            width: target.width,
+            height: target.height,
+            cellsize: target.cellsize,
+            data: data,
+            no_data: Some(fill),
+            geo_transform: target.geo_transform,
+            proj4: target.proj4.clone(),
+            path: self.path.clone(),
+            name: self.name.clone(),
+            map_type: self.map_type.clone(),
+            wgs_transform: target.wgs_transform,
+        }
+    }
+}
+
+/// Points sampled along each edge of the source extent when sizing a
+/// reprojected grid; a curved edge in the target CRS can bulge past its corners.
+const REPROJECT_EDGE_SAMPLES: usize = 21;
+
+impl Raster<f64> {
+    /// Reprojects the raster into `target_proj4`, sampling with `method`.
+    ///
+    /// Like `gdalwarp -t_srs`, the output grid is north-up and covers the
+    /// transformed extent of the source, and its square cells keep the number
+    /// of cells along the source diagonal. Each output cell center is mapped
+    /// back into the source and sampled (see `sample_at`); cells that fall
+    /// outside the source or on no-data take the source's no-data value (-9999
+    /// when it has none).
+    ///
+    /// Fails with `InvalidArgument` when the raster has no projection, and with
+    /// a PROJ error when the extent cannot be transformed.
+    #[allow(dead_code)]
+    pub fn reproject(&self, target_proj4: &str, method: ResampleMethod) -> Result<Raster<f64>, RasterError> {
+        let src_proj4 = self.proj4.as_deref().ok_or_else(|| RasterError::InvalidArgument(
+            format!("{} has no projection to reproject from", self.path)))?;
+
+        // transformed extent of the source, from points along its edges
+        let (w, h) = (self.width as f64, self.height as f64);
+        let mut edge: Vec<(f64, f64)> = Vec::with_capacity(4 * REPROJECT_EDGE_SAMPLES);
+        for i in 0..REPROJECT_EDGE_SAMPLES {
+            let t = i as f64 / (REPROJECT_EDGE_SAMPLES - 1) as f64;
+            edge.extend_from_slice(&[(t * w, 0.0), (t * w, h), (0.0, t * h), (w, t * h)]);
+        }
+        let (mut min_e, mut min_n, mut max_e, mut max_n) =
+            (f64::INFINITY, f64::INFINITY, f64::NEG_INFINITY, f64::NEG_INFINITY);
+        for (px, py) in edge {
+            let (e, n) = self.px_to_map(px, py);
+            let (e, n) = transform_coords(e, n, src_proj4, target_proj4)?;
+            min_e = min_e.min(e);
+            min_n = min_n.min(n);
+            max_e = max_e.max(e);
+            max_n = max_n.max(n);
+        }
+        if ![min_e, min_n, max_e, max_n].iter().all(|v| v.is_finite()) {
+            return Err(RasterError::InvalidArgument(format!(
+                "{}: extent is not finite in {}", self.path, target_proj4)));
+        }
+
+        // keep as many cells along the diagonal as the source has
+        let (e0, n0) = self.px_to_map(0.0, 0.0);
+        let (e1, n1) = self.px_to_map(w, h);
+        let (e0, n0) = transform_coords(e0, n0, src_proj4, target_proj4)?;
+        let (e1, n1) = transform_coords(e1, n1, src_proj4, target_proj4)?;
+        let cellsize = ((e1 - e0).powi(2) + (n1 - n0).powi(2)).sqrt() / (w * w + h * h).sqrt();
+        if !(cellsize.is_finite() && cellsize > 0.0) {
+            return Err(RasterError::InvalidArgument(format!(
+                "{}: cannot derive a cell size in {}", self.path, target_proj4)));
+        }
+
+        let width = (((max_e - min_e) / cellsize).ceil() as usize).max(1);
+        let height = (((max_n - min_n) / cellsize).ceil() as usize).max(1);
+        let geo_transform = [min_e, cellsize, 0.0, max_n, 0.0, -cellsize];
+        let fill = self.no_data.unwrap_or(-9999.0);
+
+        let data: Vec<f64> = (0..width * height).into_par_iter()
+            .map(|indx| {
+                let (x, y) = (indx % width, indx / width);
+                let e = geo_transform[0] + (x as f64 + 0.5) * cellsize;
+                let n = geo_transform[3] - (y as f64 + 0.5) * cellsize;
+                transform_coords(e, n, target_proj4, src_proj4).ok()
+                    .and_then(|(se, sn)| self.sample_at(se, sn, method))
+                    .unwrap_or(fill)
+            })
+            .collect();
+
+        Raster::try_new(width, height, cellsize, data, Some(fill), geo_transform,
+            Some(target_proj4.to_string()), self.path.clone(), self.name.clone(), self.map_type.clone())
+    }
+}
+
+
+impl<T: PartialEq + Copy> Raster<T> {
+    /// Returns one flag per cell, in `data` order, that is `true` on no-data (including NaN
+    /// when the no-data value is NaN).
     pub fn mask(&self) -> Vec<bool> {
 
         let mut the_mask = Vec::new();
@@ -437,7 +1729,7 @@ impl<T: std::hash::Hash + Eq + Copy> Raster<T> {
             for x in 0..self.width {
                 let index = self.xy_to_index(x, y);
                 let value = &self.data[index];
-                let mask_value = !(no_data.is_none() || value != no_data.unwrap());
+                let mask_value = is_no_data_value(value, no_data);
                 // add value to the_mask
                 the_mask.push(mask_value);
             }
@@ -447,6 +1739,30 @@ impl<T: std::hash::Hash + Eq + Copy> Raster<T> {
 }
 
 
+impl<T: PartialEq + Copy + FromF64> Raster<T> {
+    /// Restricts the raster to the valid cells of an aligned `other`, e.g. a
+    /// continuous surface to the delineated watershed of a `SUBWTA`.
+    ///
+    /// Cells that are no-data in `other` become no-data; the rest keep their
+    /// value. The result takes the no-data value of `self`, else -9999.
+    ///
+    /// # Errors
+    ///
+    /// Returns `RasterError::Dimension` when the rasters are not aligned
+    /// (see `is_aligned_with`).
+    #[allow(dead_code)]
+    pub fn masked_by_valid<U: PartialEq + Copy>(&self, other: &Raster<U>) -> Result<Raster<T>, RasterError> {
+        self.check_aligned_with(other)?;
+
+        let no_data = self.no_data.unwrap_or_else(|| T::from_f64(-9999.0));
+        let data: Vec<T> = self.data.iter().zip(other.data.iter())
+            .map(|(&value, &mask)| if other.is_no_data(mask) { no_data } else { value })
+            .collect();
+
+        Ok(self.with_data(data, Some(no_data)))
+    }
+}
+
 impl<T: std::hash::Hash + Eq + Copy> Raster<T> {
     pub fn unique_values(&self) -> HashSet<T> {
 
@@ -457,7 +1773,7 @@ impl<T: std::hash::Hash + Eq + Copy> Raster<T> {
             for x in 0..self.width {
                 let index = y * self.width + x;
                 let value = &self.data[index];
-                if no_data.is_none() || value != no_data.unwrap() {
+                if !is_no_data_value(value, no_data) {
                     unique_values.insert(*value);
                 }
             }
@@ -466,6 +1782,17 @@ impl<T: std::hash::Hash + Eq + Copy> Raster<T> {
     }
 }
 
+impl<T: std::hash::Hash + Eq + Copy + Ord> Raster<T> {
+    /// Returns the distinct valid values (see `unique_values`) in ascending
+    /// order, for reproducible id lists.
+    #[allow(dead_code)]
+    pub fn sorted_unique_values(&self) -> Vec<T> {
+        let mut values: Vec<T> = self.unique_values().into_iter().collect();
+        values.sort_unstable();
+        values
+    }
+}
+
 //impl<T: std::hash::Hash + Eq + Copy> Raster<T> {
 impl Raster<i32> {
     pub fn indices_of(&self, target: i32) -> HashSet<usize> {
@@ -483,6 +1810,359 @@ impl Raster<i32> {
         }
         indices
     }
+
+    /// Bins every cell index by its value in one pass, skipping no-data cells.
+    ///
+    /// Equivalent to calling `indices_of` for each unique value, without a full
+    /// scan per value. The indices of each value are ascending.
+    #[allow(dead_code)]
+    pub fn group_indices(&self) -> HashMap<i32, Vec<usize>> {
+        let mut groups: HashMap<i32, Vec<usize>> = HashMap::new();
+        for (indx, &value) in self.data.iter().enumerate() {
+            if self.is_no_data(value) {
+                continue;
+            }
+            groups.entry(value).or_default().push(indx);
+        }
+        groups
+    }
+
+    /// Maps per-zone values back onto the grid: every cell takes the value of
+    /// its zone id in `values`, the inverse of a zonal statistic.
+    ///
+    /// Cells whose zone is no-data or missing from `values` take `default`,
+    /// which is also the no-data value of the result.
+    #[allow(dead_code)]
+    pub fn paint_values(&self, values: &HashMap<i32, f64>, default: f64) -> Raster<f64> {
+        let data: Vec<f64> = self.data.iter()
+            .map(|zone| {
+                if self.no_data == Some(*zone) {
+                    default
+                } else {
+                    values.get(zone).cloned().unwrap_or(default)
+                }
+            })
+            .collect();
+        self.with_data(data, Some(default))
+    }
+
+    /// Keeps only the hillslope cells of a SUBWTA raster: channel cells (ids
+    /// ending in 4) and background cells (id 0) become no-data, e.g. for a clean
+    /// hillslope mask before zonal statistics.
+    ///
+    /// The result keeps the no-data value of `self`, else uses 0.
+    #[allow(dead_code)]
+    pub fn hillslopes_only(&self) -> Raster<i32> {
+        let no_data = self.no_data.unwrap_or(BACKGROUND_KEY);
+        let data: Vec<i32> = self.data.iter()
+            .map(|&id| if id == BACKGROUND_KEY || is_channel(id) { no_data } else { id })
+            .collect();
+        self.with_data(data, Some(no_data))
+    }
+
+    /// Euclidean allocation: assigns every `background` cell the value of the
+    /// nearest non-background, non-no-data cell.
+    ///
+    /// Nearest sources are propagated outward from the valued cells through
+    /// background cells (Dijkstra ordered by squared pixel distance to the
+    /// source), so gaps such as removed channels are filled with the adjacent
+    /// hillslope id. Background cells not connected to any source keep
+    /// their value; ties resolve to the source with the lower index.
+    #[allow(dead_code)]
+    pub fn allocate_nearest(&self, background: i32) -> Raster<i32> {
+        let n = self.data.len();
+        let mut best: Vec<u64> = vec![u64::MAX; n];
+        let mut source: Vec<usize> = (0..n).collect();
+        let mut heap = BinaryHeap::new();
+
+        for (indx, &value) in self.data.iter().enumerate() {
+            if value != background && Some(value) != self.no_data {
+                best[indx] = 0;
+                heap.push(Reverse((0u64, indx, indx)));
+            }
+        }
+
+        while let Some(Reverse((dist2, indx, src))) = heap.pop() {
+            if dist2 > best[indx] || source[indx] != src {
+                continue;
+            }
+
+            let (x, y) = self.index_to_xy(indx);
+            let (sx, sy) = self.index_to_xy(src);
+            for dy in -1i64..=1 {
+                for dx in -1i64..=1 {
+                    let (nx, ny) = (x as i64 + dx, y as i64 + dy);
+                    if (dx == 0 && dy == 0) || nx < 0 || ny < 0 || nx >= self.width as i64 || ny >= self.height as i64 {
+                        continue;
+                    }
+
+                    let neighbor = self.xy_to_index(nx as usize, ny as usize);
+                    if self.data[neighbor] != background {
+                        continue;
+                    }
+
+                    let d2 = ((nx - sx as i64).pow(2) + (ny - sy as i64).pow(2)) as u64;
+                    if d2 < best[neighbor] {
+                        best[neighbor] = d2;
+                        source[neighbor] = src;
+                        heap.push(Reverse((d2, neighbor, src)));
+                    }
+                }
+            }
+        }
+
+        let data: Vec<i32> = (0..n).map(|indx| self.data[source[indx]]).collect();
+        self.with_data(data, self.no_data)
+    }
+
+    /// Coarsens a categorical raster by `factor`, giving every coarse cell the
+    /// majority class of the `factor x factor` block of fine cells it covers.
+    ///
+    /// Unlike nearest-neighbor resampling, small classes such as narrow
+    /// hillslopes survive wherever they dominate a block. No-data cells do not
+    /// vote; a block without valid cells is no-data, and ties resolve to the
+    /// smallest class. Blocks on the right and bottom edges may be partial, so
+    /// the result is `ceil(width / factor)` by `ceil(height / factor)` with the
+    /// same origin.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `factor` is 0.
+    #[allow(dead_code)]
+    pub fn aggregate_majority(&self, factor: usize) -> Raster<i32> {
+        assert!(factor > 0, "aggregation factor must be greater than 0");
+
+        let width = (self.width + factor - 1) / factor;
+        let height = (self.height + factor - 1) / factor;
+        let fill = self.no_data.unwrap_or_default();
+
+        let mut data = Vec::with_capacity(width * height);
+        let mut block: Vec<i32> = Vec::with_capacity(factor * factor);
+        for by in 0..height {
+            for bx in 0..width {
+                block.clear();
+                for y in by * factor..((by + 1) * factor).min(self.height) {
+                    for x in bx * factor..((bx + 1) * factor).min(self.width) {
+                        let value = self.data[self.xy_to_index(x, y)];
+                        if !self.is_no_data(value) {
+                            block.push(value);
+                        }
+                    }
+                }
+                block.sort_unstable();
+
+                // runs of equal classes; the first longest run holds the smallest class
+                let mut majority = (fill, 0);
+                let mut start = 0;
+                while start < block.len() {
+                    let end = block[start..].iter().position(|&v| v != block[start]).map_or(block.len(), |n| start + n);
+                    if end - start > majority.1 {
+                        majority = (block[start], end - start);
+                    }
+                    start = end;
+                }
+                data.push(majority.0);
+            }
+        }
+
+        let mut geo_transform = self.geo_transform;
+        for term in [1, 2, 4, 5] {
+            geo_transform[term] *= factor as f64;
+        }
+
+        Raster::new(
+            width,
+            height,
+            self.cellsize * factor as f64,
+            data,
+            self.no_data,
+            geo_transform,
+            self.proj4.clone(),
+            self.path.clone(),
+            self.name.clone(),
+            self.map_type.clone(),
+        )
+    }
+}
+
+/// Returns the `(dx, dy)` pixel step of a TOPAZ `FLOVEC` direction code.
+///
+/// Codes follow the keypad layout of `flow_direction_glyph` (1 is up-left,
+/// 9 is down-right); 5 (no flow) and unknown codes have no step.
+fn flow_direction_offset(direction: i32) -> Option<(isize, isize)> {
+    match direction {
+        1 => Some((-1, -1)),
+        2 => Some((0, -1)),
+        3 => Some((1, -1)),
+        4 => Some((-1, 0)),
+        6 => Some((1, 0)),
+        7 => Some((-1, 1)),
+        8 => Some((0, 1)),
+        9 => Some((1, 1)),
+        _ => None,
+    }
+}
+
+/// Computes the downslope flow length from every cell to where its flow path ends.
+///
+/// Paths follow the D8 directions of `flowdir` (TOPAZ `FLOVEC` codes, see
+/// `flow_direction_offset`), adding `cellsize` for cardinal and
+/// `cellsize * √2` for diagonal steps. A path ends at a cell that has no flow
+/// (code 5 or unknown), drains into no-data, or drains off the grid; such
+/// cells have a length of 0. Should the directions form a loop, the path
+/// ends where it first revisits a cell.
+///
+/// No-data cells of `flowdir` are no-data (-9999) in the result.
+#[allow(dead_code)]
+pub fn flow_length(flowdir: &Raster<i32>, cellsize: f64) -> Raster<f64> {
+    flow_length_until(flowdir, cellsize, |_, _| false)
+}
+
+/// Computes the downslope flow length from every cell to where its flow path
+/// leaves the cell's zone of `zones` (e.g. a TOPAZ hillslope), including the
+/// step out of the zone. Paths that end inside the zone end as in `flow_length`.
+///
+/// `flowdir` and `zones` must be on the same grid.
+#[allow(dead_code)]
+pub fn flow_length_within(flowdir: &Raster<i32>, zones: &Raster<i32>, cellsize: f64) -> Raster<f64> {
+    flow_length_until(flowdir, cellsize, |indx, next| zones.data[indx] != zones.data[next])
+}
+
+/// Shared walk of `flow_length` and `flow_length_within`: a path also ends,
+/// after taking the step, where `leaves(indx, next)` holds.
+fn flow_length_until<F: Fn(usize, usize) -> bool>(flowdir: &Raster<i32>, cellsize: f64, leaves: F) -> Raster<f64> {
+    const NO_DATA: f64 = -9999.0;
+    let diagonal = cellsize * std::f64::consts::SQRT_2;
+    let is_valid = |indx: usize| flowdir.no_data != Some(flowdir.data[indx]);
+
+    // the next cell downstream and the length of the step to it
+    let downstream = |indx: usize| -> Option<(usize, f64)> {
+        let (dx, dy) = flow_direction_offset(flowdir.data[indx])?;
+        let (x, y) = flowdir.index_to_xy(indx);
+        let (nx, ny) = (x as isize + dx, y as isize + dy);
+        if nx < 0 || ny < 0 || nx as usize >= flowdir.width || ny as usize >= flowdir.height {
+            return None;
+        }
+        let next = flowdir.xy_to_index(nx as usize, ny as usize);
+        if !is_valid(next) {
+            return None;
+        }
+        Some((next, if dx != 0 && dy != 0 { diagonal } else { cellsize }))
+    };
+
+    let mut lengths: Vec<Option<f64>> = vec![None; flowdir.data.len()];
+    let mut on_path = vec![false; flowdir.data.len()];
+    let mut path: Vec<(usize, f64)> = Vec::new();
+
+    for start in 0..flowdir.data.len() {
+        if lengths[start].is_some() || !is_valid(start) {
+            continue;
+        }
+
+        // walk downstream until reaching a cell with a known length or the end of the path
+        let mut indx = start;
+        let mut length = loop {
+            if let Some(length) = lengths[indx] {
+                break length;
+            }
+            match downstream(indx) {
+                Some((next, step)) if leaves(indx, next) => {
+                    lengths[indx] = Some(step);
+                    break step;
+                },
+                Some((next, step)) if !on_path[next] => {
+                    on_path[indx] = true;
+                    path.push((indx, step));
+                    indx = next;
+                },
+                _ => {
+                    lengths[indx] = Some(0.0);
+                    break 0.0;
+                },
+            }
+        };
+
+        // unwind, accumulating the step lengths back up the path
+        while let Some((indx, step)) = path.pop() {
+            length += step;
+            lengths[indx] = Some(length);
+            on_path[indx] = false;
+        }
+    }
+
+    let data = lengths.into_iter().map(|length| length.unwrap_or(NO_DATA)).collect();
+    flowdir.with_data(data, flowdir.no_data.map(|_| NO_DATA))
+}
+
+/// Estimates the representative length and width of each hillslope of `subwta`,
+/// as used for WEPP hillslope geometry.
+///
+/// The length is the longest flow path within the hillslope (see
+/// `flow_length_within`), at least one cell; the width is the hillslope area
+/// divided by that length. Both are in map units. Channels (ids ending in 4),
+/// the background (`BACKGROUND_KEY`), and no-data cells are skipped, so a raw
+/// TOPAZ SUBWTA without a no-data value gives the same hillslopes.
+///
+/// # Errors
+///
+/// Returns `RasterError::Dimension` when the rasters are not aligned.
+#[allow(dead_code)]
+pub fn hillslope_geometry(subwta: &Raster<i32>, flowdir: &Raster<i32>) -> Result<HashMap<i32, (f64, f64)>, RasterError> {
+    subwta.check_aligned_with(flowdir)?;
+    let cellsize = subwta.cellsize;
+    let lengths = flow_length_within(flowdir, subwta, cellsize);
+
+    // per hillslope: longest flow path and cell count
+    let mut extents: HashMap<i32, (f64, usize)> = HashMap::new();
+    for (&id, &length) in subwta.data.iter().zip(lengths.data.iter()) {
+        if subwta.no_data == Some(id) || id == BACKGROUND_KEY || is_channel(id) {
+            continue;
+        }
+        let extent = extents.entry(id).or_insert((0.0, 0));
+        extent.0 = extent.0.max(length);
+        extent.1 += 1;
+    }
+
+    Ok(extents.into_iter()
+        .map(|(id, (length, count))| {
+            let length = length.max(cellsize);
+            let area = count as f64 * cellsize * cellsize;
+            (id, (length, area / length))
+        })
+        .collect())
+}
+
+/// Finds the outlet cell of each hillslope of `subwta`: the cell with the
+/// smallest distance to the channel in `discha` (TOPAZ `DISCHA`), i.e. the
+/// cell where the hillslope drains into its channel.
+///
+/// Returns the `(x, y)` pixel of each outlet; ties go to the first cell in
+/// row-major order. Channels (ids ending in 4), the background
+/// (`BACKGROUND_KEY`), no-data cells of `subwta`, and no-data cells of `discha`
+/// are skipped.
+///
+/// # Errors
+///
+/// Returns `RasterError::Dimension` when the rasters are not aligned.
+#[allow(dead_code)]
+pub fn hillslope_outlets(subwta: &Raster<i32>, discha: &Raster<f64>) -> Result<HashMap<i32, (usize, usize)>, RasterError> {
+    subwta.check_aligned_with(discha)?;
+
+    // per hillslope: smallest distance and its index
+    let mut outlets: HashMap<i32, (f64, usize)> = HashMap::new();
+    for (indx, (&id, &distance)) in subwta.data.iter().zip(discha.data.iter()).enumerate() {
+        if subwta.no_data == Some(id) || id == BACKGROUND_KEY || is_channel(id) || discha.is_no_data(distance) {
+            continue;
+        }
+        let outlet = outlets.entry(id).or_insert((distance, indx));
+        if distance < outlet.0 {
+            *outlet = (distance, indx);
+        }
+    }
+
+    Ok(outlets.into_iter()
+        .map(|(id, (_, indx))| (id, subwta.index_to_xy(indx)))
+        .collect())
 }
 
 pub trait ToIndices {
@@ -515,95 +2195,694 @@ impl<T> Raster<T> {
             sum_y += y as f64;
         }
 
-        let num_points = indices_vec.len() as f64;
-        let centroid_x = (sum_x / num_points).round() as usize;
-        let centroid_y = (sum_y / num_points).round() as usize;
-        
-        (centroid_x, centroid_y)
+        let num_points = indices_vec.len() as f64;
+        let centroid_x = (sum_x / num_points).round() as usize;
+        let centroid_y = (sum_y / num_points).round() as usize;
+        
+        (centroid_x, centroid_y)
+    }
+
+    /// Returns the `(x0, y0, width, height)` pixel window enclosing all of
+    /// `indices`, or `None` when `indices` is empty.
+    #[allow(dead_code)]
+    pub fn bounding_window<I: ToIndices>(&self, indices: &I) -> Option<(usize, usize, usize, usize)> {
+        let indices_vec = indices.to_indices();
+        if indices_vec.is_empty() {
+            return None;
+        }
+
+        let (mut x0, mut y0) = (usize::MAX, usize::MAX);
+        let (mut x1, mut y1) = (0, 0);
+        for &index in &indices_vec {
+            let (x, y) = self.index_to_xy(index);
+            x0 = x0.min(x);
+            y0 = y0.min(y);
+            x1 = x1.max(x);
+            y1 = y1.max(y);
+        }
+
+        Some((x0, y0, x1 - x0 + 1, y1 - y0 + 1))
+    }
+
+    #[allow(dead_code)]
+    pub fn px_to_lnglat(&self, px: (usize, usize)) -> Result<(f64, f64), RasterError> {
+        self.px_to_crs(px, "+proj=longlat +datum=WGS84 +no_defs")
+    }
+
+    /// Projects the corner of pixel `px` into the CRS described by `target_proj4`,
+    /// e.g. an equal-area CRS for area-correct work.
+    ///
+    /// Transformers are cached per thread and per (source, target) CRS, so repeated
+    /// calls with the same target only pay for the point conversion.
+    #[allow(dead_code)]
+    pub fn px_to_crs(&self, px: (usize, usize), target_proj4: &str) -> Result<(f64, f64), RasterError> {
+        let e: f64 = self.geo_transform[0] + px.0 as f64 * self.geo_transform[1] + px.1 as f64 * self.geo_transform[2];
+        let n: f64 = self.geo_transform[3] + px.0 as f64 * self.geo_transform[4] + px.1 as f64 * self.geo_transform[5];
+    
+        let proj4 = self.proj4.as_ref().ok_or_else(|| RasterError::InvalidArgument(
+            format!("{}: raster has no projection", self.path)))?;
+        transform_coords(e, n, proj4, target_proj4)
+    }
+    
+    
+}
+
+
+/// How `Raster::rasterize_points` fills cells from scattered points.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum PointInterpolation {
+    /// Value of the closest point.
+    Nearest,
+    /// Inverse distance weighted (power 2) mean of all points.
+    Idw,
+}
+
+impl FromStr for PointInterpolation {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "nearest" => Ok(PointInterpolation::Nearest),
+            "idw" => Ok(PointInterpolation::Idw),
+            _ => Err(()),
+        }
+    }
+}
+
+impl<T: Sync> Raster<T> {
+    /// Interpolates scattered `(easting, northing, value)` points onto the grid
+    /// of `self`, which only serves as the reference grid and projection.
+    ///
+    /// Points must be in the raster's CRS; points outside its extent are
+    /// ignored. Distances are measured from cell centers, and a cell center
+    /// that coincides with a point takes that point's value. When no point
+    /// falls within the extent every cell is no-data (-9999).
+    #[allow(dead_code)]
+    pub fn rasterize_points(&self, points: &[(f64, f64, f64)], method: PointInterpolation) -> Raster<f64> {
+        const NO_DATA: f64 = -9999.0;
+
+        let inside: Vec<(f64, f64, f64)> = points.iter()
+            .filter(|&&(e, n, _)| {
+                let (px, py) = self.map_to_px(e, n);
+                px >= 0.0 && py >= 0.0 && px <= self.width as f64 && py <= self.height as f64
+            })
+            .cloned()
+            .collect();
+
+        let data: Vec<f64> = (0..self.width * self.height).into_par_iter()
+            .map(|indx| {
+                let (x, y) = self.index_to_xy(indx);
+                let (e, n) = self.px_to_map(x as f64 + 0.5, y as f64 + 0.5);
+                let dist2 = |&(pe, pn, _): &(f64, f64, f64)| (pe - e).powi(2) + (pn - n).powi(2);
+
+                match method {
+                    PointInterpolation::Nearest => inside.iter()
+                        .min_by(|a, b| dist2(a).partial_cmp(&dist2(b)).unwrap_or(std::cmp::Ordering::Equal))
+                        .map(|&(_, _, value)| value)
+                        .unwrap_or(NO_DATA),
+                    PointInterpolation::Idw => {
+                        let (mut weighted, mut weights) = (0.0, 0.0);
+                        for point in &inside {
+                            let d2 = dist2(point);
+                            if d2 == 0.0 {
+                                return point.2;
+                            }
+                            weighted += point.2 / d2;
+                            weights += 1.0 / d2;
+                        }
+                        if weights > 0.0 { weighted / weights } else { NO_DATA }
+                    },
+                }
+            })
+            .collect();
+
+        self.with_data(data, Some(NO_DATA))
+    }
+}
+
+
+/// How `Raster::mosaic` resolves cells covered by more than one input.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum MosaicMethod {
+    First,
+    Last,
+    Max,
+    Mean,
+}
+
+impl FromStr for MosaicMethod {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "first" => Ok(MosaicMethod::First),
+            "last" => Ok(MosaicMethod::Last),
+            "max" => Ok(MosaicMethod::Max),
+            "mean" => Ok(MosaicMethod::Mean),
+            _ => Err(()),
+        }
+    }
+}
+
+impl Raster<f64> {
+    /// Blends two aligned rasters cell-wise as `alpha * a + (1 - alpha) * b`,
+    /// the raster analog of the watershed/hill bias in `cli_revision`.
+    ///
+    /// A cell that is no-data in either input is no-data in the result, which
+    /// takes the no-data value of `a`, else of `b`, else -9999.
+    ///
+    /// # Errors
+    ///
+    /// Returns `RasterError::InvalidArgument` when `alpha` is outside `[0, 1]` and
+    /// `RasterError::Dimension` when the rasters are not aligned (see `is_aligned_with`).
+    #[allow(dead_code)]
+    pub fn blend(a: &Raster<f64>, b: &Raster<f64>, alpha: f64) -> Result<Raster<f64>, RasterError> {
+        if !(0.0..=1.0).contains(&alpha) {
+            return Err(RasterError::InvalidArgument(format!("alpha {} is outside [0, 1]", alpha)));
+        }
+        a.check_aligned_with(b)?;
+
+        let no_data = a.no_data.or(b.no_data).unwrap_or(-9999.0);
+        let data: Vec<f64> = a.data.iter().zip(b.data.iter())
+            .map(|(&va, &vb)| {
+                if a.is_no_data(va) || b.is_no_data(vb) {
+                    no_data
+                } else {
+                    alpha * va + (1.0 - alpha) * vb
+                }
+            })
+            .collect();
+
+        Ok(a.with_data(data, Some(no_data)))
+    }
+
+    /// Subtracts two aligned rasters cell-wise as `a - b`, e.g. a treatment
+    /// scenario's soil loss minus the baseline's.
+    ///
+    /// Differences whose magnitude does not exceed `threshold` are set to 0 so
+    /// floating-point noise is not reported as change. A cell that is no-data
+    /// in either input is no-data in the result, which takes the no-data value
+    /// of `a`, else of `b`, else -9999. Also returns the number of cells with a
+    /// significant (nonzero) difference.
+    ///
+    /// # Errors
+    ///
+    /// Returns `RasterError::InvalidArgument` when `threshold` is negative or NaN
+    /// and `RasterError::Dimension` when the rasters are not aligned (see
+    /// `is_aligned_with`).
+    #[allow(dead_code)]
+    pub fn difference(a: &Raster<f64>, b: &Raster<f64>, threshold: f64) -> Result<(Raster<f64>, usize), RasterError> {
+        if threshold.is_nan() || threshold < 0.0 {
+            return Err(RasterError::InvalidArgument(format!("threshold {} is not a non-negative number", threshold)));
+        }
+        a.check_aligned_with(b)?;
+
+        let no_data = a.no_data.or(b.no_data).unwrap_or(-9999.0);
+        let mut significant = 0;
+        let data: Vec<f64> = a.data.iter().zip(b.data.iter())
+            .map(|(&va, &vb)| {
+                if a.is_no_data(va) || b.is_no_data(vb) {
+                    return no_data;
+                }
+                let diff = va - vb;
+                if diff.abs() > threshold {
+                    significant += 1;
+                    diff
+                } else {
+                    0.0
+                }
+            })
+            .collect();
+
+        Ok((a.with_data(data, Some(no_data)), significant))
+    }
+
+    /// Takes `a` where `mask` is nonzero and `b` elsewhere, e.g. to splice a
+    /// treated-hillslope surface into a baseline surface.
+    ///
+    /// A cell is no-data in the result when the mask is no-data there or the
+    /// selected input is. The result takes the no-data value of `a`, else of
+    /// `b`, else -9999.
+    ///
+    /// # Errors
+    ///
+    /// Returns `RasterError::Dimension` when the three rasters are not aligned
+    /// (see `is_aligned_with`).
+    #[allow(dead_code)]
+    pub fn select(mask: &Raster<i32>, a: &Raster<f64>, b: &Raster<f64>) -> Result<Raster<f64>, RasterError> {
+        mask.check_aligned_with(a)?;
+        mask.check_aligned_with(b)?;
+
+        let no_data = a.no_data.or(b.no_data).unwrap_or(-9999.0);
+        let data: Vec<f64> = mask.data.iter().zip(a.data.iter().zip(b.data.iter()))
+            .map(|(&m, (&va, &vb))| {
+                if mask.is_no_data(m) {
+                    no_data
+                } else if m != 0 {
+                    if a.is_no_data(va) { no_data } else { va }
+                } else if b.is_no_data(vb) {
+                    no_data
+                } else {
+                    vb
+                }
+            })
+            .collect();
+
+        Ok(a.with_data(data, Some(no_data)))
+    }
+
+    /// Stitches aligned rasters into one grid covering their union extent.
+    ///
+    /// All inputs must be north-up, share the projection and cell size, and
+    /// be offset from each other by whole cells. Valid cells are placed by
+    /// their geo_transform and overlaps are resolved with `method`. Cells not
+    /// covered by any valid input take the first raster's no-data value
+    /// (-9999 when it has none).
+    #[allow(dead_code)]
+    pub fn mosaic(rasters: &[Raster<f64>], method: MosaicMethod) -> Result<Raster<f64>, RasterError> {
+        let first = rasters.first()
+            .ok_or_else(|| RasterError::InvalidArgument("mosaic requires at least one raster".to_string()))?;
+        let (dx, dy) = (first.geo_transform[1], first.geo_transform[5]);
+
+        for raster in rasters {
+            if raster.geo_transform[2] != 0.0 || raster.geo_transform[4] != 0.0 {
+                return Err(RasterError::InvalidArgument(format!("{}: rotated rasters cannot be mosaicked", raster.path)));
+            }
+            if raster.proj4 != first.proj4 {
+                return Err(RasterError::Dimension(format!(
+                    "{}: projection {:?} differs from {:?}", raster.path, raster.proj4, first.proj4)));
+            }
+            if raster.geo_transform[1] != dx || raster.geo_transform[5] != dy {
+                return Err(RasterError::Dimension(format!(
+                    "{}: cell size {} x {} differs from {} x {}",
+                    raster.path, raster.geo_transform[1], raster.geo_transform[5], dx, dy)));
+            }
+        }
+
+        let min_e = rasters.iter().map(|r| r.geo_transform[0]).fold(f64::INFINITY, f64::min);
+        let max_e = rasters.iter().map(|r| r.geo_transform[0] + r.width as f64 * dx).fold(f64::NEG_INFINITY, f64::max);
+        let max_n = rasters.iter().map(|r| r.geo_transform[3]).fold(f64::NEG_INFINITY, f64::max);
+        let min_n = rasters.iter().map(|r| r.geo_transform[3] + r.height as f64 * dy).fold(f64::INFINITY, f64::min);
+
+        let width = ((max_e - min_e) / dx).round() as usize;
+        let height = ((min_n - max_n) / dy).round() as usize;
+        let no_data = first.no_data.unwrap_or(-9999.0);
+
+        let mut data = vec![no_data; width * height];
+        let mut sums = vec![0.0; if method == MosaicMethod::Mean { width * height } else { 0 }];
+        let mut counts = vec![0u32; width * height];
+
+        for raster in rasters {
+            let x_off = (raster.geo_transform[0] - min_e) / dx;
+            let y_off = (raster.geo_transform[3] - max_n) / dy;
+            if (x_off - x_off.round()).abs() > 1e-3 || (y_off - y_off.round()).abs() > 1e-3 {
+                return Err(RasterError::Dimension(format!(
+                    "{}: origin is not aligned to the mosaic grid", raster.path)));
+            }
+            let (x_off, y_off) = (x_off.round() as usize, y_off.round() as usize);
+
+            for y in 0..raster.height {
+                for x in 0..raster.width {
+                    let value = raster.data[raster.xy_to_index(x, y)];
+                    if raster.is_no_data(value) {
+                        continue;
+                    }
+
+                    let indx = (y + y_off) * width + x + x_off;
+                    match method {
+                        MosaicMethod::First => if counts[indx] == 0 { data[indx] = value },
+                        MosaicMethod::Last => data[indx] = value,
+                        MosaicMethod::Max => if counts[indx] == 0 || value > data[indx] { data[indx] = value },
+                        MosaicMethod::Mean => sums[indx] += value,
+                    }
+                    counts[indx] += 1;
+                }
+            }
+        }
+
+        if method == MosaicMethod::Mean {
+            for indx in 0..data.len() {
+                if counts[indx] > 0 {
+                    data[indx] = sums[indx] / counts[indx] as f64;
+                }
+            }
+        }
+
+        Ok(Raster::new(
+            width,
+            height,
+            first.cellsize,
+            data,
+            Some(no_data),
+            [min_e, dx, 0.0, max_n, 0.0, dy],
+            first.proj4.clone(),
+            String::new(),
+            "mosaic".to_string(),
+            MapType::OTHER,
+        ))
+    }
+}
+
+impl Raster<f64> {
+
+    #[allow(dead_code)]
+    pub fn determine_aspect<I: ToIndices>(&self, indices: &I) -> f64 {
+        assert!(self.map_type == MapType::TASPEC, 
+            "Raster must be TASPEC type to determine aspect");
+    
+        let indices_vec = indices.to_indices();
+    
+        let mut rad_aspects: Vec<f64> = Vec::new();
+        for &index in &indices_vec {
+            let deg_aspect = self.data[index];
+            rad_aspects.push(deg_aspect.to_radians());
+        }
+        let mut aspect = circmean(rad_aspects.as_slice()).to_degrees();
+
+        if aspect < 0.0 {
+            aspect += 360.0;
+        }
+        aspect
+    }
+}
+
+
+impl<T: ToF64> Raster<T> {
+    /// Counts the valid cells of the raster into `bins` equal-width bins.
+    ///
+    /// When `range` is `None` the bins span the minimum and maximum of the
+    /// valid cells. As with numpy, every bin is half-open except the last,
+    /// which also includes its right edge, and values outside the range are
+    /// not counted.
+    ///
+    /// # Returns
+    ///
+    /// A tuple of the `bins + 1` bin edges and the `bins` counts.
+    #[allow(dead_code)]
+    pub fn histogram(&self, bins: usize, range: Option<(f64, f64)>) -> (Vec<f64>, Vec<u64>) {
+        assert!(bins > 0, "Histogram requires at least one bin");
+
+        let no_data: Option<f64> = self.no_data.as_ref().map(|v| v.to_f64());
+        let valid = || self.data.iter()
+            .map(|v| v.to_f64())
+            .filter(move |&v| !v.is_nan() && (no_data.is_none() || v != no_data.unwrap()));
+
+        let (mut lo, mut hi) = match range {
+            Some(r) => r,
+            None => {
+                let lo = valid().fold(f64::INFINITY, f64::min);
+                let hi = valid().fold(f64::NEG_INFINITY, f64::max);
+                if lo > hi { (0.0, 1.0) } else { (lo, hi) }
+            }
+        };
+
+        // match numpy and widen a degenerate range so every value lands in a bin
+        if lo == hi {
+            lo -= 0.5;
+            hi += 0.5;
+        }
+
+        let bin_width = (hi - lo) / bins as f64;
+        let edges: Vec<f64> = (0..=bins).map(|i| lo + i as f64 * bin_width).collect();
+
+        let mut counts = vec![0u64; bins];
+        for value in valid() {
+            if value < lo || value > hi {
+                continue;
+            }
+            let bin = (((value - lo) / bin_width) as usize).min(bins - 1);
+            counts[bin] += 1;
+        }
+
+        (edges, counts)
+    }
+
+    /// Counts the valid cells with `lo <= value <= hi`; both bounds are inclusive.
+    ///
+    /// No-data and NaN cells are never counted.
+    #[allow(dead_code)]
+    pub fn count_in_range(&self, lo: f64, hi: f64) -> usize {
+        let no_data: Option<f64> = self.no_data.as_ref().map(|v| v.to_f64());
+        self.data.iter()
+            .map(|v| v.to_f64())
+            .filter(|&v| Some(v) != no_data && lo <= v && v <= hi)
+            .count()
+    }
+
+    /// Sums the valid cells; no-data and NaN cells are skipped.
+    ///
+    /// Meant for extensive quantities such as per-cell soil loss, where the
+    /// total over the grid is the basin total.
+    #[allow(dead_code)]
+    pub fn sum(&self) -> f64 {
+        let no_data: Option<f64> = self.no_data.as_ref().map(|v| v.to_f64());
+        self.data.iter()
+            .map(|v| v.to_f64())
+            .filter(|&v| !v.is_nan() && Some(v) != no_data)
+            .sum()
+    }
+
+    /// Counts the valid cells that are not zero; no-data and NaN cells are never counted.
+    #[allow(dead_code)]
+    pub fn count_nonzero(&self) -> usize {
+        let no_data: Option<f64> = self.no_data.as_ref().map(|v| v.to_f64());
+        self.data.iter()
+            .map(|v| v.to_f64())
+            .filter(|&v| !v.is_nan() && Some(v) != no_data && v != 0.0)
+            .count()
+    }
+}
+
+/// Number of cells each rayon task folds when computing band statistics.
+const STATS_CHUNK_SIZE: usize = 1 << 16;
+
+/// Running min/max/sum/sum-of-squares/count over a chunk of cells.
+#[derive(Debug, Clone, Copy)]
+struct PartialStatistics {
+    min: f64,
+    max: f64,
+    sum: f64,
+    sum_of_squares: f64,
+    count: usize,
+}
+
+impl PartialStatistics {
+    fn new() -> PartialStatistics {
+        PartialStatistics {
+            min: f64::INFINITY,
+            max: f64::NEG_INFINITY,
+            sum: 0.0,
+            sum_of_squares: 0.0,
+            count: 0,
+        }
+    }
+
+    fn merge(self, other: PartialStatistics) -> PartialStatistics {
+        PartialStatistics {
+            min: self.min.min(other.min),
+            max: self.max.max(other.max),
+            sum: self.sum + other.sum,
+            sum_of_squares: self.sum_of_squares + other.sum_of_squares,
+            count: self.count + other.count,
+        }
+    }
+}
+
+impl<T: ToF64 + Sync> Raster<T> { 
+    /// Computes band statistics by folding chunks of the data in parallel
+    /// and merging the partial results. No-data and NaN cells are not valid
+    /// and are left out of every field.
+    ///
+    /// The median and the 2nd/98th percentiles sort a copy of the valid values,
+    /// so this costs O(n log n) time and O(n) memory on top of the linear pass;
+    /// keep it off hot paths.
+    #[allow(dead_code)]
+    pub fn compute_band_statistics(&self) -> BandStatistics {
+        let no_data: Option<f64> = self.no_data.as_ref().map(|v| v.to_f64());
+        // NaN cells are skipped whatever the no-data value, so every field agrees
+        let is_valid = |v: f64| !is_no_data_value(v, no_data) && !v.is_nan();
+
+        let stats = self.data
+            .par_chunks(STATS_CHUNK_SIZE)
+            .map(|chunk| {
+                let mut partial = PartialStatistics::new();
+                for value in chunk {
+                    let value_f64 = value.to_f64();
+                    if !is_valid(value_f64) {
+                        continue;
+                    }
+
+                    if value_f64 < partial.min {
+                        partial.min = value_f64;
+                    }
+
+                    if value_f64 > partial.max {
+                        partial.max = value_f64;
+                    }
+
+                    partial.sum += value_f64;
+                    partial.sum_of_squares += value_f64 * value_f64;
+                    partial.count += 1;
+                }
+                partial
+            })
+            .reduce(PartialStatistics::new, PartialStatistics::merge);
+
+        let mean = stats.sum / stats.count as f64;
+        let variance = (stats.sum_of_squares / stats.count as f64) - (mean * mean);
+        let std_dev = variance.sqrt();
+        let valid_percent = 100.0 * (stats.count as f64) / (self.width * self.height) as f64;
+
+        let mut valid: Vec<f64> = self.data.iter()
+            .map(|v| v.to_f64())
+            .filter(|&v| is_valid(v))
+            .collect();
+        valid.par_sort_unstable_by(|a, b| a.total_cmp(b));
+        let pct = |p: f64| if valid.is_empty() { f64::NAN } else { percentile(&valid, p) };
+
+        BandStatistics {
+            minimum: stats.min,
+            maximum: stats.max,
+            mean,
+            std_dev,
+            valid_percent,
+            median: pct(50.0),
+            p2: pct(2.0),
+            p98: pct(98.0),
+        }
     }
 
+    /// Computes the statistics of each band of a stack (see `read_all_bands`),
+    /// applying each band's own no-data value.
     #[allow(dead_code)]
-    pub fn px_to_lnglat(&self, px: (usize, usize)) -> (f64, f64) {
-        let e: f64 = self.geo_transform[0] + px.0 as f64 * self.geo_transform[1] + px.1 as f64 * self.geo_transform[2];
-        let n: f64 = self.geo_transform[3] + px.0 as f64 * self.geo_transform[4] + px.1 as f64 * self.geo_transform[5];
-    
-        let (lng, lat) = transform_coords(e, n, &self.proj4.as_ref().unwrap(), "+proj=longlat +datum=WGS84 +no_defs").unwrap();
-        (lng, lat)
+    pub fn band_statistics_all(bands: &[Raster<T>]) -> Vec<BandStatistics> {
+        bands.iter().map(|band| band.compute_band_statistics()).collect()
     }
-    
-    
 }
 
+/// Per-type rendering hooks for `display_grid`, resolved statically so the
+/// renderers need no runtime downcasts.
+pub trait CellDisplay {
+    /// ANSI color code for a SUBWTA cell, or `None` to print it uncolored.
+    fn subwta_color(&self) -> Option<u8> {
+        None
+    }
 
-impl Raster<f64> {
+    /// Arrow glyph for a FLOVEC direction code, or `None` if the value is not one.
+    fn flow_glyph(&self) -> Option<&'static str> {
+        None
+    }
+}
 
-    #[allow(dead_code)]
-    pub fn determine_aspect<I: ToIndices>(&self, indices: &I) -> f64 {
-        assert!(self.map_type == MapType::TASPEC, 
-            "Raster must be TASPEC type to determine aspect");
-    
-        let indices_vec = indices.to_indices();
-    
-        let mut rad_aspects: Vec<f64> = Vec::new();
-        for &index in &indices_vec {
-            let deg_aspect = self.data[index];
-            rad_aspects.push(deg_aspect.to_radians());
-        }
-        let mut aspect = circmean(rad_aspects.as_slice()).to_degrees();
+fn subwta_color_code(topaz_id: i64) -> u8 {
+    match topaz_id % 10 {
+        0 => 31, // Red
+        1 => 33, // Yellow
+        2 => 32, // Green
+        3 => 35, // Magenta
+        4 => 34, // Blue
+        _ => 37, // White (for remainders 5 through 9)
+    }
+}
 
-        if aspect < 0.0 {
-            aspect += 360.0;
-        }
-        aspect
+/// Returns the RGB color of a TOPAZ id in rendered images, matching the
+/// terminal colors of `display_grid` (channels blue, hillslopes by side).
+pub fn subwta_rgb(topaz_id: i32) -> [u8; 3] {
+    match subwta_color_code(topaz_id as i64) {
+        31 => [220, 50, 47],   // Red
+        33 => [230, 190, 30],  // Yellow
+        32 => [80, 170, 60],   // Green
+        35 => [180, 70, 170],  // Magenta
+        34 => [40, 100, 210],  // Blue
+        _ => [235, 235, 235],  // White
     }
 }
 
+fn flow_direction_glyph(direction: i64) -> Option<&'static str> {
+    match direction {
+        1 => Some("↖"),
+        2 => Some("↑"),
+        3 => Some("↗"),
+        4 => Some("←"),
+        5 => Some("-"),
+        6 => Some("→"),
+        7 => Some("↙"),
+        8 => Some("↓"),
+        9 => Some("↘"),
+        _ => None,
+    }
+}
 
-impl<T: ToF64> Raster<T> { 
-    #[allow(dead_code)]
-    pub fn compute_band_statistics(&self) -> BandStatistics {
-        // Initialize stats variables. Normally, you'd get these values from your raster data.
-        let mut min = f64::INFINITY;
-        let mut max = f64::NEG_INFINITY;
-        let mut sum = 0.0;
-        let mut count = 0;
-        let mut sum_of_squares = 0.0;
+impl CellDisplay for i32 {
+    fn subwta_color(&self) -> Option<u8> {
+        Some(subwta_color_code(*self as i64))
+    }
 
-        let no_data: Option<f64> = self.no_data.as_ref().map(|v| v.to_f64());
+    fn flow_glyph(&self) -> Option<&'static str> {
+        flow_direction_glyph(*self as i64)
+    }
+}
 
-        for &value_f64 in &self.convert_data_to_f64() {
+impl CellDisplay for u32 {
+    fn subwta_color(&self) -> Option<u8> {
+        Some(subwta_color_code(*self as i64))
+    }
 
-            if value_f64 < min {
-                min = value_f64;
-            }
+    fn flow_glyph(&self) -> Option<&'static str> {
+        flow_direction_glyph(*self as i64)
+    }
+}
 
-            if value_f64 > max {
-                max = value_f64;
-            }
+impl CellDisplay for u16 {
+    fn subwta_color(&self) -> Option<u8> {
+        Some(subwta_color_code(*self as i64))
+    }
+
+    fn flow_glyph(&self) -> Option<&'static str> {
+        flow_direction_glyph(*self as i64)
+    }
+}
+
+impl CellDisplay for f32 {}
 
-            sum += value_f64;
-            sum_of_squares += value_f64 * value_f64;
-            if no_data.is_none() || value_f64 != no_data.unwrap() {
-                count += 1;
+impl CellDisplay for f64 {}
+
+impl Raster<i32> {
+    /// Renders a categorical raster such as SUBWTA as an RGBA PNG for visual QA,
+    /// coloring each id with `subwta_rgb`; no-data cells are transparent.
+    ///
+    /// Returns the legend: each rendered id with its color, in ascending order.
+    #[allow(dead_code)]
+    pub fn render_categorical_png(&self, path: &str) -> Result<Vec<(i32, [u8; 3])>, RasterError> {
+        let mut channels: Vec<Vec<u8>> = vec![vec![0; self.data.len()]; 4];
+        for (indx, &value) in self.data.iter().enumerate() {
+            if self.is_no_data(value) {
+                continue;
             }
+            let [r, g, b] = subwta_rgb(value);
+            channels[0][indx] = r;
+            channels[1][indx] = g;
+            channels[2][indx] = b;
+            channels[3][indx] = 255;
         }
 
-        let mean = sum / count as f64;
-        let variance = (sum_of_squares / count as f64) - (mean * mean);
-        let std_dev = variance.sqrt();
-        let valid_percent = 100.0 * (count as f64) / (self.width * self.height) as f64;
-
-        BandStatistics {
-            minimum: min,
-            maximum: max,
-            mean,
-            std_dev,
-            valid_percent,
+        // the PNG driver only supports CreateCopy
+        let dataset = gdal::Driver::get("MEM")?
+            .create_with_band_type::<u8, &str>("", self.width as isize, self.height as isize, 4)?;
+        for (band_indx, channel) in channels.into_iter().enumerate() {
+            let mut band = dataset.rasterband(band_indx as isize + 1)?;
+            let buffer = Buffer::new((self.width, self.height), channel);
+            band.write((0, 0), (self.width, self.height), &buffer)?;
         }
+        dataset.create_copy(&gdal::Driver::get("PNG")?, path, &[])?;
+
+        Ok(self.sorted_unique_values().into_iter()
+            .map(|id| (id, subwta_rgb(id)))
+            .collect())
     }
 }
 
-impl<T: std::fmt::Display + std::cmp::PartialEq + Any> Raster<T> {
+impl<T: std::fmt::Display + std::cmp::PartialEq + CellDisplay> Raster<T> {
     
     #[allow(dead_code)]
     pub fn display_grid(&self) {
@@ -623,20 +2902,11 @@ impl<T: std::fmt::Display + std::cmp::PartialEq + Any> Raster<T> {
             for x in 0..self.width {
                 let index = y * self.width + x;
                 let value = &self.data[index];
-                if no_data.is_none() || value != no_data.unwrap() {
-                    if let Some(int_value) = (value as &dyn Any).downcast_ref::<i32>() {
-                        let remainder = *int_value % 10;
-                        let color_code = match remainder {
-                            0 => 31, // Red
-                            1 => 33, // Yellow
-                            2 => 32, // Green
-                            3 => 35, // Magenta
-                            4 => 34, // Blue
-                            _ => 37, // White (for remainders 5 through 9)
-                        };
-                        print!("\x1b[{}m{:<4}\x1b[0m ", color_code, int_value);
+                if !is_no_data_value(value, no_data) {
+                    if let Some(color_code) = value.subwta_color() {
+                        print!("\x1b[{}m{:<4}\x1b[0m ", color_code, value);
                     } else {
-                        // Just print the value normally if T isn't i32
+                        // Just print the value normally if T isn't an integer type
                         print!("{:<4} ", value);
                     }
                 } else {
@@ -654,7 +2924,7 @@ impl<T: std::fmt::Display + std::cmp::PartialEq + Any> Raster<T> {
             for x in 0..self.width {
                 let index = y * self.width + x;
                 let value = &self.data[index];
-                if no_data.is_none() || value != no_data.unwrap() {
+                if !is_no_data_value(value, no_data) {
                     print!("\x1b[{}m{:<1}\x1b[0m ", 35, value);
                 } else {
                     print!("{:<1} ", ".");
@@ -671,7 +2941,7 @@ impl<T: std::fmt::Display + std::cmp::PartialEq + Any> Raster<T> {
             for x in 0..self.width {
                 let index = y * self.width + x;
                 let value = &self.data[index];
-                if no_data.is_none() || value != no_data.unwrap() {
+                if !is_no_data_value(value, no_data) {
                     print!("\x1b[{}m{:<1}\x1b[0m ", 34, value);
                 } else {
                     print!("{:<1} ", ".");
@@ -688,19 +2958,9 @@ impl<T: std::fmt::Display + std::cmp::PartialEq + Any> Raster<T> {
             for x in 0..self.width {
                 let index = y * self.width + x;
                 let value = &self.data[index];
-                if no_data.is_none() || value != no_data.unwrap() {
-                    let character = match (value as &dyn Any).downcast_ref::<i32>() {
-                        Some(1) => "↖",
-                        Some(2) => "↑",
-                        Some(3) => "↗",
-                        Some(4) => "←",
-                        Some(5) => "-",
-                        Some(6) => "→",
-                        Some(7) => "↙",
-                        Some(8) => "↓",
-                        Some(9) => "↘",
-                        _ => " ",  // Default for non-matched values
-                    };
+                if !is_no_data_value(value, no_data) {
+                    // Default for non-matched values
+                    let character = value.flow_glyph().unwrap_or(" ");
                     print!("{:<1} ", character);
                 } else {
                     print!("{:<1} ", ".");
@@ -717,7 +2977,7 @@ impl<T: std::fmt::Display + std::cmp::PartialEq + Any> Raster<T> {
             for x in 0..self.width {
                 let index = y * self.width + x;
                 let value = &self.data[index];
-                if no_data.is_none() || value != no_data.unwrap() {
+                if !is_no_data_value(value, no_data) {
                     print!("{:<4} ", value);
                 } else {
                     print!("{:<4} ", ".");
@@ -729,6 +2989,32 @@ impl<T: std::fmt::Display + std::cmp::PartialEq + Any> Raster<T> {
 }
 
 
+impl<T: std::fmt::Display + std::cmp::PartialEq> Raster<T> {
+    /// Renders the grid as plain text without ANSI escapes, one line per row
+    /// with no-data cells shown as `.`. Suitable for logs and text files.
+    #[allow(dead_code)]
+    pub fn format_grid_plain(&self) -> String {
+        let no_data = self.no_data.as_ref();
+        let mut out = String::new();
+        for y in 0..self.height {
+            let mut line = String::new();
+            for x in 0..self.width {
+                let index = y * self.width + x;
+                let value = &self.data[index];
+                if !is_no_data_value(value, no_data) {
+                    line.push_str(&format!("{:<4} ", value));
+                } else {
+                    line.push_str(&format!("{:<4} ", "."));
+                }
+            }
+            out.push_str(line.trim_end());
+            out.push('\n');
+        }
+        out
+    }
+}
+
+
 impl<T: fmt::Display> fmt::Display for Raster<T> {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         let no_data_str = match &self.no_data {
@@ -749,11 +3035,17 @@ impl<T: fmt::Display> fmt::Display for Raster<T> {
 
 #[derive(Debug)]
 pub struct BandStatistics {
-    minimum: f64,
-    maximum: f64,
-    mean: f64,
-    std_dev: f64,
-    valid_percent: f64,
+    pub minimum: f64,
+    pub maximum: f64,
+    pub mean: f64,
+    pub std_dev: f64,
+    /// Percentage of the cells that are not no-data.
+    pub valid_percent: f64,
+    pub median: f64,
+    /// 2nd percentile, the usual lower bound of a display contrast stretch.
+    pub p2: f64,
+    /// 98th percentile, the usual upper bound of a display contrast stretch.
+    pub p98: f64,
 }
 
 
@@ -761,8 +3053,9 @@ impl fmt::Display for BandStatistics {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         write!(
             f,
-            "Min: {}\nMax: {}\nMean: {}\nStd Dev: {}\nValid Percent: {}",
-            self.minimum, self.maximum, self.mean, self.std_dev, self.valid_percent
+            "Min: {}\nMax: {}\nMean: {}\nStd Dev: {}\nValid Percent: {}\nMedian: {}\nP2: {}\nP98: {}",
+            self.minimum, self.maximum, self.mean, self.std_dev, self.valid_percent,
+            self.median, self.p2, self.p98
         )
     }
 }
@@ -771,7 +3064,7 @@ impl fmt::Display for BandStatistics {
 mod tests {
     extern crate maplit;
 
-    use super::Raster;  // Assuming Raster is in the parent module
+    use super::{geotiff_creation_options, Compression, Raster, RasterBuilder, RasterCube, MapType, MosaicMethod, TryFromF64, RasterError, aspect_class, flow_length, flow_length_within, hillslope_geometry, hillslope_outlets, PointInterpolation, ResampleMethod, AlignmentReport, driver_for_path, gdal_type_size, subwta_rgb};  // Assuming Raster is in the parent module
     use std::collections::HashSet;
     use maplit::hashset;
 
@@ -786,6 +3079,43 @@ mod tests {
         assert_eq!(unique_vals, expected);
     }
 
+    #[test]
+    fn test_sorted_unique_values() {
+        let raster = Raster::new(3, 2, 1.0, vec![32, 22, 0, 22, 24, 31], Some(0),
+            [0.0, 1.0, 0.0, 2.0, 0.0, -1.0], None, "".to_string(), "SUBWTA".to_string(), MapType::SUBWTA);
+        assert_eq!(raster.sorted_unique_values(), vec![22, 24, 31, 32]);
+    }
+
+    #[test]
+    fn test_geotiff_creation_options() {
+        assert!(geotiff_creation_options(Compression::None, false, true).is_empty());
+        assert_eq!(geotiff_creation_options(Compression::Deflate, false, true),
+            vec![("COMPRESS", "DEFLATE"), ("PREDICTOR", "3")]);
+        assert_eq!(geotiff_creation_options(Compression::Zstd, true, false),
+            vec![("COMPRESS", "ZSTD"), ("PREDICTOR", "2"), ("TILED", "YES"), ("BLOCKXSIZE", "256"), ("BLOCKYSIZE", "256")]);
+        assert_eq!("lzw".parse::<Compression>(), Ok(Compression::Lzw));
+        assert!("jpeg".parse::<Compression>().is_err());
+
+        let raster = Raster::new(1, 1, 1.0, vec![1.0], None,
+            [0.0, 1.0, 0.0, 1.0, 0.0, -1.0], None, "".to_string(), "grid".to_string(), MapType::OTHER);
+        assert!(matches!(raster.write_compressed("out.tif", "jpeg", false), Err(RasterError::InvalidArgument(_))));
+    }
+
+    #[test]
+    fn test_group_indices() {
+        let raster = Raster::new(4, 2, 1.0, vec![32, 22, 0, 22, 24, 31, 22, 0], Some(0),
+            [0.0, 1.0, 0.0, 2.0, 0.0, -1.0], None, "".to_string(), "SUBWTA".to_string(), MapType::SUBWTA);
+        let groups = raster.group_indices();
+
+        assert_eq!(groups.len(), 4);
+        assert!(!groups.contains_key(&0));
+        assert_eq!(groups[&22], vec![1, 3, 6]);
+        for value in raster.unique_values() {
+            let expected = raster.indices_of(value);
+            assert_eq!(groups[&value].iter().cloned().collect::<HashSet<usize>>(), expected);
+        }
+    }
+
     #[test]
     fn test_indices_of() {
         let path = "tests/fixtures/watershed_abstraction/litigious-sagacity/dem/topaz/SUBWTA.ARC";
@@ -798,6 +3128,27 @@ mod tests {
     }
 
 
+    #[test]
+    fn test_write_round_trip_preserves_georeferencing() {
+        let path = "../raster_characteristics/tests/fixtures/input_files/SUBWTA.ARC";
+        let raster = Raster::<i32>::read(&path).unwrap();
+        assert!(raster.proj4.is_some());
+
+        let out_path = std::env::temp_dir().join("raster_round_trip_SUBWTA.tif");
+        let out_path = out_path.to_str().unwrap();
+        raster.write(out_path).unwrap();
+        let written = Raster::<i32>::read(out_path).unwrap();
+        std::fs::remove_file(out_path).ok();
+
+        for (original, round_tripped) in raster.geo_transform.iter().zip(written.geo_transform.iter()) {
+            assert!((original - round_tripped).abs() < 1e-9,
+                "geo_transform changed: {:?} vs {:?}", raster.geo_transform, written.geo_transform);
+        }
+        assert_eq!(written.proj4, raster.proj4);
+        assert_eq!(written.no_data, raster.no_data);
+        assert_eq!(written.data, raster.data);
+    }
+
     #[test]
     fn test_mask() {
         let path = "tests/fixtures/watershed_abstraction/small/SUBWTA.ARC";
@@ -808,4 +3159,583 @@ mod tests {
 
         assert_eq!(indices, expected);
     }
+
+
+    fn write_fixture(name: &str, contents: &str) -> String {
+        let path = std::env::temp_dir().join(name);
+        std::fs::write(&path, contents).unwrap();
+        path.to_str().unwrap().to_string()
+    }
+
+    #[test]
+    fn test_from_ascii_grid_scientific_notation() {
+        let path = write_fixture("wepppyo3_sci.asc",
+            "ncols 3\nnrows 2\nxllcorner 100.0\nyllcorner 200.0\ncellsize 10.0\nNODATA_value -9999\n\
+             1.0e3 2.5E-1 -9999\n4 5.0 6e0\n");
+        let raster = Raster::<f64>::from_ascii_grid(&path).unwrap();
+
+        assert_eq!(raster.data, vec![1000.0, 0.25, -9999.0, 4.0, 5.0, 6.0]);
+        assert_eq!(raster.no_data, Some(-9999.0));
+        assert_eq!(raster.geo_transform, [100.0, 10.0, 0.0, 220.0, 0.0, -10.0]);
+    }
+
+    #[test]
+    fn test_from_ascii_grid_rejects_comma_decimal() {
+        let path = write_fixture("wepppyo3_comma.asc",
+            "ncols 2\nnrows 1\nxllcorner 0\nyllcorner 0\ncellsize 1\n1,5 2,5\n");
+        let err = Raster::<f64>::from_ascii_grid(&path).unwrap_err();
+
+        assert!(matches!(err, RasterError::Parse(_)));
+        assert!(err.to_string().contains("'1,5'"));
+    }
+
+
+    #[test]
+    fn test_format_grid_plain() {
+        let raster = Raster::new(3, 2, 1.0, vec![1, 22, -1, 4, -1, 333], Some(-1),
+            [0.0, 1.0, 0.0, 2.0, 0.0, -1.0], None, "".to_string(), "grid".to_string(), MapType::OTHER);
+
+        assert_eq!(raster.format_grid_plain(), "1    22   .\n4    .    333\n");
+
+        let nan = Raster::new(2, 1, 1.0, vec![1.5, f64::NAN], Some(f64::NAN),
+            [0.0, 1.0, 0.0, 1.0, 0.0, -1.0], None, "".to_string(), "grid".to_string(), MapType::OTHER);
+        assert_eq!(nan.format_grid_plain(), "1.5  .\n");
+    }
+
+
+    #[test]
+    fn test_try_from_f64_rejects_unrepresentable() {
+        assert_eq!(i32::try_from_f64(-9999.0), Some(-9999));
+        assert_eq!(i32::try_from_f64(3.4e38), None);
+        assert_eq!(i32::try_from_f64(f64::NAN), None);
+        assert_eq!(i32::try_from_f64(0.5), None);
+        assert_eq!(u32::try_from_f64(-1.0), None);
+        assert_eq!(u32::try_from_f64(4294967295.0), Some(u32::MAX));
+    }
+
+
+    #[test]
+    fn test_pad() {
+        let raster = Raster::new(2, 1, 10.0, vec![1, 2], Some(-1),
+            [100.0, 10.0, 0.0, 200.0, 0.0, -10.0], None, "".to_string(), "grid".to_string(), MapType::OTHER);
+        let padded = raster.pad(1);
+
+        assert_eq!((padded.width, padded.height), (4, 3));
+        assert_eq!(padded.data, vec![-1, -1, -1, -1, -1, 1, 2, -1, -1, -1, -1, -1]);
+        assert_eq!(padded.geo_transform, [90.0, 10.0, 0.0, 210.0, 0.0, -10.0]);
+    }
+
+
+    #[test]
+    fn test_mosaic_overlap() {
+        let a = Raster::new(2, 1, 1.0, vec![1.0, 2.0], Some(-1.0),
+            [0.0, 1.0, 0.0, 1.0, 0.0, -1.0], None, "a".to_string(), "a".to_string(), MapType::OTHER);
+        let b = Raster::new(2, 2, 1.0, vec![4.0, 5.0, -1.0, 6.0], Some(-1.0),
+            [1.0, 1.0, 0.0, 1.0, 0.0, -1.0], None, "b".to_string(), "b".to_string(), MapType::OTHER);
+
+        let mean = Raster::mosaic(&[a.clone(), b.clone()], MosaicMethod::Mean).unwrap();
+        assert_eq!((mean.width, mean.height), (3, 2));
+        assert_eq!(mean.data, vec![1.0, 3.0, 5.0, -1.0, -1.0, 6.0]);
+
+        let first = Raster::mosaic(&[a, b], MosaicMethod::First).unwrap();
+        assert_eq!(first.data, vec![1.0, 2.0, 5.0, -1.0, -1.0, 6.0]);
+    }
+
+
+    #[test]
+    fn test_allocate_nearest() {
+        let raster = Raster::new(5, 2, 1.0, vec![21, 0, 0, 0, 22, 0, 0, -1, 0, 0], Some(-1),
+            [0.0, 1.0, 0.0, 2.0, 0.0, -1.0], None, "".to_string(), "SUBWTA".to_string(), MapType::SUBWTA);
+        let allocated = raster.allocate_nearest(0);
+
+        assert_eq!(allocated.data, vec![21, 21, 21, 22, 22, 21, 21, -1, 22, 22]);
+    }
+
+
+    #[test]
+    fn test_with_inferred_no_data() {
+        let subwta = Raster::new(2, 1, 1.0, vec![0, 22], None,
+            [0.0, 1.0, 0.0, 1.0, 0.0, -1.0], None, "".to_string(), "SUBWTA".to_string(), MapType::SUBWTA);
+        assert_eq!(subwta.with_inferred_no_data().no_data, Some(0));
+
+        let explicit = Raster::new(2, 1, 1.0, vec![0, 22], Some(-1),
+            [0.0, 1.0, 0.0, 1.0, 0.0, -1.0], None, "".to_string(), "SUBWTA".to_string(), MapType::SUBWTA);
+        assert_eq!(explicit.with_inferred_no_data().no_data, Some(-1));
+
+        let other = Raster::new(2, 1, 1.0, vec![0, 22], None,
+            [0.0, 1.0, 0.0, 1.0, 0.0, -1.0], None, "".to_string(), "grid".to_string(), MapType::OTHER);
+        assert_eq!(other.with_inferred_no_data().no_data, None);
+    }
+
+
+    #[test]
+    fn test_valid_window() {
+        let raster = Raster::new(4, 3, 1.0, vec![-1, -1, -1, -1, -1, 5, 6, -1, -1, -1, 7, -1], Some(-1),
+            [0.0, 1.0, 0.0, 3.0, 0.0, -1.0], None, "".to_string(), "grid".to_string(), MapType::OTHER);
+        assert_eq!(raster.valid_window(), Some((1, 1, 2, 2)));
+        assert_eq!(raster.valid_bounds_lnglat(), None);
+
+        let empty = raster.with_data(vec![-1; 12], Some(-1));
+        assert_eq!(empty.valid_window(), None);
+    }
+
+
+    #[test]
+    fn test_aspect_class() {
+        assert_eq!(aspect_class(0.0), Some(0));
+        assert_eq!(aspect_class(22.4), Some(0));
+        assert_eq!(aspect_class(22.5), Some(1));
+        assert_eq!(aspect_class(337.4), Some(7));
+        assert_eq!(aspect_class(337.5), Some(0));
+        assert_eq!(aspect_class(360.0), Some(0));
+        assert_eq!(aspect_class(180.0), Some(4));
+        assert_eq!(aspect_class(-1.0), None);
+        assert_eq!(aspect_class(f64::NAN), None);
+    }
+
+
+    #[test]
+    fn test_flow_length() {
+        // 6 = east, 3 = north-east, 5 = outlet
+        let flowdir = Raster::new(3, 2, 10.0, vec![6, 6, 5, 3, -1, 8], Some(-1),
+            [0.0, 10.0, 0.0, 20.0, 0.0, -10.0], None, "".to_string(), "FLOVEC".to_string(), MapType::FLOVEC);
+        let lengths = flow_length(&flowdir, 10.0);
+
+        let diagonal = 10.0 * std::f64::consts::SQRT_2;
+        assert_eq!(lengths.data[..3], [20.0, 10.0, 0.0]);
+        assert!((lengths.data[3] - (diagonal + 10.0)).abs() < 1e-9);
+        assert_eq!(lengths.data[4], -9999.0);
+        // drains off the grid
+        assert_eq!(lengths.data[5], 0.0);
+        assert_eq!(lengths.no_data, Some(-9999.0));
+    }
+
+    #[test]
+    fn test_hillslope_geometry() {
+        // two hillslopes draining east into the channel 24 in the last column
+        let subwta = Raster::new(4, 2, 10.0, vec![22, 22, 22, 24, 23, 23, 0, 24], Some(0),
+            [0.0, 10.0, 0.0, 20.0, 0.0, -10.0], None, "".to_string(), "SUBWTA".to_string(), MapType::SUBWTA);
+        let flowdir = subwta.with_data(vec![6, 6, 6, 8, 6, 3, 6, 5], None);
+
+        let lengths = flow_length_within(&flowdir, &subwta, 10.0);
+        assert_eq!(lengths.data[..3], [30.0, 20.0, 10.0]);
+
+        let geometry = hillslope_geometry(&subwta, &flowdir).unwrap();
+        assert_eq!(geometry.len(), 2);
+        assert_eq!(geometry[&22], (30.0, 10.0));
+        // 23 leaves diagonally into 22
+        let diagonal = 10.0 * std::f64::consts::SQRT_2;
+        assert!((geometry[&23].0 - (10.0 + diagonal)).abs() < 1e-9);
+        assert!((geometry[&23].1 - 200.0 / (10.0 + diagonal)).abs() < 1e-9);
+
+        // a raw TOPAZ SUBWTA: no no-data value and a zero-filled border
+        let raw = subwta.with_data(subwta.data.clone(), None).pad(1);
+        let geometry = hillslope_geometry(&raw, &flowdir.pad(1)).unwrap();
+        assert_eq!(geometry.len(), 2);
+        assert!(!geometry.contains_key(&0));
+        assert_eq!(geometry[&22], (30.0, 10.0));
+    }
+
+    #[test]
+    fn test_hillslopes_only() {
+        let subwta = Raster::new(4, 2, 10.0, vec![22, 22, 22, 24, 23, 23, 0, 24], Some(-9999),
+            [0.0, 10.0, 0.0, 20.0, 0.0, -10.0], None, "".to_string(), "SUBWTA".to_string(), MapType::SUBWTA);
+
+        let hillslopes = subwta.hillslopes_only();
+        assert_eq!(hillslopes.data, vec![22, 22, 22, -9999, 23, 23, -9999, -9999]);
+        assert_eq!(hillslopes.no_data, Some(-9999));
+
+        let unmarked = subwta.with_data(subwta.data.clone(), None).hillslopes_only();
+        assert_eq!(unmarked.data, vec![22, 22, 22, 0, 23, 23, 0, 0]);
+        assert_eq!(unmarked.no_data, Some(0));
+    }
+
+    #[test]
+    fn test_hillslope_outlets() {
+        let subwta = Raster::new(4, 2, 10.0, vec![22, 22, 22, 24, 23, 23, 0, 24], Some(0),
+            [0.0, 10.0, 0.0, 20.0, 0.0, -10.0], None, "".to_string(), "SUBWTA".to_string(), MapType::SUBWTA);
+        let discha = subwta.with_data(vec![30.0, 20.0, 10.0, 0.0, 14.0, 14.0, 5.0, 0.0], None);
+
+        let outlets = hillslope_outlets(&subwta, &discha).unwrap();
+        assert_eq!(outlets.len(), 2);
+        assert_eq!(outlets[&22], (2, 0));
+        // equal distances, the first cell wins
+        assert_eq!(outlets[&23], (0, 1));
+
+        // a raw TOPAZ SUBWTA: no no-data value and a zero-filled border
+        let raw = subwta.with_data(subwta.data.clone(), None).pad(1);
+        let outlets = hillslope_outlets(&raw, &discha.pad(1)).unwrap();
+        assert_eq!(outlets.len(), 2);
+        assert!(!outlets.contains_key(&0));
+        assert_eq!(outlets[&22], (3, 1));
+
+        assert!(matches!(hillslope_outlets(&subwta, &discha.pad(1)), Err(RasterError::Dimension(_))));
+    }
+
+
+    #[test]
+    fn test_clamp() {
+        let raster = Raster::new(5, 1, 1.0, vec![-5.0, 0.5, 3.0, -9999.0, f64::NAN], Some(-9999.0),
+            [0.0, 1.0, 0.0, 1.0, 0.0, -1.0], None, "".to_string(), "grid".to_string(), MapType::OTHER);
+
+        let clamped = raster.clamp(Some(0.0), Some(1.0));
+        assert_eq!(clamped.data[..4], [0.0, 0.5, 1.0, -9999.0]);
+        assert!(clamped.data[4].is_nan());
+
+        let upper_only = raster.clamp(None, Some(1.0));
+        assert_eq!(upper_only.data[..4], [-5.0, 0.5, 1.0, -9999.0]);
+    }
+
+
+    #[test]
+    fn test_blend() {
+        let a = Raster::new(3, 1, 1.0, vec![10.0, 20.0, -1.0], Some(-1.0),
+            [0.0, 1.0, 0.0, 1.0, 0.0, -1.0], None, "a".to_string(), "a".to_string(), MapType::OTHER);
+        let b = a.with_data(vec![0.0, 40.0, 5.0], None);
+
+        let blended = Raster::blend(&a, &b, 0.25).unwrap();
+        assert_eq!(blended.data, vec![2.5, 35.0, -1.0]);
+        assert_eq!(blended.no_data, Some(-1.0));
+
+        assert!(matches!(Raster::blend(&a, &b, 1.5), Err(RasterError::InvalidArgument(_))));
+        assert!(matches!(Raster::blend(&a, &a.pad(1), 0.5), Err(RasterError::Dimension(_))));
+    }
+
+    #[test]
+    fn test_select() {
+        let a = Raster::new(4, 1, 1.0, vec![10.0, 20.0, -1.0, 40.0], Some(-1.0),
+            [0.0, 1.0, 0.0, 1.0, 0.0, -1.0], None, "a".to_string(), "a".to_string(), MapType::OTHER);
+        let b = a.with_data(vec![1.0, 2.0, 3.0, 4.0], None);
+        let mask = a.with_data(vec![1, 0, 1, -9], Some(-9));
+
+        let selected = Raster::select(&mask, &a, &b).unwrap();
+        assert_eq!(selected.data, vec![10.0, 2.0, -1.0, -1.0]);
+        assert_eq!(selected.no_data, Some(-1.0));
+
+        assert!(matches!(Raster::select(&mask, &a, &b.pad(1)), Err(RasterError::Dimension(_))));
+    }
+
+
+    #[test]
+    fn test_difference() {
+        let a = Raster::new(4, 1, 1.0, vec![10.0, 20.0, -1.0, 40.0], Some(-1.0),
+            [0.0, 1.0, 0.0, 1.0, 0.0, -1.0], None, "a".to_string(), "a".to_string(), MapType::OTHER);
+        let b = a.with_data(vec![10.001, 15.0, 3.0, 50.0], None);
+
+        let (diff, significant) = Raster::difference(&a, &b, 0.01).unwrap();
+        assert_eq!(diff.data, vec![0.0, 5.0, -1.0, -10.0]);
+        assert_eq!(significant, 2);
+
+        assert!(matches!(Raster::difference(&a, &b, -1.0), Err(RasterError::InvalidArgument(_))));
+        assert!(matches!(Raster::difference(&a, &b.pad(1), 0.0), Err(RasterError::Dimension(_))));
+    }
+
+    #[test]
+    fn test_masked_by_valid() {
+        let surface = Raster::new(4, 1, 1.0, vec![1.5, 2.5, 3.5, 4.5], None,
+            [0.0, 1.0, 0.0, 1.0, 0.0, -1.0], None, "a".to_string(), "a".to_string(), MapType::OTHER);
+        let subwta = surface.with_data(vec![22, 0, 23, 0], Some(0));
+
+        let masked = surface.masked_by_valid(&subwta).unwrap();
+        assert_eq!(masked.data, vec![1.5, -9999.0, 3.5, -9999.0]);
+        assert_eq!(masked.no_data, Some(-9999.0));
+
+        assert!(matches!(surface.masked_by_valid(&subwta.pad(1)), Err(RasterError::Dimension(_))));
+    }
+
+    #[test]
+    fn test_rasterize_points() {
+        let reference = Raster::new(3, 1, 10.0, vec![0, 0, 0], None,
+            [0.0, 10.0, 0.0, 10.0, 0.0, -10.0], None, "".to_string(), "grid".to_string(), MapType::OTHER);
+        // cell centers are at eastings 5, 15, and 25; the last point is outside the extent
+        let points = vec![(5.0, 5.0, 1.0), (25.0, 5.0, 3.0), (500.0, 5.0, 100.0)];
+
+        let nearest = reference.rasterize_points(&points, PointInterpolation::Nearest);
+        assert_eq!(nearest.data, vec![1.0, 1.0, 3.0]);
+
+        let idw = reference.rasterize_points(&points, PointInterpolation::Idw);
+        assert_eq!(idw.data, vec![1.0, 2.0, 3.0]);
+
+        let empty = reference.rasterize_points(&points[2..], PointInterpolation::Idw);
+        assert_eq!(empty.data, vec![-9999.0; 3]);
+    }
+
+
+    #[test]
+    fn test_count_in_range() {
+        let raster = Raster::new(6, 1, 1.0, vec![0.5, 1.0, 2.0, 3.0, -9999.0, f64::NAN], Some(-9999.0),
+            [0.0, 1.0, 0.0, 1.0, 0.0, -1.0], None, "".to_string(), "grid".to_string(), MapType::OTHER);
+        assert_eq!(raster.count_in_range(1.0, 3.0), 3);
+        assert_eq!(raster.count_in_range(f64::NEG_INFINITY, f64::INFINITY), 4);
+        assert_eq!(raster.count_in_range(3.5, 10.0), 0);
+    }
+
+    #[test]
+    fn test_sum_and_count_nonzero() {
+        let raster = Raster::new(6, 1, 1.0, vec![0.5, 0.0, 2.0, 3.0, -9999.0, f64::NAN], Some(-9999.0),
+            [0.0, 1.0, 0.0, 1.0, 0.0, -1.0], None, "".to_string(), "grid".to_string(), MapType::OTHER);
+        assert_eq!(raster.sum(), 5.5);
+        assert_eq!(raster.count_nonzero(), 3);
+    }
+
+
+    #[test]
+    fn test_band_statistics_all_uses_per_band_no_data() {
+        let band1 = Raster::new(4, 1, 1.0, vec![1.0, -9999.0, 3.0, 4.0], Some(-9999.0),
+            [0.0, 1.0, 0.0, 1.0, 0.0, -1.0], None, "".to_string(), "stack".to_string(), MapType::OTHER);
+        let band2 = band1.with_data(vec![0.0, 2.0, -9999.0, 0.0], Some(0.0));
+
+        let stats = Raster::band_statistics_all(&[band1, band2]);
+        assert_eq!(stats.len(), 2);
+        assert_eq!(stats[0].valid_percent, 75.0);
+        assert_eq!(stats[1].valid_percent, 50.0);
+    }
+
+    #[test]
+    fn test_raster_cube_from_bands() {
+        let band1 = Raster::new(2, 1, 1.0, vec![1.0, -9999.0], Some(-9999.0),
+            [0.0, 1.0, 0.0, 1.0, 0.0, -1.0], None, "".to_string(), "stack".to_string(), MapType::OTHER);
+        let band2 = band1.with_data(vec![0.0, 2.0], Some(0.0));
+
+        let cube = RasterCube::from_bands(vec![band1.clone(), band2]).unwrap();
+        assert_eq!((cube.width, cube.height, cube.n_bands), (2, 1, 2));
+        assert_eq!(cube.data, vec![1.0, -9999.0, 0.0, 2.0]);
+        assert_eq!(cube.band(1), &[0.0, 2.0]);
+        assert_eq!(cube.no_data, vec![Some(-9999.0), Some(0.0)]);
+
+        let wide = Raster::new(3, 1, 1.0, vec![0.0; 3], None,
+            [0.0, 1.0, 0.0, 1.0, 0.0, -1.0], None, "".to_string(), "stack".to_string(), MapType::OTHER);
+        assert!(matches!(RasterCube::from_bands(vec![band1, wide]), Err(RasterError::Dimension(_))));
+        assert!(matches!(RasterCube::<f64>::from_bands(vec![]), Err(RasterError::InvalidArgument(_))));
+    }
+
+    #[test]
+    fn test_alignment_report() {
+        let mut a = Raster::new(4, 4, 30.0, vec![0.0; 16], None,
+            [1000.0, 30.0, 0.0, 2000.0, 0.0, -30.0], None, "".to_string(), "a".to_string(), MapType::OTHER);
+        a.proj4 = Some("+proj=utm +zone=11".to_string());
+        assert_eq!(a.alignment_report(&a), AlignmentReport::default());
+        assert!(a.alignment_report(&a).suggestion().is_none());
+
+        let mut b = a.with_data(vec![0.0; 16], None);
+        b.geo_transform[0] += 15.0;
+        let report = a.alignment_report(&b);
+        assert_eq!(report.origin.as_deref(), Some("b is offset by 0.5 px in x"));
+        assert!(report.cellsize.is_none() && report.crs.is_none());
+
+        let c = Raster::new(12, 12, 10.0, vec![0.0; 144], None,
+            [1000.0, 10.0, 0.0, 2000.0, 0.0, -10.0], None, "".to_string(), "c".to_string(), MapType::OTHER);
+        let report = a.alignment_report(&c);
+        assert_eq!(report.width.as_deref(), Some("widths differ 4 vs 12"));
+        assert_eq!(report.cellsize.as_deref(), Some("cellsizes differ 30 vs 10"));
+        assert!(report.origin.is_none());
+        assert!(report.crs.is_some());
+        assert!(report.suggestion().unwrap().starts_with("reproject"));
+    }
+
+    #[test]
+    fn test_sample_cubic() {
+        // a plane is reproduced exactly by cubic convolution
+        let raster = Raster::new(6, 6, 1.0, (0..36).map(|i| (i % 6) as f64 + 10.0 * (i / 6) as f64).collect(), None,
+            [0.0, 1.0, 0.0, 6.0, 0.0, -1.0], None, "".to_string(), "grid".to_string(), MapType::OTHER);
+        let value = raster.sample_at(2.7, 6.0 - 3.2, ResampleMethod::Cubic).unwrap();
+        assert!((value - 29.2).abs() < 1e-9);
+        assert_eq!(raster.sample_at(-0.5, 3.0, ResampleMethod::Cubic), None);
+
+        let holed = raster.with_data((0..36).map(|i| if i == 7 { -9999.0 } else { 1.0 }).collect(), Some(-9999.0));
+        assert_eq!(holed.sample_at(2.7, 6.0 - 3.2, ResampleMethod::Cubic), None);
+        assert_eq!("cubic".parse::<ResampleMethod>(), Ok(ResampleMethod::Cubic));
+    }
+
+    #[test]
+    fn test_sample_bilinear() {
+        // cell centers at e = 5, 15 and n = 15, 5
+        let raster = Raster::new(2, 2, 10.0, vec![0.0, 10.0, 20.0, -9999.0], Some(-9999.0),
+            [0.0, 10.0, 0.0, 20.0, 0.0, -10.0], None, "".to_string(), "grid".to_string(), MapType::OTHER);
+        let top = raster.with_data(vec![0.0, 10.0, 20.0, 30.0], None);
+
+        assert_eq!(top.sample_bilinear(10.0, 15.0), Some(5.0));
+        assert_eq!(top.sample_bilinear(10.0, 10.0), Some(15.0));
+        assert_eq!(top.sample_bilinear(2.0, 18.0), Some(0.0));
+        assert_eq!(top.sample_bilinear(-1.0, 10.0), None);
+        assert_eq!(raster.sample_bilinear(2.0, 18.0), Some(0.0));
+        assert_eq!(raster.sample_bilinear(10.0, 10.0), None);
+
+        assert_eq!(top.sample_at(12.0, 8.0, ResampleMethod::Nearest), Some(30.0));
+        assert_eq!(top.sample_at(10.0, 10.0, ResampleMethod::Bilinear), Some(15.0));
+        assert_eq!(raster.sample_at(12.0, 8.0, ResampleMethod::Nearest), None);
+        assert_eq!(top.sample_at(25.0, 10.0, ResampleMethod::Nearest), None);
+        assert_eq!("bilinear".parse::<ResampleMethod>(), Ok(ResampleMethod::Bilinear));
+    }
+
+    #[test]
+    fn test_nan_no_data() {
+        let raster = Raster::new(3, 1, 1.0, vec![f64::NAN, 2.0, f64::NAN], Some(f64::NAN),
+            [0.0, 1.0, 0.0, 1.0, 0.0, -1.0], None, "".to_string(), "grid".to_string(), MapType::OTHER);
+        assert!(raster.is_no_data(f64::NAN));
+        assert!(!raster.is_no_data(2.0));
+        assert_eq!(raster.valid_window(), Some((1, 0, 1, 1)));
+        assert_eq!(raster.sample_nearest(0.5, 0.5), None);
+        assert_eq!(raster.compute_band_statistics().valid_percent, 100.0 / 3.0);
+    }
+
+    #[test]
+    fn test_band_statistics_exclude_no_data() {
+        let raster = Raster::new(3, 2, 1.0, vec![2.0, -9999.0, 4.0, 4.0, -9999.0, 6.0], Some(-9999.0),
+            [0.0, 1.0, 0.0, 2.0, 0.0, -1.0], None, "".to_string(), "grid".to_string(), MapType::OTHER);
+        let stats = raster.compute_band_statistics();
+        assert_eq!(stats.minimum, 2.0);
+        assert_eq!(stats.maximum, 6.0);
+        assert_eq!(stats.mean, 4.0);
+        // population variance of 2, 4, 4, 6 is 2
+        assert!((stats.std_dev - 2.0_f64.sqrt()).abs() < 1e-12);
+        assert!((stats.valid_percent - 200.0 / 3.0).abs() < 1e-12);
+        assert_eq!(stats.median, 4.0);
+        // ranks 0.06 and 2.94 of 2, 4, 4, 6
+        assert!((stats.p2 - 2.12).abs() < 1e-12);
+        assert!((stats.p98 - 5.88).abs() < 1e-12);
+    }
+
+    #[test]
+    fn test_band_statistics_skip_nan() {
+        let raster = Raster::new(4, 1, 1.0, vec![2.0, f64::NAN, -9999.0, 4.0], Some(-9999.0),
+            [0.0, 1.0, 0.0, 1.0, 0.0, -1.0], None, "".to_string(), "grid".to_string(), MapType::OTHER);
+        let stats = raster.compute_band_statistics();
+        assert_eq!(stats.mean, 3.0);
+        assert_eq!(stats.std_dev, 1.0);
+        assert_eq!(stats.valid_percent, 50.0);
+        assert_eq!(stats.median, 3.0);
+    }
+
+    #[test]
+    fn test_sanitize() {
+        let raster = Raster::new(5, 1, 1.0, vec![1.0, f64::NAN, f64::INFINITY, -9999.0, f64::NEG_INFINITY], Some(-9999.0),
+            [0.0, 1.0, 0.0, 1.0, 0.0, -1.0], None, "".to_string(), "grid".to_string(), MapType::OTHER);
+
+        let sanitized = raster.sanitize();
+        assert_eq!(sanitized.data, vec![1.0, -9999.0, -9999.0, -9999.0, -9999.0]);
+        assert_eq!(sanitized.no_data, Some(-9999.0));
+
+        let sanitized = raster.sanitize_with(-1.0);
+        assert_eq!(sanitized.data, vec![1.0, -1.0, -1.0, -1.0, -1.0]);
+        assert_eq!(sanitized.no_data, Some(-1.0));
+    }
+
+    #[test]
+    fn test_mask_float() {
+        let raster = Raster::new(3, 1, 1.0, vec![1.0, f64::NAN, 2.0], Some(f64::NAN),
+            [0.0, 1.0, 0.0, 1.0, 0.0, -1.0], None, "".to_string(), "grid".to_string(), MapType::OTHER);
+        assert_eq!(raster.mask(), vec![false, true, false]);
+    }
+
+    #[test]
+    fn test_aggregate_majority() {
+        // 5 x 4 with partial blocks on the right edge
+        let raster = Raster::new(5, 4, 1.0, vec![
+            1, 1, 2, 2, 3,
+            1, 4, 2, 0, 3,
+            0, 0, 5, 6, 3,
+            0, 0, 6, 5, 0,
+        ], Some(0), [10.0, 1.0, 0.0, 20.0, 0.0, -1.0], None, "".to_string(), "grid".to_string(), MapType::OTHER);
+
+        let coarse = raster.aggregate_majority(2);
+        assert_eq!((coarse.width, coarse.height), (3, 2));
+        assert_eq!(coarse.cellsize, 2.0);
+        assert_eq!(coarse.geo_transform, [10.0, 2.0, 0.0, 20.0, 0.0, -2.0]);
+        // 5 and 6 tie, the smaller wins; an all no-data block stays no-data
+        assert_eq!(coarse.data, vec![1, 2, 3, 0, 5, 3]);
+    }
+
+    #[test]
+    fn test_raster_builder() {
+        let raster = RasterBuilder::new()
+            .width(3)
+            .height(2)
+            .cellsize(30.0)
+            .data(vec![1, 2, 3, 4, 5, 6])
+            .nodata(0)
+            .name("SUBWTA")
+            .map_type(MapType::SUBWTA)
+            .build()
+            .unwrap();
+        assert_eq!((raster.width, raster.height), (3, 2));
+        assert_eq!(raster.geo_transform, [0.0, 30.0, 0.0, 0.0, 0.0, -30.0]);
+        assert_eq!(raster.no_data, Some(0));
+        assert_eq!(raster.map_type, MapType::SUBWTA);
+
+        let shifted = raster.to_builder().data(vec![7; 6]).build().unwrap();
+        assert_eq!(shifted.data, vec![7; 6]);
+        assert_eq!(shifted.name, "SUBWTA");
+
+        // transposed dimensions still match, a short data vector does not
+        assert!(raster.to_builder().width(2).height(3).build().is_ok());
+        assert!(matches!(raster.to_builder().data(vec![1, 2]).build(), Err(RasterError::Dimension(_))));
+    }
+
+    #[test]
+    fn test_try_new_checks_data_length() {
+        let result = Raster::try_new(3, 2, 1.0, vec![1, 2, 3, 4, 5], None,
+            [0.0, 1.0, 0.0, 2.0, 0.0, -1.0], None, "short.tif".to_string(), "grid".to_string(), MapType::OTHER);
+        match result {
+            Err(RasterError::Dimension(msg)) => assert!(msg.contains("5 cells given for a 3 x 2 raster")),
+            other => panic!("expected a dimension error, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_subwta_rgb() {
+        // channels share a color distinct from the hillslopes draining into them
+        assert_eq!(subwta_rgb(24), subwta_rgb(34));
+        assert_ne!(subwta_rgb(22), subwta_rgb(23));
+        assert_ne!(subwta_rgb(22), subwta_rgb(24));
+        assert_eq!(subwta_rgb(27), subwta_rgb(29));
+    }
+
+    #[test]
+    fn test_polygon_mask() {
+        let raster = Raster::new(4, 4, 1.0, vec![0; 16], None,
+            [0.0, 1.0, 0.0, 4.0, 0.0, -1.0], None, "".to_string(), "grid".to_string(), MapType::OTHER);
+        // a square over the whole grid with a hole over the center cell (1, 1),
+        // and a second part over cell (3, 3)
+        let polygons = vec![
+            vec![
+                vec![(0.0, 1.0), (3.0, 1.0), (3.0, 4.0), (0.0, 4.0), (0.0, 1.0)],
+                vec![(1.0, 2.0), (2.0, 2.0), (2.0, 3.0), (1.0, 3.0), (1.0, 2.0)],
+            ],
+            vec![vec![(3.0, 0.0), (4.0, 0.0), (4.0, 1.0), (3.0, 1.0)]],
+        ];
+
+        let mask = raster.polygon_mask(&polygons);
+        let inside: Vec<usize> = (0..16).filter(|&i| mask[i]).collect();
+        assert_eq!(inside, vec![0, 1, 2, 4, 6, 8, 9, 10, 15]);
+
+        assert!(matches!(raster.polygon_mask_lnglat(&polygons), Err(RasterError::InvalidArgument(_))));
+    }
+
+    #[test]
+    fn test_driver_for_path() {
+        assert_eq!(driver_for_path("/tmp/loss.tif"), "GTiff");
+        assert_eq!(driver_for_path("/tmp/loss.ASC"), "AAIGrid");
+        assert_eq!(driver_for_path("/tmp/loss.img"), "HFA");
+        assert_eq!(driver_for_path("/tmp/loss"), "GTiff");
+    }
+
+    #[test]
+    fn test_gdal_type_size() {
+        assert_eq!(gdal_type_size(1), 1);
+        assert_eq!(gdal_type_size(5), 4);
+        assert_eq!(gdal_type_size(7), 8);
+        assert_eq!(gdal_type_size(11), 16);
+    }
+
+    #[test]
+    fn test_paint_values() {
+        let zones = Raster::new(4, 1, 1.0, vec![22, 23, 0, 32], Some(0),
+            [0.0, 1.0, 0.0, 1.0, 0.0, -1.0], None, "".to_string(), "SUBWTA".to_string(), MapType::SUBWTA);
+        let values: std::collections::HashMap<i32, f64> = [(22, 1.5), (23, 2.5), (0, 9.0)].iter().cloned().collect();
+
+        let painted = zones.paint_values(&values, -9999.0);
+        assert_eq!(painted.data, vec![1.5, 2.5, -9999.0, -9999.0]);
+        assert_eq!(painted.no_data, Some(-9999.0));
+    }
 }
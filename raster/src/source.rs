@@ -0,0 +1,115 @@
+//! A GDAL dataset opened once and shared by every band read from it.
+//!
+//! `Raster::read_band` opens the file for every call; reading the twelve bands
+//! of a monthly stack that way opens twelve handles, which adds up to file
+//! descriptor exhaustion in large batches. A `RasterSource` holds one handle
+//! behind a mutex, so it can also be shared between threads.
+
+use std::str::FromStr;
+use std::sync::Mutex;
+
+use gdal::raster::GdalType;
+use gdal::spatial_ref::SpatialRef;
+use gdal::{Dataset, Metadata};
+
+use crate::cache;
+use crate::error::RasterError;
+use crate::raster::{MapType, Raster, TryFromF64};
+
+
+/// An open GDAL dataset that lends out its bands as `Raster`s.
+pub struct RasterSource {
+    path: String,
+    dataset: Mutex<Dataset>,
+}
+
+impl RasterSource {
+    /// Opens the dataset at `path`; its bands are read lazily.
+    pub fn open(path: &str) -> Result<RasterSource, RasterError> {
+        Ok(RasterSource {
+            path: path.to_string(),
+            dataset: Mutex::new(Dataset::open(path)?),
+        })
+    }
+
+    pub fn path(&self) -> &str {
+        &self.path
+    }
+
+    pub fn band_count(&self) -> isize {
+        self.dataset.lock().unwrap().raster_count()
+    }
+
+    /// Returns the GDAL description of band `band_indx`, e.g. `ppt_01`.
+    pub fn band_description(&self, band_indx: isize) -> Result<String, RasterError> {
+        let dataset = self.dataset.lock().unwrap();
+        let description = dataset.rasterband(band_indx)?.description()?;
+        Ok(description)
+    }
+
+    /// Reads band `band_indx` like `Raster::read_band`, including the raster
+    /// cache, without reopening the file.
+    pub fn read_band<T: GdalType + Default + Copy + TryFromF64 + Send + 'static>(
+        &self,
+        band_indx: isize
+    ) -> Result<Raster<T>, RasterError> {
+        if let Some(raster) = cache::get::<Raster<T>>(&self.path, band_indx) {
+            return Ok(raster);
+        }
+
+        let raster: Raster<T> = self.read_band_uncached(band_indx)?;
+        if cache::is_enabled() {
+            cache::insert(&self.path, band_indx, raster.clone());
+        }
+        Ok(raster)
+    }
+
+    fn read_band_uncached<T: GdalType + Default + Copy + TryFromF64>(&self, band_indx: isize) -> Result<Raster<T>, RasterError> {
+        let path = self.path.as_str();
+        let dataset = self.dataset.lock().unwrap();
+        let (width, height) = dataset.raster_size();
+        let geo_transform = dataset.geo_transform()?;
+        let cellsize = geo_transform[1];
+
+        // a dataset without a projection (e.g. a bare .ARC) has an empty wkt
+        let wkt = dataset.projection();
+        let proj4 = if wkt.is_empty() {
+            None
+        } else {
+            SpatialRef::from_wkt(&wkt)?.to_proj4().ok()
+        };
+
+        let band = dataset.rasterband(band_indx)?;
+        let buffer = band.read_as::<T>((0, 0), (width, height), (width, height), None)?;
+        let data = buffer.data;
+
+        // an out of range no-data (e.g. a float sentinel on an integer read) is an error,
+        // not a silently saturated value
+        let no_data: Option<T> = match band.no_data_value() {
+            Some(v) => Some(T::try_from_f64(v).ok_or_else(|| RasterError::Parse(format!(
+                "{}: no-data value {} of band {} is not representable in the requested cell type",
+                path, v, band_indx
+            )))?),
+            None => None,
+        };
+
+        // find the name by spliting the path and removing the extension from the filename of the file
+        let name = path.split("/").last().unwrap().split(".").next().unwrap().to_string();
+
+        // find the map type from the name using from_str
+        let map_type = MapType::from_str(&name).unwrap();
+
+        Raster::try_new(
+            width,
+            height,
+            cellsize,
+            data,
+            no_data,
+            geo_transform,
+            proj4,
+            path.to_string(),
+            name,
+            map_type,
+        )
+    }
+}
@@ -2,7 +2,154 @@ use pyo3::prelude::*;
 use pyo3::wrap_pyfunction;
 use std::collections::{HashSet, HashMap};
 
-use raster::raster::Raster;
+use rayon::prelude::*;
+
+use gdal::raster::GdalType;
+
+use raster::cache;
+use raster::error::RasterError;
+use raster::zonal::{self, ZonalStat, BACKGROUND_KEY, CHANNEL_MODULUS};
+use std::str::FromStr;
+
+use proj::Proj;
+
+use raster::raster::{Raster, MapType, FromF64, ToF64, TryFromF64, ResampleMethod, MosaicMethod, PointInterpolation, band_no_data,
+    band_footprint, aspect_class, hillslope_geometry as hillslope_geometry_rs,
+    hillslope_outlets as hillslope_outlets_rs, Polygon, ASPECT_CLASSES};
+
+
+/// Translates a `RasterError` into a Python exception, prefixing `context`.
+///
+/// GDAL, PROJ, and I/O failures raise `IOError`; unparsable values, mismatched
+/// grids, and invalid arguments raise `ValueError`.
+fn to_py_err(context: &str, err: RasterError) -> PyErr {
+    let msg = format!("{}: {}", context, err);
+    match err {
+        RasterError::Gdal(_) | RasterError::ProjCreate(_) | RasterError::Proj(_) | RasterError::Io(_) =>
+            pyo3::exceptions::PyIOError::new_err(msg),
+        RasterError::Parse(_) | RasterError::Dimension(_) | RasterError::InvalidArgument(_) =>
+            pyo3::exceptions::PyValueError::new_err(msg),
+    }
+}
+
+/// Reads band `band_indx` of the raster at `path`, raising a Python exception
+/// that names the file when it cannot be read (see `to_py_err`).
+fn read_raster_band<T: GdalType + Default + Copy + TryFromF64 + Send + 'static>(path: &str, band_indx: isize) -> PyResult<Raster<T>> {
+    read_raster_band_with_options(path, band_indx, false)
+}
+
+/// Like `read_raster_band`, optionally inferring the no-data value from the map type
+/// (e.g. `0` for `SUBWTA`) when the file does not set one.
+fn read_raster_band_with_options<T: GdalType + Default + Copy + TryFromF64 + Send + 'static>(
+    path: &str,
+    band_indx: isize,
+    infer_nodata_by_map_type: bool
+) -> PyResult<Raster<T>> {
+    Raster::<T>::read_band_with_options(path, band_indx, infer_nodata_by_map_type)
+        .map_err(|e| to_py_err(&format!("Failed to read raster {}", path), e))
+}
+
+/// Raises a Python `ValueError` unless the two rasters share the same width and height.
+fn check_dimensions<A, B>(a_fn: &str, a: &Raster<A>, b_fn: &str, b: &Raster<B>) -> PyResult<()> {
+    if a.width != b.width || a.height != b.height {
+        return Err(pyo3::exceptions::PyValueError::new_err(format!(
+            "Raster dimensions differ: {} is {} x {} but {} is {} x {}",
+            a_fn, a.width, a.height, b_fn, b.width, b.height
+        )));
+    }
+    Ok(())
+}
+
+/// Resamples `raster` onto the grid of `key_map` when `resample_if_needed` is set and
+/// the two grids are not already aligned.
+fn align_to_key<T: Copy + PartialEq + ToF64 + FromF64, K>(
+    raster: Raster<T>,
+    key_map: &Raster<K>,
+    resample_if_needed: bool,
+    method: ResampleMethod
+) -> Raster<T> {
+    if resample_if_needed && !raster.is_aligned_with(key_map) {
+        raster.resample_to_match(key_map, method)
+    } else {
+        raster
+    }
+}
+
+/// Resolves the `channel_modulus` argument: `None` is the TOPAZ convention `(10, 4)`
+/// (`zonal::CHANNEL_MODULUS`), and a divisor of 0 is a `ValueError`.
+fn resolve_channel_modulus(channel_modulus: Option<(i32, i32)>) -> PyResult<(i32, i32)> {
+    let modulus = channel_modulus.unwrap_or(CHANNEL_MODULUS);
+    if modulus.0 == 0 {
+        return Err(pyo3::exceptions::PyValueError::new_err("channel_modulus divisor must not be 0"));
+    }
+    Ok(modulus)
+}
+
+/// Resolves the `ignore_channels` and `channel_modulus` arguments into the modulus the
+/// `zonal` routines skip channels by, `None` when channels are kept.
+fn resolve_channel_filter(ignore_channels: bool, channel_modulus: Option<(i32, i32)>) -> PyResult<Option<(i32, i32)>> {
+    let modulus = resolve_channel_modulus(channel_modulus)?;
+    Ok(if ignore_channels { Some(modulus) } else { None })
+}
+
+/// Parses a statistic name such as `"mean"` into a `ZonalStat`, raising `ValueError` for
+/// unknown names.
+fn parse_zonal_stat(name: &str) -> PyResult<ZonalStat> {
+    ZonalStat::from_str(name).map_err(|_| pyo3::exceptions::PyValueError::new_err(format!(
+        "Unknown statistic '{}', expected count, sum, mean, min, max, median, mode, or std", name)))
+}
+
+/// Reads the optional boolean raster `mask_fn` onto the `key_map` grid and returns, per
+/// cell, whether the cell may be accumulated: every cell when there is no mask, otherwise
+/// only cells where the mask is nonzero and not no-data.
+fn read_cell_mask<K>(
+    mask_fn: Option<&str>,
+    key_fn: &str,
+    key_map: &Raster<K>,
+    resample_if_needed: bool
+) -> PyResult<Vec<bool>> {
+    let mask_fn = match mask_fn {
+        Some(mask_fn) => mask_fn,
+        None => return Ok(vec![true; key_map.data.len()]),
+    };
+
+    let mask_map: Raster<i32> = read_raster_band(mask_fn, 1)?;
+    let mask_map = align_to_key(mask_map, key_map, resample_if_needed, ResampleMethod::Nearest);
+    check_dimensions(key_fn, key_map, mask_fn, &mask_map)?;
+
+    Ok(mask_map.data.iter()
+        .map(|&value| value != 0 && mask_map.no_data != Some(value))
+        .collect())
+}
+
+fn identify_mode_single_raster_key_rs(
+    key_fn: &str, 
+    parameter_fn: &str, 
+    ignore_channels: bool,
+    mut ignore_keys: HashSet<i32>,
+    band_indx: isize,
+    resample_if_needed: bool,
+    infer_nodata_by_map_type: bool,
+    mask_fn: Option<&str>,
+    exclude_zero: bool,
+    channel_modulus: Option<(i32, i32)>
+) -> PyResult<HashMap<String, i32>> {
+    let channel_modulus = resolve_channel_filter(ignore_channels, channel_modulus)?;
+    if exclude_zero {
+        ignore_keys.insert(BACKGROUND_KEY);
+    }
+
+    let key_map: Raster<i32> = read_raster_band_with_options(key_fn, 1, infer_nodata_by_map_type)?;
+    let parameter_map: Raster<i32> = read_raster_band(parameter_fn, band_indx)?;
+    let parameter_map = align_to_key(parameter_map, &key_map, resample_if_needed, ResampleMethod::Nearest);
+    check_dimensions(key_fn, &key_map, parameter_fn, &parameter_map)?;
+    let include = read_cell_mask(mask_fn, key_fn, &key_map, resample_if_needed)?;
+
+    Ok(zonal::mode_by_key(&key_map, &parameter_map, Some(&include), channel_modulus, &ignore_keys)
+        .into_iter()
+        .map(|(key, val)| (key.to_string(), val))
+        .collect())
+}
 
 /// Identify the mode (most common) value of each key in a raster dataset.
 ///
@@ -16,9 +163,23 @@ use raster::raster::Raster;
 /// 
 /// * `key_fn: &str` - The file path to the raster data to be used as keys.
 /// * `parameter_fn: &str` - The file path to the raster data to determine the mode value for each key.
-/// * `ignore_channels: bool` - If `true`, keys that end in 4.
+/// * `ignore_channels: bool` - If `true`, channel keys (see `channel_modulus`) are skipped.
 /// * `mut ignore_keys: HashSet<i32>` - A set of keys to be ignored during processing. If a "no data" 
 ///    value is defined in `key_map`, it is automatically added to this set.
+/// * `band_indx: isize` - The band of `parameter_fn` to read.
+/// * `resample_if_needed: bool` - If `true` and the parameter raster is not aligned with the key
+///    raster, it is first resampled onto the key grid (nearest neighbour). Defaults to `false`.
+/// * `infer_nodata_by_map_type: bool` - If `true` and `key_fn` has no "no data" value, the
+///    default for its map type is used instead (`0` for `SUBWTA`). Defaults to `false`.
+/// * `mask_fn: Option<&str>` - An optional boolean raster (e.g. burned cells). When given, only
+///    cells where it is nonzero and not "no data" are accumulated. It is resampled (nearest
+///    neighbour) onto the key grid like the other rasters when `resample_if_needed` is set.
+///    Defaults to `None`.
+/// * `exclude_zero: bool` - If `true`, key `0` (the TOPAZ background) is skipped like an
+///    ignored key. Defaults to `false`.
+/// * `channel_modulus: Option<(i32, i32)>` - The `(divisor, remainder)` identifying channel
+///    keys as `key % divisor == remainder`. Defaults to `None`, which keeps the TOPAZ
+///    convention `(10, 4)` (keys that end in 4).
 ///
 /// # Returns
 /// 
@@ -28,9 +189,9 @@ use raster::raster::Raster;
 ///
 /// # Errors
 /// 
-/// Returns `Err` if there is a failure reading the raster data from the provided file paths.
-/// Note: The current implementation uses `unwrap()` which may cause panics on errors 
-/// (to be improved for production use).
+/// Returns `Err` (a Python `IOError` naming the file) if there is a failure reading the
+/// raster data from the provided file paths, and `Err` (`ValueError`) if the rasters differ
+/// in size.
 ///
 /// # Example
 /// 
@@ -46,59 +207,251 @@ use raster::raster::Raster;
 ///
 /// # Note
 ///
-/// Ensure that the raster datasets provided via `key_fn` and `parameter_fn` are of 
-/// identical dimensions, as the function does not perform dimensionality checks.
-///
-/// # Panics
-///
-/// The function may panic if it is unable to read the raster data from the provided paths.
-#[pyfunction]
+/// The raster datasets provided via `key_fn` and `parameter_fn` must be of identical
+/// dimensions, or pass `resample_if_needed` to align the parameter raster to the key
+/// raster's grid; otherwise a `ValueError` giving both shapes is raised.
+#[pyfunction(resample_if_needed = "false", infer_nodata_by_map_type = "false", mask_fn = "None", exclude_zero = "false", channel_modulus = "None")]
 fn identify_mode_single_raster_key(
     key_fn: &str, 
     parameter_fn: &str, 
     ignore_channels: bool,
-    mut ignore_keys: HashSet<i32>,
-    band_indx: isize
+    ignore_keys: HashSet<i32>,
+    band_indx: isize,
+    resample_if_needed: bool,
+    infer_nodata_by_map_type: bool,
+    mask_fn: Option<&str>,
+    exclude_zero: bool,
+    channel_modulus: Option<(i32, i32)>
 ) -> PyResult<HashMap<String, i32>> {
+    identify_mode_single_raster_key_rs(key_fn, parameter_fn, ignore_channels, ignore_keys, band_indx,
+        resample_if_needed, infer_nodata_by_map_type, mask_fn, exclude_zero, channel_modulus)
+}
 
-    let key_map: Raster<i32> = Raster::<i32>::read(key_fn).unwrap();
-    let parameter_map: Raster<i32> = Raster::<i32>::read_band(parameter_fn, band_indx).unwrap();
-
-    if let Some(no_data_value) = key_map.no_data {
-        ignore_keys.insert(no_data_value);
+/// Identify the mode value of each key with the counts behind it.
+///
+/// Like `identify_mode_single_raster_key`, but also reports how many cells voted for the
+/// mode and how many valid cells the key has, so keys whose mode is only marginally
+/// dominant can be filtered out.
+///
+/// # Arguments
+///
+/// * `key_fn: &str` - The file path to the raster data to be used as keys.
+/// * `parameter_fn: &str` - The file path to the raster data to determine the mode value for each key.
+/// * `ignore_channels: bool` - If `true`, channel keys (see `channel_modulus`) are skipped.
+/// * `ignore_keys: HashSet<i32>` - A set of keys to be ignored during processing.
+/// * `band_indx: isize` - The band of `parameter_fn` to read.
+/// * `resample_if_needed: bool` - As in `identify_mode_single_raster_key`. Defaults to `false`.
+/// * `infer_nodata_by_map_type: bool` - As in `identify_mode_single_raster_key`. Defaults to
+///    `false`.
+/// * `mask_fn: Option<&str>` - As in `identify_mode_single_raster_key`. Defaults to `None`.
+/// * `exclude_zero: bool` - If `true`, key `0` (the TOPAZ background) is skipped like an
+///    ignored key. Defaults to `false`.
+/// * `channel_modulus: Option<(i32, i32)>` - The `(divisor, remainder)` identifying channel
+///    keys as `key % divisor == remainder`. Defaults to `None`, which keeps the TOPAZ
+///    convention `(10, 4)` (keys that end in 4).
+///
+/// # Returns
+///
+/// `PyResult<HashMap<String, (i32, usize, usize)>>` - `(mode_value, mode_count, total_count)`
+/// for each key.
+///
+/// # Errors
+///
+/// Returns `Err` (a Python `IOError` naming the file) if a raster cannot be read, and `Err`
+/// (`ValueError`) if the rasters differ in size.
+#[pyfunction(resample_if_needed = "false", infer_nodata_by_map_type = "false", mask_fn = "None", exclude_zero = "false", channel_modulus = "None")]
+fn identify_mode_with_counts_single_raster_key(
+    key_fn: &str,
+    parameter_fn: &str,
+    ignore_channels: bool,
+    mut ignore_keys: HashSet<i32>,
+    band_indx: isize,
+    resample_if_needed: bool,
+    infer_nodata_by_map_type: bool,
+    mask_fn: Option<&str>,
+    exclude_zero: bool,
+    channel_modulus: Option<(i32, i32)>
+) -> PyResult<HashMap<String, (i32, usize, usize)>> {
+    let channel_modulus = resolve_channel_filter(ignore_channels, channel_modulus)?;
+    if exclude_zero {
+        ignore_keys.insert(BACKGROUND_KEY);
     }
 
-    let mut count_d: HashMap<i32, HashMap<i32, usize>> = HashMap::new();
+    let key_map: Raster<i32> = read_raster_band_with_options(key_fn, 1, infer_nodata_by_map_type)?;
+    let parameter_map: Raster<i32> = read_raster_band(parameter_fn, band_indx)?;
+    let parameter_map = align_to_key(parameter_map, &key_map, resample_if_needed, ResampleMethod::Nearest);
+    check_dimensions(key_fn, &key_map, parameter_fn, &parameter_map)?;
+    let include = read_cell_mask(mask_fn, key_fn, &key_map, resample_if_needed)?;
 
-    for (key, val) in key_map.data.iter().zip(parameter_map.data.iter()) {
-        if ignore_channels && key % 10 == 4 {
-            continue;
-        }
+    Ok(zonal::mode_with_counts_by_key(&key_map, &parameter_map, Some(&include), channel_modulus, &ignore_keys)
+        .into_iter()
+        .map(|(key, counts)| (key.to_string(), counts))
+        .collect())
+}
 
+/// Count the cells of each parameter value per key.
+///
+/// Returns the full value-to-count table of each key that `identify_mode_single_raster_key`
+/// reduces to a single mode, so any statistic of the distribution can be computed by the
+/// caller.
+///
+/// # Arguments
+///
+/// * `key_fn: &str` - The file path to the raster data to be used as keys.
+/// * `parameter_fn: &str` - The file path to the raster data to count for each key.
+/// * `ignore_channels: bool` - If `true`, channel keys (see `channel_modulus`) are skipped.
+/// * `ignore_keys: HashSet<i32>` - Keys to skip. The key raster's "no data" value is always
+///    skipped.
+/// * `band_indx: isize` - The band of `parameter_fn` to read.
+/// * `exclude_zero: bool` - If `true`, key `0` (the TOPAZ background) is skipped like an
+///    ignored key. Defaults to `false`.
+/// * `channel_modulus: Option<(i32, i32)>` - The `(divisor, remainder)` identifying channel
+///    keys as `key % divisor == remainder`. Defaults to `None`, which keeps the TOPAZ
+///    convention `(10, 4)` (keys that end in 4).
+///
+/// # Returns
+///
+/// `PyResult<HashMap<String, HashMap<i32, usize>>>` - The count of each parameter value of
+/// each key. The parameter raster's "no data" cells are not counted.
+///
+/// # Errors
+///
+/// Returns `Err` (a Python `IOError` naming the file) if a raster cannot be read, and `Err`
+/// (`ValueError`) if the rasters differ in size.
+#[pyfunction(exclude_zero = "false", channel_modulus = "None")]
+fn identify_histogram_single_raster_key(
+    key_fn: &str,
+    parameter_fn: &str,
+    ignore_channels: bool,
+    mut ignore_keys: HashSet<i32>,
+    band_indx: isize,
+    exclude_zero: bool,
+    channel_modulus: Option<(i32, i32)>
+) -> PyResult<HashMap<String, HashMap<i32, usize>>> {
+    let channel_modulus = resolve_channel_filter(ignore_channels, channel_modulus)?;
+    if exclude_zero {
+        ignore_keys.insert(BACKGROUND_KEY);
+    }
 
-        if let Some(no_data_value) = parameter_map.no_data {
-            if no_data_value == *val {
-                continue;
-            }
-        }
+    let key_map: Raster<i32> = read_raster_band(key_fn, 1)?;
+    let parameter_map: Raster<i32> = read_raster_band(parameter_fn, band_indx)?;
+    check_dimensions(key_fn, &key_map, parameter_fn, &parameter_map)?;
 
-        if ignore_keys.contains(key) {
-            continue;
-        }
+    Ok(zonal::counts_by_key(&key_map, &parameter_map, None, channel_modulus, &ignore_keys)
+        .into_iter()
+        .map(|(key, counts)| (key.to_string(), counts))
+        .collect())
+}
 
-        *count_d.entry(*key).or_insert_with(HashMap::new).entry(*val).or_insert(0) += 1;
+/// Identify the mode value of each key for several parameter rasters at once.
+///
+/// Reads `key_fn` once and computes the mode of every parameter raster over it, e.g. the
+/// landuse, soil, and slope class of each subcatchment.
+///
+/// # Arguments
+///
+/// * `key_fn: &str` - The file path to the raster data to be used as keys.
+/// * `parameter_fns: Vec<String>` - The file paths of the parameter rasters.
+/// * `ignore_channels: bool` - If `true`, channel keys (see `channel_modulus`) are skipped.
+/// * `ignore_keys: HashSet<i32>` - Keys to skip. The key raster's "no data" value is always
+///    skipped.
+/// * `band_indx: isize` - The band of each parameter raster to read.
+/// * `exclude_zero: bool` - If `true`, key `0` (the TOPAZ background) is skipped like an
+///    ignored key. Defaults to `false`.
+/// * `default: i32` - The entry for a parameter without valid values under a key. Defaults
+///    to `-9999`.
+/// * `channel_modulus: Option<(i32, i32)>` - The `(divisor, remainder)` identifying channel
+///    keys as `key % divisor == remainder`. Defaults to `None`, which keeps the TOPAZ
+///    convention `(10, 4)` (keys that end in 4).
+///
+/// # Returns
+///
+/// `PyResult<HashMap<String, Vec<i32>>>` - For each key with a mode in at least one parameter
+/// raster, one mode per parameter raster in the order of `parameter_fns`.
+///
+/// # Errors
+///
+/// Returns `Err` (a Python `IOError` naming the file) if a raster cannot be read, and `Err`
+/// (`ValueError`) if a parameter raster differs in size from the key raster.
+#[pyfunction(exclude_zero = "false", default = "-9999", channel_modulus = "None")]
+fn identify_mode_multi_parameter(
+    key_fn: &str,
+    parameter_fns: Vec<String>,
+    ignore_channels: bool,
+    mut ignore_keys: HashSet<i32>,
+    band_indx: isize,
+    exclude_zero: bool,
+    default: i32,
+    channel_modulus: Option<(i32, i32)>
+) -> PyResult<HashMap<String, Vec<i32>>> {
+    let channel_modulus = resolve_channel_filter(ignore_channels, channel_modulus)?;
+    if exclude_zero {
+        ignore_keys.insert(BACKGROUND_KEY);
     }
 
-    let mut result: HashMap<String, i32> = HashMap::new();
-    for (key, sub_map) in &count_d {
-        if let Some((&val, &_count)) = sub_map.iter().max_by_key(|&(_, count)| count) {
-            result.insert(key.to_string(), val);
+    let key_map: Raster<i32> = read_raster_band(key_fn, 1)?;
+
+    let mut result: HashMap<String, Vec<i32>> = HashMap::new();
+    for (indx, parameter_fn) in parameter_fns.iter().enumerate() {
+        let parameter_map: Raster<i32> = read_raster_band(parameter_fn, band_indx)?;
+        check_dimensions(key_fn, &key_map, parameter_fn, &parameter_map)?;
+
+        for (key, mode) in zonal::mode_by_key(&key_map, &parameter_map, None, channel_modulus, &ignore_keys) {
+            result.entry(key.to_string()).or_insert_with(|| vec![default; parameter_fns.len()])[indx] = mode;
         }
     }
 
     Ok(result)
 }
 
+/// Identify the mode value of each key for a batch of key/parameter raster pairs.
+///
+/// Applies `identify_mode_single_raster_key` to every `(key_fn, parameter_fn)` pair in
+/// parallel (the GIL is released while the rasters are processed). A pair that fails, e.g.
+/// because a raster cannot be read, yields an error entry instead of aborting the batch.
+///
+/// # Arguments
+/// 
+/// * `pairs: Vec<(String, String)>` - The `(key_fn, parameter_fn)` file path pairs.
+/// * `ignore_channels: bool` - If `true`, channel keys (see `channel_modulus`) are skipped.
+/// * `ignore_keys: HashSet<i32>` - A set of keys to be ignored for every pair.
+/// * `band_indx: isize` - The band of each parameter raster to read.
+/// * `exclude_zero: bool` - If `true`, key `0` (the TOPAZ background) is skipped like an
+///    ignored key. Defaults to `false`.
+/// * `channel_modulus: Option<(i32, i32)>` - The `(divisor, remainder)` identifying channel
+///    keys as `key % divisor == remainder`. Defaults to `None`, which keeps the TOPAZ
+///    convention `(10, 4)` (keys that end in 4).
+///
+/// # Returns
+/// 
+/// `PyResult<Vec<(Option<HashMap<String, i32>>, Option<String>)>>` - One `(result, error)`
+/// tuple per pair, in input order. Exactly one of the two is set.
+#[pyfunction(exclude_zero = "false", channel_modulus = "None")]
+fn identify_mode_batch(
+    py: Python,
+    pairs: Vec<(String, String)>,
+    ignore_channels: bool,
+    ignore_keys: HashSet<i32>,
+    band_indx: isize,
+    exclude_zero: bool,
+    channel_modulus: Option<(i32, i32)>
+) -> PyResult<Vec<(Option<HashMap<String, i32>>, Option<String>)>> {
+    let results: Vec<PyResult<HashMap<String, i32>>> = py.allow_threads(|| {
+        pairs.par_iter()
+            .map(|(key_fn, parameter_fn)| {
+                identify_mode_single_raster_key_rs(key_fn, parameter_fn, ignore_channels, ignore_keys.clone(), band_indx, false, false, None, exclude_zero, channel_modulus)
+            })
+            .collect()
+    });
+
+    Ok(results.into_iter()
+        .map(|result| match result {
+            Ok(modes) => (Some(modes), None),
+            Err(err) => (None, Some(err.to_string())),
+        })
+        .collect())
+}
+
 /// Identify the mode (most common) parameter values across intersecting raster key datasets.
 ///
 /// This function analyzes three raster datasets: two providing keys (`key_fn` and `key2_fn`) and 
@@ -113,9 +466,14 @@ fn identify_mode_single_raster_key(
 /// * `key_fn: &str` - File path to the first raster dataset providing key values.
 /// * `key2_fn: &str` - File path to the second raster dataset providing key values.
 /// * `parameter_fn: &str` - File path to the raster data providing parameter values to calculate the mode for each key pair.
-/// * `ignore_channels: bool` - If `true`, keys that are multiples of 10 are ignored during processing.
+/// * `ignore_channels: bool` - If `true`, channel keys of `key_fn` (see `channel_modulus`) are ignored during processing.
 /// * `mut ignore_keys: HashSet<i32>` - A set of key values to ignore during processing. If a "no data" value is defined in the key raster datasets, it should be added to this set.
 /// * `mut ignore_keys2: HashSet<i32>` - A set of key values to ignore during processing. If a "no data" value is defined in the key2 raster datasets, it should be added to this set.
+/// * `band_indx: isize` - The band of `parameter_fn` to read.
+/// * `resample_if_needed: bool` - If `true`, `key2_fn` and `parameter_fn` are resampled (nearest neighbour) onto the `key_fn` grid when they are not aligned with it. Defaults to `false`.
+/// * `mask_fn: Option<&str>` - An optional boolean raster (e.g. burned cells). When given, only cells where it is nonzero and not "no data" are accumulated. It is resampled (nearest neighbour) onto the `key_fn` grid like the other rasters when `resample_if_needed` is set. Defaults to `None`.
+/// * `exclude_zero: bool` - If `true`, key `0` (the TOPAZ background) is skipped in both `key_fn` and `key2_fn` like an ignored key. Defaults to `false`.
+/// * `channel_modulus: Option<(i32, i32)>` - The `(divisor, remainder)` identifying channel keys as `key % divisor == remainder`. Defaults to `None`, which keeps the TOPAZ convention `(10, 4)` (keys that end in 4).
 ///
 /// # Returns
 /// 
@@ -124,9 +482,9 @@ fn identify_mode_single_raster_key(
 ///
 /// # Errors
 /// 
-/// Returns `Err` if there is a failure reading the raster data from the provided file paths.
-/// Note: In the current implementation using `unwrap()`, the function may panic on errors 
-/// (improvement recommended for production use).
+/// Returns `Err` (a Python `IOError` naming the file) if there is a failure reading the
+/// raster data from the provided file paths, and `Err` (`ValueError`) if the rasters differ
+/// in size.
 ///
 /// # Example
 /// 
@@ -143,13 +501,10 @@ fn identify_mode_single_raster_key(
 ///
 /// # Note
 ///
-/// Ensure that the raster datasets provided via `key_fn`, `key2_fn`, and `parameter_fn` are of 
-/// identical dimensions as the function does not perform dimensionality checks.
-///
-/// # Panics
-///
-/// The function may panic if it is unable to read the raster data from the provided paths.
-#[pyfunction]
+/// The raster datasets provided via `key_fn`, `key2_fn`, and `parameter_fn` must be of
+/// identical dimensions, or pass `resample_if_needed` to align them to the `key_fn` grid;
+/// otherwise a `ValueError` giving both shapes is raised.
+#[pyfunction(resample_if_needed = "false", mask_fn = "None", exclude_zero = "false", channel_modulus = "None")]
 fn identify_mode_intersecting_raster_keys(
     key_fn: &str, 
     key2_fn: &str, 
@@ -157,12 +512,27 @@ fn identify_mode_intersecting_raster_keys(
     ignore_channels: bool,
     mut ignore_keys: HashSet<i32>,
     mut ignore_keys2: HashSet<i32>,
-    band_indx: isize
+    band_indx: isize,
+    resample_if_needed: bool,
+    mask_fn: Option<&str>,
+    exclude_zero: bool,
+    channel_modulus: Option<(i32, i32)>
 ) -> PyResult<HashMap<String, HashMap<String, i32>>> {
+    let channel_modulus = resolve_channel_modulus(channel_modulus)?;
+    if exclude_zero {
+        ignore_keys.insert(BACKGROUND_KEY);
+        ignore_keys2.insert(BACKGROUND_KEY);
+    }
 
-    let key_map: Raster<i32> = Raster::<i32>::read(key_fn).unwrap();
-    let key2_map: Raster<i32> = Raster::<i32>::read(key2_fn).unwrap();
-    let parameter_map: Raster<i32> = Raster::<i32>::read_band(parameter_fn, band_indx).unwrap();
+
+    let key_map: Raster<i32> = read_raster_band(key_fn, 1)?;
+    let key2_map: Raster<i32> = read_raster_band(key2_fn, 1)?;
+    let parameter_map: Raster<i32> = read_raster_band(parameter_fn, band_indx)?;
+    let key2_map = align_to_key(key2_map, &key_map, resample_if_needed, ResampleMethod::Nearest);
+    let parameter_map = align_to_key(parameter_map, &key_map, resample_if_needed, ResampleMethod::Nearest);
+    check_dimensions(key_fn, &key_map, key2_fn, &key2_map)?;
+    check_dimensions(key_fn, &key_map, parameter_fn, &parameter_map)?;
+    let include = read_cell_mask(mask_fn, key_fn, &key_map, resample_if_needed)?;
     
     // Handle no_data values for key_map and key2_map
     if let Some(no_data_value) = key_map.no_data {
@@ -172,37 +542,47 @@ fn identify_mode_intersecting_raster_keys(
         ignore_keys2.insert(no_data_value);
     }
     
-    // Nested HashMap to store count information: key -> key2 -> parameter_value -> count
-    let mut count_d: HashMap<i32, HashMap<i32, HashMap<i32, usize>>> = HashMap::new();
-    
-    // Iterate through corresponding entries in the three rasters
-    for ((key, key2), val) in key_map.data.iter().zip(key2_map.data.iter()).zip(parameter_map.data.iter()) {
-        if ignore_channels && key % 10 == 4 {
-            continue;
-        }
-        
-        if let Some(no_data_value) = parameter_map.no_data {
-            if no_data_value == *val {
-                continue;
+    // Nested HashMap to store count information: key -> key2 -> parameter_value -> count,
+    // counted over chunks of the rasters in parallel
+    let count_d = zonal::fold_cells(key_map.data.len(), HashMap::new,
+        |count_d: &mut HashMap<i32, HashMap<i32, HashMap<i32, usize>>>, indx| {
+            let (key, key2, val) = (key_map.data[indx], key2_map.data[indx], parameter_map.data[indx]);
+            if !include[indx] {
+                return;
             }
-        }
-        
-        if ignore_keys.contains(key) || ignore_keys2.contains(key2) {
-            continue;
-        }
-        
-        // Increment the count for the current key, key2, and parameter value
-        *count_d.entry(*key).or_insert_with(HashMap::new)
-            .entry(*key2).or_insert_with(HashMap::new)
-            .entry(*val).or_insert(0) += 1;
-    }
+
+            if ignore_channels && zonal::is_channel_by(key, channel_modulus) {
+                return;
+            }
+
+            if parameter_map.no_data == Some(val) {
+                return;
+            }
+
+            if ignore_keys.contains(&key) || ignore_keys2.contains(&key2) {
+                return;
+            }
+
+            // Increment the count for the current key, key2, and parameter value
+            *count_d.entry(key).or_default()
+                .entry(key2).or_default()
+                .entry(val).or_insert(0) += 1;
+        },
+        |mut count_d, other| {
+            for (key, other_counts) in other {
+                let counts = zonal::merge_counts(count_d.remove(&key).unwrap_or_default(), other_counts);
+                count_d.insert(key, counts);
+            }
+            count_d
+        });
     
     // Determine the mode value for each key, key2 pair
     let mut result: HashMap<String, HashMap<String, i32>> = HashMap::new();
     for (key, sub_map) in &count_d {
         let mut key2_mode_map: HashMap<String, i32> = HashMap::new();
         for (key2, val_count_map) in sub_map {
-            if let Some((&val, &_count)) = val_count_map.iter().max_by_key(|&(_, count)| count) {
+            // ties go to the smallest value so the result does not depend on hash order
+            if let Some((&val, &_count)) = val_count_map.iter().max_by(|a, b| a.1.cmp(b.1).then(b.0.cmp(a.0))) {
                 key2_mode_map.insert(key2.to_string(), val);
             }
         }
@@ -212,6 +592,129 @@ fn identify_mode_intersecting_raster_keys(
     Ok(result)
 }
 
+/// Identify the mode parameter value of each intersecting triple of raster keys.
+///
+/// Like `identify_mode_intersecting_raster_keys` with a third key raster, e.g. grouping a
+/// parameter by subcatchment (`key_fn`), landuse (`key2_fn`), and soil (`key3_fn`) in one
+/// pass instead of chaining two calls.
+///
+/// # Arguments
+///
+/// * `key_fn: &str` - File path to the first raster dataset providing key values.
+/// * `key2_fn: &str` - File path to the second raster dataset providing key values.
+/// * `key3_fn: &str` - File path to the third raster dataset providing key values.
+/// * `parameter_fn: &str` - File path to the raster data providing parameter values.
+/// * `ignore_channels: bool` - If `true`, channel keys of `key_fn` (see `channel_modulus`) are ignored.
+/// * `ignore_keys: HashSet<i32>` - Keys of `key_fn` to ignore.
+/// * `ignore_keys2: HashSet<i32>` - Keys of `key2_fn` to ignore.
+/// * `ignore_keys3: HashSet<i32>` - Keys of `key3_fn` to ignore.
+/// * `band_indx: isize` - The band of `parameter_fn` to read.
+/// * `exclude_zero: bool` - If `true`, key `0` (the TOPAZ background) is skipped in all three
+///    key rasters like an ignored key. Defaults to `false`.
+/// * `channel_modulus: Option<(i32, i32)>` - The `(divisor, remainder)` identifying channel keys
+///    as `key % divisor == remainder`. Defaults to `None`, which keeps the TOPAZ convention
+///    `(10, 4)` (keys that end in 4).
+///
+/// The "no data" value of each key raster is always ignored for that raster.
+///
+/// # Returns
+///
+/// `PyResult<HashMap<String, HashMap<String, HashMap<String, i32>>>>` - key -> key2 -> key3 ->
+/// the mode parameter value of that triple. Ties go to the smallest parameter value.
+///
+/// # Errors
+///
+/// Returns `Err` (a Python `IOError` naming the file) if a raster cannot be read, and `Err`
+/// (`ValueError`) if the rasters differ in size.
+#[pyfunction(exclude_zero = "false", channel_modulus = "None")]
+fn identify_mode_three_raster_keys(
+    key_fn: &str,
+    key2_fn: &str,
+    key3_fn: &str,
+    parameter_fn: &str,
+    ignore_channels: bool,
+    mut ignore_keys: HashSet<i32>,
+    mut ignore_keys2: HashSet<i32>,
+    mut ignore_keys3: HashSet<i32>,
+    band_indx: isize,
+    exclude_zero: bool,
+    channel_modulus: Option<(i32, i32)>
+) -> PyResult<HashMap<String, HashMap<String, HashMap<String, i32>>>> {
+    let channel_modulus = resolve_channel_modulus(channel_modulus)?;
+    if exclude_zero {
+        ignore_keys.insert(BACKGROUND_KEY);
+        ignore_keys2.insert(BACKGROUND_KEY);
+        ignore_keys3.insert(BACKGROUND_KEY);
+    }
+
+    let key_map: Raster<i32> = read_raster_band(key_fn, 1)?;
+    let key2_map: Raster<i32> = read_raster_band(key2_fn, 1)?;
+    let key3_map: Raster<i32> = read_raster_band(key3_fn, 1)?;
+    let parameter_map: Raster<i32> = read_raster_band(parameter_fn, band_indx)?;
+    check_dimensions(key_fn, &key_map, key2_fn, &key2_map)?;
+    check_dimensions(key_fn, &key_map, key3_fn, &key3_map)?;
+    check_dimensions(key_fn, &key_map, parameter_fn, &parameter_map)?;
+
+    if let Some(no_data_value) = key_map.no_data {
+        ignore_keys.insert(no_data_value);
+    }
+    if let Some(no_data_value) = key2_map.no_data {
+        ignore_keys2.insert(no_data_value);
+    }
+    if let Some(no_data_value) = key3_map.no_data {
+        ignore_keys3.insert(no_data_value);
+    }
+
+    // key -> key2 -> key3 -> parameter_value -> count, counted over chunks of the
+    // rasters in parallel
+    let count_d = zonal::fold_cells(key_map.data.len(), HashMap::new,
+        |count_d: &mut HashMap<i32, HashMap<i32, HashMap<i32, HashMap<i32, usize>>>>, indx| {
+            let (key, key2, key3) = (key_map.data[indx], key2_map.data[indx], key3_map.data[indx]);
+            let val = parameter_map.data[indx];
+            if ignore_channels && zonal::is_channel_by(key, channel_modulus) {
+                return;
+            }
+
+            if parameter_map.no_data == Some(val) {
+                return;
+            }
+
+            if ignore_keys.contains(&key) || ignore_keys2.contains(&key2) || ignore_keys3.contains(&key3) {
+                return;
+            }
+
+            *count_d.entry(key).or_default()
+                .entry(key2).or_default()
+                .entry(key3).or_default()
+                .entry(val).or_insert(0) += 1;
+        },
+        |mut count_d, other| {
+            for (key, other_key2_counts) in other {
+                let key2_counts = count_d.entry(key).or_default();
+                for (key2, other_counts) in other_key2_counts {
+                    let counts = zonal::merge_counts(key2_counts.remove(&key2).unwrap_or_default(), other_counts);
+                    key2_counts.insert(key2, counts);
+                }
+            }
+            count_d
+        });
+
+    let mut result: HashMap<String, HashMap<String, HashMap<String, i32>>> = HashMap::new();
+    for (key, key2_counts) in &count_d {
+        let key2_map = result.entry(key.to_string()).or_default();
+        for (key2, key3_counts) in key2_counts {
+            let key3_map = key2_map.entry(key2.to_string()).or_default();
+            for (key3, val_count_map) in key3_counts {
+                if let Some((&val, &_count)) = val_count_map.iter().max_by(|a, b| a.1.cmp(b.1).then(b.0.cmp(a.0))) {
+                    key3_map.insert(key3.to_string(), val);
+                }
+            }
+        }
+    }
+
+    Ok(result)
+}
+
 /// Identify the median value of each key in a raster dataset.
 ///
 /// Given the file paths to two raster datasets, `key_fn` and `parameter_fn`, this function 
@@ -224,9 +727,23 @@ fn identify_mode_intersecting_raster_keys(
 /// 
 /// * `key_fn: &str` - The file path to the raster data to be used as keys.
 /// * `parameter_fn: &str` - The file path to the raster data to determine the mode value for each key.
-/// * `ignore_channels: bool` - If `true`, keys that end in 4.
+/// * `ignore_channels: bool` - If `true`, channel keys (see `channel_modulus`) are skipped.
 /// * `mut ignore_keys: HashSet<i32>` - A set of keys to be ignored during processing. If a "no data" 
 ///    value is defined in `key_map`, it is automatically added to this set.
+/// * `band_indx: isize` - The band of `parameter_fn` to read.
+/// * `resample_if_needed: bool` - If `true` and the parameter raster is not aligned with the key
+///    raster, it is first resampled onto the key grid (bilinear). Defaults to `false`.
+/// * `infer_nodata_by_map_type: bool` - If `true` and `key_fn` has no "no data" value, the
+///    default for its map type is used instead (`0` for `SUBWTA`). Defaults to `false`.
+/// * `mask_fn: Option<&str>` - An optional boolean raster (e.g. burned cells). When given, only
+///    cells where it is nonzero and not "no data" are accumulated. It is resampled (nearest
+///    neighbour) onto the key grid like the other rasters when `resample_if_needed` is set.
+///    Defaults to `None`.
+/// * `exclude_zero: bool` - If `true`, key `0` (the TOPAZ background) is skipped like an
+///    ignored key. Defaults to `false`.
+/// * `channel_modulus: Option<(i32, i32)>` - The `(divisor, remainder)` identifying channel
+///    keys as `key % divisor == remainder`. Defaults to `None`, which keeps the TOPAZ
+///    convention `(10, 4)` (keys that end in 4).
 ///
 /// # Returns
 /// 
@@ -236,9 +753,9 @@ fn identify_mode_intersecting_raster_keys(
 ///
 /// # Errors
 /// 
-/// Returns `Err` if there is a failure reading the raster data from the provided file paths.
-/// Note: The current implementation uses `unwrap()` which may cause panics on errors 
-/// (to be improved for production use).
+/// Returns `Err` (a Python `IOError` naming the file) if there is a failure reading the
+/// raster data from the provided file paths, and `Err` (`ValueError`) if the rasters differ
+/// in size.
 ///
 /// # Example
 /// 
@@ -254,120 +771,294 @@ fn identify_mode_intersecting_raster_keys(
 ///
 /// # Note
 ///
-/// Ensure that the raster datasets provided via `key_fn` and `parameter_fn` are of 
-/// identical dimensions, as the function does not perform dimensionality checks.
-///
-/// # Panics
-///
-/// The function may panic if it is unable to read the raster data from the provided paths.
-#[pyfunction]
+/// The raster datasets provided via `key_fn` and `parameter_fn` must be of identical
+/// dimensions, or pass `resample_if_needed` to align the parameter raster to the key
+/// raster's grid; otherwise a `ValueError` giving both shapes is raised.
+#[pyfunction(resample_if_needed = "false", infer_nodata_by_map_type = "false", mask_fn = "None", exclude_zero = "false", channel_modulus = "None")]
 fn identify_median_single_raster_key(
     key_fn: &str,
     parameter_fn: &str,
     ignore_channels: bool,
     mut ignore_keys: HashSet<i32>,
-    band_indx: isize
+    band_indx: isize,
+    resample_if_needed: bool,
+    infer_nodata_by_map_type: bool,
+    mask_fn: Option<&str>,
+    exclude_zero: bool,
+    channel_modulus: Option<(i32, i32)>
 ) -> PyResult<HashMap<String, f64>> {
-    let key_map: Raster<i32> = Raster::<i32>::read(key_fn).unwrap();
-    let parameter_map: Raster<f64> = Raster::<f64>::read_band(parameter_fn, band_indx).unwrap();
-
-    if let Some(no_data_value) = key_map.no_data {
-        ignore_keys.insert(no_data_value);
-    }
-
-    let mut values_d: HashMap<i32, Vec<f64>> = HashMap::new();
-
-    for (key, &val) in key_map.data.iter().zip(parameter_map.data.iter()) {
-        if ignore_channels && key % 10 == 4 {
-            continue;
-        }
-
-        if let Some(no_data_value) = parameter_map.no_data {
-            if (no_data_value - val).abs() < std::f64::EPSILON {
-                continue;
-            }
-        }
-
-        if ignore_keys.contains(key) {
-            continue;
-        }
-
-        values_d.entry(*key).or_insert_with(Vec::new).push(val);
+    let channel_modulus = resolve_channel_filter(ignore_channels, channel_modulus)?;
+    if exclude_zero {
+        ignore_keys.insert(BACKGROUND_KEY);
     }
 
-    let mut result: HashMap<String, f64> = HashMap::new();
-    for (key, values) in values_d {
-        let median = calculate_median(values);
-        result.insert(key.to_string(), median);
-    }
+    let key_map: Raster<i32> = read_raster_band_with_options(key_fn, 1, infer_nodata_by_map_type)?;
+    let parameter_map: Raster<f64> = read_raster_band(parameter_fn, band_indx)?;
+    let parameter_map = align_to_key(parameter_map, &key_map, resample_if_needed, ResampleMethod::Bilinear);
+    check_dimensions(key_fn, &key_map, parameter_fn, &parameter_map)?;
+    let include = read_cell_mask(mask_fn, key_fn, &key_map, resample_if_needed)?;
 
-    Ok(result)
+    Ok(zonal::median_by_key(&key_map, &parameter_map, Some(&include), channel_modulus, &ignore_keys)
+        .into_iter()
+        .map(|(key, median)| (key.to_string(), median))
+        .collect())
 }
 
 
-/// Identify the median  parameter values across intersecting raster key datasets.
+/// Compute a chosen statistic of the parameter values of each key.
 ///
-/// This function analyzes three raster datasets: two providing keys (`key_fn` and `key2_fn`) and 
-/// one providing parameter values (`parameter_fn`). For each intersecting key pair (from `key_fn` 
-/// and `key2_fn`), it determines the median value from `parameter_fn`, excluding specified 
-/// keys and/or designated "no data" values. The resulting mode values are returned in a nested 
-/// HashMap where each entry associates a key from `key_fn` with a HashMap. This inner HashMap, in turn, 
-/// associates keys from `key2_fn` with their respective mode values.
+/// The parameter values of each key are collected once and `stat` is computed from them,
+/// so one function covers the summaries of `identify_mode_single_raster_key` and
+/// `identify_median_single_raster_key` and more. Unlike the mode function, `"mode"` here
+/// reads the parameter as floats.
 ///
 /// # Arguments
-/// 
-/// * `key_fn: &str` - File path to the first raster dataset providing key values.
-/// * `key2_fn: &str` - File path to the second raster dataset providing key values.
-/// * `parameter_fn: &str` - File path to the raster data providing parameter values to calculate the mode for each key pair.
-/// * `ignore_channels: bool` - If `true`, keys that are multiples of 10 are ignored during processing.
-/// * `mut ignore_keys: HashSet<i32>` - A set of key values to ignore during processing. If a "no data" value is defined in the key raster datasets, it should be added to this set.
-/// * `mut ignore_keys2: HashSet<i32>` - A set of key values to ignore during processing. If a "no data" value is defined in the key2 raster datasets, it should be added to this set.
 ///
-/// # Returns
-/// 
-/// `PyResult<HashMap<String, HashMap<String, f64>>>` - A nested HashMap where each entry associates a key from `key_fn` 
-/// with another HashMap. This inner HashMap associates keys from `key2_fn` with the mode parameter value for that key pair.
-///
-/// # Errors
-/// 
-/// Returns `Err` if there is a failure reading the raster data from the provided file paths.
-/// Note: In the current implementation using `unwrap()`, the function may panic on errors 
-/// (improvement recommended for production use).
-///
-/// # Example
-/// 
-/// ```
-/// let key_fn = "path/to/key_map.tif";
-/// let key2_fn = "path/to/key2_map.tif";
-/// let parameter_fn = "path/to/parameter_map.tif";
-/// let ignore_channels = false;
-/// let mut ignore_keys = HashSet::new();
-/// ignore_keys.insert(-9999);
-/// 
-/// let result = identify_mode_intersecting_raster_keys(key_fn, key2_fn, parameter_fn, ignore_channels, ignore_keys);
-/// ```
+/// * `key_fn: &str` - The file path to the raster data to be used as keys.
+/// * `parameter_fn: &str` - The file path to the raster data to summarize for each key.
+/// * `stat: &str` - One of `"count"`, `"sum"`, `"mean"`, `"min"`, `"max"`, `"median"`,
+///    `"mode"`, and `"std"` (population standard deviation).
+/// * `ignore_channels: bool` - If `true`, channel keys (see `channel_modulus`) are skipped.
+/// * `ignore_keys: HashSet<i32>` - Keys to skip. The key raster's "no data" value is always
+///    skipped.
+/// * `band_indx: isize` - The band of `parameter_fn` to read.
+/// * `exclude_zero: bool` - If `true`, key `0` (the TOPAZ background) is skipped like an
+///    ignored key. Defaults to `false`.
+/// * `channel_modulus: Option<(i32, i32)>` - The `(divisor, remainder)` identifying channel
+///    keys as `key % divisor == remainder`. Defaults to `None`, which keeps the TOPAZ
+///    convention `(10, 4)` (keys that end in 4).
 ///
-/// # Note
+/// # Returns
 ///
-/// Ensure that the raster datasets provided via `key_fn`, `key2_fn`, and `parameter_fn` are of 
-/// identical dimensions as the function does not perform dimensionality checks.
+/// `PyResult<HashMap<String, f64>>` - The statistic of each key with at least one valid
+/// parameter value.
 ///
-/// # Panics
+/// # Errors
 ///
-/// The function may panic if it is unable to read the raster data from the provided paths.
-#[pyfunction]
-fn identify_median_intersecting_raster_keys(
+/// Returns `Err` (`ValueError`) if `stat` is unknown or the rasters differ in size, and `Err`
+/// (`IOError`) if a raster cannot be read.
+#[pyfunction(exclude_zero = "false", channel_modulus = "None")]
+fn identify_stat_single_raster_key(
     key_fn: &str,
-    key2_fn: &str,
     parameter_fn: &str,
+    stat: &str,
     ignore_channels: bool,
     mut ignore_keys: HashSet<i32>,
-    mut ignore_keys2: HashSet<i32>,
-    band_indx: isize
-) -> PyResult<HashMap<String, HashMap<String, f64>>> {
-    let key_map: Raster<i32> = Raster::<i32>::read(key_fn).unwrap();
-    let key2_map: Raster<i32> = Raster::<i32>::read(key2_fn).unwrap();
-    let parameter_map: Raster<f64> = Raster::<f64>::read_band(parameter_fn, band_indx).unwrap();
+    band_indx: isize,
+    exclude_zero: bool,
+    channel_modulus: Option<(i32, i32)>
+) -> PyResult<HashMap<String, f64>> {
+    let channel_modulus = resolve_channel_filter(ignore_channels, channel_modulus)?;
+    let zonal_stat = parse_zonal_stat(stat)?;
+    if exclude_zero {
+        ignore_keys.insert(BACKGROUND_KEY);
+    }
+
+    let key_map: Raster<i32> = read_raster_band(key_fn, 1)?;
+    let parameter_map: Raster<f64> = read_raster_band(parameter_fn, band_indx)?;
+    check_dimensions(key_fn, &key_map, parameter_fn, &parameter_map)?;
+
+    Ok(zonal::stat_by_key(&key_map, &parameter_map, zonal_stat, None, channel_modulus, &ignore_keys)
+        .into_iter()
+        .map(|(key, value)| (key.to_string(), value))
+        .collect())
+}
+
+/// Compute a percentile of the parameter values of each key.
+///
+/// Uses linear interpolation between the closest ranks, matching numpy's default
+/// ("linear") method, so `percentile = 50` equals `identify_median_single_raster_key`.
+///
+/// # Arguments
+///
+/// * `key_fn: &str` - The file path to the raster data to be used as keys.
+/// * `parameter_fn: &str` - The file path to the raster data to summarize for each key.
+/// * `percentile: f64` - The percentile in `[0, 100]`, e.g. `10.0` or `90.0`.
+/// * `ignore_channels: bool` - If `true`, channel keys (see `channel_modulus`) are skipped.
+/// * `ignore_keys: HashSet<i32>` - Keys to skip. The key raster's "no data" value is always
+///    skipped.
+/// * `band_indx: isize` - The band of `parameter_fn` to read.
+/// * `exclude_zero: bool` - If `true`, key `0` (the TOPAZ background) is skipped like an
+///    ignored key. Defaults to `false`.
+/// * `channel_modulus: Option<(i32, i32)>` - The `(divisor, remainder)` identifying channel
+///    keys as `key % divisor == remainder`. Defaults to `None`, which keeps the TOPAZ
+///    convention `(10, 4)` (keys that end in 4).
+///
+/// # Returns
+///
+/// `PyResult<HashMap<String, f64>>` - The percentile of each key. Keys without valid
+/// parameter values are omitted.
+///
+/// # Errors
+///
+/// Returns `Err` (`ValueError`) if `percentile` is outside `[0, 100]` or the rasters differ
+/// in size, and `Err` (`IOError`) if a raster cannot be read.
+#[pyfunction(exclude_zero = "false", channel_modulus = "None")]
+fn identify_percentile_single_raster_key(
+    key_fn: &str,
+    parameter_fn: &str,
+    percentile: f64,
+    ignore_channels: bool,
+    mut ignore_keys: HashSet<i32>,
+    band_indx: isize,
+    exclude_zero: bool,
+    channel_modulus: Option<(i32, i32)>
+) -> PyResult<HashMap<String, f64>> {
+    let channel_modulus = resolve_channel_filter(ignore_channels, channel_modulus)?;
+    if !(0.0..=100.0).contains(&percentile) {
+        return Err(pyo3::exceptions::PyValueError::new_err(format!(
+            "percentile {} is outside [0, 100]", percentile)));
+    }
+    if exclude_zero {
+        ignore_keys.insert(BACKGROUND_KEY);
+    }
+
+    let key_map: Raster<i32> = read_raster_band(key_fn, 1)?;
+    let parameter_map: Raster<f64> = read_raster_band(parameter_fn, band_indx)?;
+    check_dimensions(key_fn, &key_map, parameter_fn, &parameter_map)?;
+
+    Ok(zonal::percentile_by_key(&key_map, &parameter_map, percentile, None, channel_modulus, &ignore_keys)
+        .into_iter()
+        .map(|(key, value)| (key.to_string(), value))
+        .collect())
+}
+
+/// Compute the area-weighted sum of the parameter values of each key.
+///
+/// Each key's sum is multiplied by the cell area, `cellsize * cellsize` of the parameter
+/// raster, e.g. a per-cell mass density in kg/m² becomes a total mass in kg. This assumes a
+/// projected CRS with square pixels in linear units; in a geographic CRS the cell area is in
+/// square degrees and varies with latitude.
+///
+/// # Arguments
+///
+/// * `key_fn: &str` - The file path to the raster data to be used as keys.
+/// * `parameter_fn: &str` - The file path to the raster data to total for each key.
+/// * `ignore_channels: bool` - If `true`, channel keys (see `channel_modulus`) are skipped.
+/// * `ignore_keys: HashSet<i32>` - Keys to skip. The key raster's "no data" value is always
+///    skipped.
+/// * `band_indx: isize` - The band of `parameter_fn` to read.
+/// * `exclude_zero: bool` - If `true`, key `0` (the TOPAZ background) is skipped like an
+///    ignored key. Defaults to `false`.
+/// * `channel_modulus: Option<(i32, i32)>` - The `(divisor, remainder)` identifying channel
+///    keys as `key % divisor == remainder`. Defaults to `None`, which keeps the TOPAZ
+///    convention `(10, 4)` (keys that end in 4).
+///
+/// # Returns
+///
+/// `PyResult<HashMap<String, f64>>` - The area-weighted sum of each key with at least one
+/// valid parameter value.
+///
+/// # Errors
+///
+/// Returns `Err` (`ValueError`) if the rasters differ in size, and `Err` (`IOError`) if a
+/// raster cannot be read.
+#[pyfunction(exclude_zero = "false", channel_modulus = "None")]
+fn identify_sum_area_weighted_single_raster_key(
+    key_fn: &str,
+    parameter_fn: &str,
+    ignore_channels: bool,
+    mut ignore_keys: HashSet<i32>,
+    band_indx: isize,
+    exclude_zero: bool,
+    channel_modulus: Option<(i32, i32)>
+) -> PyResult<HashMap<String, f64>> {
+    let channel_modulus = resolve_channel_filter(ignore_channels, channel_modulus)?;
+    if exclude_zero {
+        ignore_keys.insert(BACKGROUND_KEY);
+    }
+
+    let key_map: Raster<i32> = read_raster_band(key_fn, 1)?;
+    let parameter_map: Raster<f64> = read_raster_band(parameter_fn, band_indx)?;
+    check_dimensions(key_fn, &key_map, parameter_fn, &parameter_map)?;
+
+    let cell_area = parameter_map.cellsize * parameter_map.cellsize;
+    Ok(zonal::stat_by_key(&key_map, &parameter_map, ZonalStat::Sum, None, channel_modulus, &ignore_keys)
+        .into_iter()
+        .map(|(key, sum)| (key.to_string(), sum * cell_area))
+        .collect())
+}
+
+/// Identify the median  parameter values across intersecting raster key datasets.
+///
+/// This function analyzes three raster datasets: two providing keys (`key_fn` and `key2_fn`) and 
+/// one providing parameter values (`parameter_fn`). For each intersecting key pair (from `key_fn` 
+/// and `key2_fn`), it determines the median value from `parameter_fn`, excluding specified 
+/// keys and/or designated "no data" values. The resulting mode values are returned in a nested 
+/// HashMap where each entry associates a key from `key_fn` with a HashMap. This inner HashMap, in turn, 
+/// associates keys from `key2_fn` with their respective mode values.
+///
+/// # Arguments
+/// 
+/// * `key_fn: &str` - File path to the first raster dataset providing key values.
+/// * `key2_fn: &str` - File path to the second raster dataset providing key values.
+/// * `parameter_fn: &str` - File path to the raster data providing parameter values to calculate the mode for each key pair.
+/// * `ignore_channels: bool` - If `true`, channel keys of `key_fn` (see `channel_modulus`) are ignored during processing.
+/// * `mut ignore_keys: HashSet<i32>` - A set of key values to ignore during processing. If a "no data" value is defined in the key raster datasets, it should be added to this set.
+/// * `mut ignore_keys2: HashSet<i32>` - A set of key values to ignore during processing. If a "no data" value is defined in the key2 raster datasets, it should be added to this set.
+/// * `band_indx: isize` - The band of `parameter_fn` to read.
+/// * `resample_if_needed: bool` - If `true`, `key2_fn` (nearest neighbour) and `parameter_fn` (bilinear) are resampled onto the `key_fn` grid when they are not aligned with it. Defaults to `false`.
+/// * `mask_fn: Option<&str>` - An optional boolean raster (e.g. burned cells). When given, only cells where it is nonzero and not "no data" are accumulated. It is resampled (nearest neighbour) onto the `key_fn` grid like the other rasters when `resample_if_needed` is set. Defaults to `None`.
+/// * `exclude_zero: bool` - If `true`, key `0` (the TOPAZ background) is skipped in both `key_fn` and `key2_fn` like an ignored key. Defaults to `false`.
+/// * `channel_modulus: Option<(i32, i32)>` - The `(divisor, remainder)` identifying channel keys as `key % divisor == remainder`. Defaults to `None`, which keeps the TOPAZ convention `(10, 4)` (keys that end in 4).
+///
+/// # Returns
+/// 
+/// `PyResult<HashMap<String, HashMap<String, f64>>>` - A nested HashMap where each entry associates a key from `key_fn` 
+/// with another HashMap. This inner HashMap associates keys from `key2_fn` with the mode parameter value for that key pair.
+///
+/// # Errors
+/// 
+/// Returns `Err` (a Python `IOError` naming the file) if there is a failure reading the
+/// raster data from the provided file paths, and `Err` (`ValueError`) if the rasters differ
+/// in size.
+///
+/// # Example
+/// 
+/// ```
+/// let key_fn = "path/to/key_map.tif";
+/// let key2_fn = "path/to/key2_map.tif";
+/// let parameter_fn = "path/to/parameter_map.tif";
+/// let ignore_channels = false;
+/// let mut ignore_keys = HashSet::new();
+/// ignore_keys.insert(-9999);
+/// 
+/// let result = identify_mode_intersecting_raster_keys(key_fn, key2_fn, parameter_fn, ignore_channels, ignore_keys);
+/// ```
+///
+/// # Note
+///
+/// The raster datasets provided via `key_fn`, `key2_fn`, and `parameter_fn` must be of
+/// identical dimensions, or pass `resample_if_needed` to align them to the `key_fn` grid;
+/// otherwise a `ValueError` giving both shapes is raised.
+#[pyfunction(resample_if_needed = "false", mask_fn = "None", exclude_zero = "false", channel_modulus = "None")]
+fn identify_median_intersecting_raster_keys(
+    key_fn: &str,
+    key2_fn: &str,
+    parameter_fn: &str,
+    ignore_channels: bool,
+    mut ignore_keys: HashSet<i32>,
+    mut ignore_keys2: HashSet<i32>,
+    band_indx: isize,
+    resample_if_needed: bool,
+    mask_fn: Option<&str>,
+    exclude_zero: bool,
+    channel_modulus: Option<(i32, i32)>
+) -> PyResult<HashMap<String, HashMap<String, f64>>> {
+    let channel_modulus = resolve_channel_modulus(channel_modulus)?;
+    if exclude_zero {
+        ignore_keys.insert(BACKGROUND_KEY);
+        ignore_keys2.insert(BACKGROUND_KEY);
+    }
+
+    let key_map: Raster<i32> = read_raster_band(key_fn, 1)?;
+    let key2_map: Raster<i32> = read_raster_band(key2_fn, 1)?;
+    let parameter_map: Raster<f64> = read_raster_band(parameter_fn, band_indx)?;
+    let key2_map = align_to_key(key2_map, &key_map, resample_if_needed, ResampleMethod::Nearest);
+    let parameter_map = align_to_key(parameter_map, &key_map, resample_if_needed, ResampleMethod::Bilinear);
+    check_dimensions(key_fn, &key_map, key2_fn, &key2_map)?;
+    check_dimensions(key_fn, &key_map, parameter_fn, &parameter_map)?;
+    let include = read_cell_mask(mask_fn, key_fn, &key_map, resample_if_needed)?;
 
     if let Some(no_data_value) = key_map.no_data {
         ignore_keys.insert(no_data_value);
@@ -376,35 +1067,46 @@ fn identify_median_intersecting_raster_keys(
         ignore_keys2.insert(no_data_value);
     }
 
-    // Nested HashMap to store value information: key -> key2 -> parameter_values
-    let mut values_d: HashMap<i32, HashMap<i32, Vec<f64>>> = HashMap::new();
+    // Nested HashMap to store value information: key -> key2 -> parameter_values,
+    // collected over chunks of the rasters in parallel
+    let values_d = zonal::fold_cells(key_map.data.len(), HashMap::new,
+        |values_d: &mut HashMap<i32, HashMap<i32, Vec<f64>>>, indx| {
+            let (key, key2, val) = (key_map.data[indx], key2_map.data[indx], parameter_map.data[indx]);
+            if !include[indx] {
+                return;
+            }
 
-    for ((key, key2), &val) in key_map.data.iter().zip(key2_map.data.iter()).zip(parameter_map.data.iter()) {
-        if ignore_channels && key % 10 == 4 {
-            continue;
-        }
+            if ignore_channels && zonal::is_channel_by(key, channel_modulus) {
+                return;
+            }
 
-        if let Some(no_data_value) = parameter_map.no_data {
-            if (no_data_value - val).abs() < std::f64::EPSILON {
-                continue;
+            if zonal::is_parameter_no_data(parameter_map.no_data, val) {
+                return;
             }
-        }
 
-        if ignore_keys.contains(key) || ignore_keys2.contains(key2) {
-            continue;
-        }
+            if ignore_keys.contains(&key) || ignore_keys2.contains(&key2) {
+                return;
+            }
 
-        values_d.entry(*key).or_insert_with(HashMap::new)
-            .entry(*key2).or_insert_with(Vec::new).push(val);
-    }
+            values_d.entry(key).or_default()
+                .entry(key2).or_default().push(val);
+        },
+        |mut values_d, other| {
+            for (key, other_values) in other {
+                let values = zonal::merge_values(values_d.remove(&key).unwrap_or_default(), other_values);
+                values_d.insert(key, values);
+            }
+            values_d
+        });
 
     // Compute the median value for each key, key2 pair
     let mut result: HashMap<String, HashMap<String, f64>> = HashMap::new();
     for (key, sub_map) in values_d {
         let mut key2_median_map: HashMap<String, f64> = HashMap::new();
         for (key2, values) in sub_map {
-            let median = calculate_median(values);
-            key2_median_map.insert(key2.to_string(), median);
+            if let Some(median) = zonal::median(values) {
+                key2_median_map.insert(key2.to_string(), median);
+            }
         }
         result.insert(key.to_string(), key2_median_map);
     }
@@ -413,13 +1115,1721 @@ fn identify_median_intersecting_raster_keys(
 }
 
 
-fn calculate_median(mut values: Vec<f64>) -> f64 {
-    values.sort_by(|a, b| a.partial_cmp(b).unwrap_or(std::cmp::Ordering::Equal));
-    let len = values.len();
-    if len % 2 == 1 {
-        values[len / 2]
-    } else {
-        (values[(len - 1) / 2] + values[len / 2]) / 2.0
+/// Compute per-key statistics and write them to a JSON file.
+///
+/// The file holds a `meta` object describing the inputs and a `stats` object mapping each
+/// key to its statistics:
+///
+/// ```text
+/// {"meta": {"key_fn": ..., "parameter_fn": ..., "band_indx": 1, "cellsize": 30.0,
+///           "key_no_data": 0, "parameter_no_data": -9999.0},
+///  "stats": {"22": {"mean": 12.5, "count": 40.0}, ...}}
+/// ```
+///
+/// # Arguments
+///
+/// * `key_fn: &str` - The file path to the raster data to be used as keys.
+/// * `parameter_fn: &str` - The file path to the raster data to summarize for each key.
+/// * `out_json: &str` - The file path of the JSON file to write.
+/// * `stats: Vec<String>` - Any of `"count"`, `"sum"`, `"mean"`, `"min"`, `"max"`,
+///    `"median"`, `"mode"`, and `"std"`.
+/// * `ignore_channels: bool` - If `true`, channel keys (see `channel_modulus`) are skipped. Defaults to `false`.
+/// * `ignore_keys: Option<HashSet<i32>>` - Keys to skip. The key raster's "no data" value is
+///    always skipped. Defaults to `None`.
+/// * `band_indx: isize` - The band of `parameter_fn` to read. Defaults to `1`.
+/// * `exclude_zero: bool` - If `true`, key `0` (the TOPAZ background) is skipped like an
+///    ignored key. Defaults to `false`.
+/// * `channel_modulus: Option<(i32, i32)>` - The `(divisor, remainder)` identifying channel
+///    keys as `key % divisor == remainder`. Defaults to `None`, which keeps the TOPAZ
+///    convention `(10, 4)` (keys that end in 4).
+///
+/// # Returns
+///
+/// `PyResult<usize>` - The number of keys written.
+///
+/// # Errors
+///
+/// Returns `Err` if a statistic is unknown or the rasters differ in size (`ValueError`), or a
+/// raster cannot be read or the JSON cannot be written (`IOError`).
+#[pyfunction(ignore_channels = "false", ignore_keys = "None", band_indx = "1", exclude_zero = "false", channel_modulus = "None")]
+fn identify_stats_single_raster_key_json(
+    key_fn: &str,
+    parameter_fn: &str,
+    out_json: &str,
+    stats: Vec<String>,
+    ignore_channels: bool,
+    ignore_keys: Option<HashSet<i32>>,
+    band_indx: isize,
+    exclude_zero: bool,
+    channel_modulus: Option<(i32, i32)>
+) -> PyResult<usize> {
+    let channel_modulus = resolve_channel_filter(ignore_channels, channel_modulus)?;
+    let zonal_stats: Vec<(String, ZonalStat)> = stats.into_iter()
+        .map(|name| parse_zonal_stat(&name).map(|stat| (name, stat)))
+        .collect::<PyResult<_>>()?;
+
+    let key_map: Raster<i32> = read_raster_band(key_fn, 1)?;
+    let parameter_map: Raster<f64> = read_raster_band(parameter_fn, band_indx)?;
+    check_dimensions(key_fn, &key_map, parameter_fn, &parameter_map)?;
+
+    let mut ignore_keys = ignore_keys.unwrap_or_default();
+    if exclude_zero {
+        ignore_keys.insert(BACKGROUND_KEY);
+    }
+    let values_d = zonal::values_by_key(&key_map, &parameter_map, None, channel_modulus, &ignore_keys);
+
+    let mut stats_json = serde_json::Map::new();
+    for (key, values) in &values_d {
+        let key_stats: serde_json::Map<String, serde_json::Value> = zonal_stats.iter()
+            .map(|(name, stat)| (name.clone(), serde_json::json!(stat.compute(values))))
+            .collect();
+        stats_json.insert(key.to_string(), serde_json::Value::Object(key_stats));
+    }
+
+    let document = serde_json::json!({
+        "meta": {
+            "key_fn": key_fn,
+            "parameter_fn": parameter_fn,
+            "band_indx": band_indx,
+            "cellsize": key_map.cellsize,
+            "key_no_data": key_map.no_data,
+            "parameter_no_data": parameter_map.no_data,
+        },
+        "stats": stats_json,
+    });
+
+    let io_err = |e: std::io::Error| pyo3::exceptions::PyIOError::new_err(format!("{}: {}", out_json, e));
+    let file = std::fs::File::create(out_json).map_err(io_err)?;
+    serde_json::to_writer(std::io::BufWriter::new(file), &document)
+        .map_err(|e| io_err(e.into()))?;
+
+    Ok(values_d.len())
+}
+
+
+/// Parses the polygons of a GeoJSON `Polygon`, `MultiPolygon`, `Feature`, or
+/// `FeatureCollection` (the union of its features) into `Polygon`s.
+fn parse_geojson_polygons(geojson: &str) -> PyResult<Vec<Polygon>> {
+    let invalid = |msg: &str| pyo3::exceptions::PyValueError::new_err(format!("Invalid polygon GeoJSON: {}", msg));
+
+    fn rings(value: &serde_json::Value) -> Option<Polygon> {
+        value.as_array()?.iter()
+            .map(|ring| ring.as_array()?.iter()
+                .map(|position| Some((position.get(0)?.as_f64()?, position.get(1)?.as_f64()?)))
+                .collect())
+            .collect()
+    }
+
+    fn collect(value: &serde_json::Value, polygons: &mut Vec<Polygon>) -> Result<(), String> {
+        let coordinates = || value.get("coordinates").ok_or_else(|| "missing coordinates".to_string());
+        match value.get("type").and_then(|t| t.as_str()) {
+            Some("Polygon") => {
+                polygons.push(rings(coordinates()?).ok_or("malformed Polygon coordinates")?);
+            },
+            Some("MultiPolygon") => {
+                for part in coordinates()?.as_array().ok_or("malformed MultiPolygon coordinates")? {
+                    polygons.push(rings(part).ok_or("malformed MultiPolygon coordinates")?);
+                }
+            },
+            Some("Feature") => collect(value.get("geometry").ok_or("Feature without geometry")?, polygons)?,
+            Some("FeatureCollection") => {
+                for feature in value.get("features").and_then(|f| f.as_array()).ok_or("FeatureCollection without features")? {
+                    collect(feature, polygons)?;
+                }
+            },
+            Some(other) => return Err(format!("unsupported type '{}'", other)),
+            None => return Err("missing type".to_string()),
+        }
+        Ok(())
+    }
+
+    let value: serde_json::Value = serde_json::from_str(geojson).map_err(|e| invalid(&e.to_string()))?;
+    let mut polygons = Vec::new();
+    collect(&value, &mut polygons).map_err(|e| invalid(&e))?;
+    Ok(polygons)
+}
+
+
+/// Compute per-key statistics restricted to the inside of a WGS84 polygon.
+///
+/// The polygon is rasterized onto the key grid (cells whose center lies inside it), so
+/// statistics can be reported within e.g. a burn perimeter without pre-clipping the rasters.
+/// Multi-part polygons and holes are supported.
+///
+/// # Arguments
+///
+/// * `key_fn: &str` - The file path to the raster data to be used as keys. It must have a
+///    projection.
+/// * `parameter_fn: &str` - The file path to the raster data to summarize for each key.
+/// * `polygon_geojson: &str` - A GeoJSON `Polygon`, `MultiPolygon`, `Feature`, or
+///    `FeatureCollection` with longitude/latitude coordinates.
+/// * `stats: Vec<String>` - Any of `"count"`, `"sum"`, `"mean"`, `"min"`, `"max"`,
+///    `"median"`, `"mode"`, and `"std"`.
+/// * `band_indx: isize` - The band of `parameter_fn` to read. Defaults to `1`.
+/// * `ignore_channels: bool` - If `true`, channel keys (see `channel_modulus`) are skipped. Defaults to `false`.
+/// * `exclude_zero: bool` - If `true`, key `0` (the TOPAZ background) is skipped. Defaults
+///    to `false`.
+/// * `channel_modulus: Option<(i32, i32)>` - The `(divisor, remainder)` identifying channel
+///    keys as `key % divisor == remainder`. Defaults to `None`, which keeps the TOPAZ
+///    convention `(10, 4)` (keys that end in 4).
+///
+/// # Returns
+///
+/// `PyResult<HashMap<String, HashMap<String, f64>>>` - The requested statistics of each key
+/// with at least one valid cell inside the polygon.
+///
+/// # Errors
+///
+/// Returns `Err` (`ValueError`) if a statistic is unknown, the GeoJSON is not a polygon, the
+/// key raster has no projection, or the rasters differ in size, and `Err` (`IOError`) if a
+/// raster cannot be read or the polygon cannot be reprojected.
+#[pyfunction(band_indx = "1", ignore_channels = "false", exclude_zero = "false", channel_modulus = "None")]
+fn identify_stats_in_polygon(
+    key_fn: &str,
+    parameter_fn: &str,
+    polygon_geojson: &str,
+    stats: Vec<String>,
+    band_indx: isize,
+    ignore_channels: bool,
+    exclude_zero: bool,
+    channel_modulus: Option<(i32, i32)>
+) -> PyResult<HashMap<String, HashMap<String, f64>>> {
+    let channel_modulus = resolve_channel_filter(ignore_channels, channel_modulus)?;
+    let zonal_stats: Vec<(String, ZonalStat)> = stats.into_iter()
+        .map(|name| parse_zonal_stat(&name).map(|stat| (name, stat)))
+        .collect::<PyResult<_>>()?;
+    let polygons = parse_geojson_polygons(polygon_geojson)?;
+
+    let key_map: Raster<i32> = read_raster_band(key_fn, 1)?;
+    let parameter_map: Raster<f64> = read_raster_band(parameter_fn, band_indx)?;
+    check_dimensions(key_fn, &key_map, parameter_fn, &parameter_map)?;
+
+    let include = key_map.polygon_mask_lnglat(&polygons)
+        .map_err(|e| to_py_err(&format!("Failed to rasterize polygon onto {}", key_fn), e))?;
+
+    let mut ignore_keys: HashSet<i32> = HashSet::new();
+    if exclude_zero {
+        ignore_keys.insert(BACKGROUND_KEY);
+    }
+    let values_d = zonal::values_by_key(&key_map, &parameter_map, Some(&include), channel_modulus, &ignore_keys);
+
+    Ok(values_d.into_iter()
+        .map(|(key, values)| {
+            let key_stats: HashMap<String, f64> = zonal_stats.iter()
+                .map(|(name, stat)| (name.clone(), stat.compute(&values)))
+                .collect();
+            (key.to_string(), key_stats)
+        })
+        .collect())
+}
+
+/// Compute the coefficient of variation of the parameter values of each key.
+///
+/// The CV is the population standard deviation divided by the mean, accumulated in one
+/// pass with Welford's algorithm. A high CV flags a heterogeneous hillslope that a single
+/// parameter value represents poorly.
+///
+/// # Arguments
+///
+/// * `key_fn: &str` - The file path to the raster data to be used as keys.
+/// * `parameter_fn: &str` - The file path to the raster data to summarize for each key.
+/// * `ignore_channels: bool` - If `true`, channel keys (see `channel_modulus`) are skipped.
+/// * `ignore_keys: HashSet<i32>` - Keys to skip. The key raster's "no data" value is always
+///    skipped.
+/// * `band_indx: isize` - The band of `parameter_fn` to read.
+/// * `exclude_zero: bool` - If `true`, key `0` (the TOPAZ background) is skipped like an
+///    ignored key. Defaults to `false`.
+/// * `channel_modulus: Option<(i32, i32)>` - The `(divisor, remainder)` identifying channel
+///    keys as `key % divisor == remainder`. Defaults to `None`, which keeps the TOPAZ
+///    convention `(10, 4)` (keys that end in 4).
+///
+/// # Returns
+///
+/// `PyResult<HashMap<String, f64>>` - The CV of each key. Keys whose mean is zero are
+/// omitted rather than reported as infinite.
+///
+/// # Errors
+///
+/// Returns `Err` if a raster cannot be read (`IOError`) or the rasters differ in size
+/// (`ValueError`).
+#[pyfunction(exclude_zero = "false", channel_modulus = "None")]
+fn identify_cv_single_raster_key(
+    key_fn: &str,
+    parameter_fn: &str,
+    ignore_channels: bool,
+    mut ignore_keys: HashSet<i32>,
+    band_indx: isize,
+    exclude_zero: bool,
+    channel_modulus: Option<(i32, i32)>
+) -> PyResult<HashMap<String, f64>> {
+    let channel_modulus = resolve_channel_filter(ignore_channels, channel_modulus)?;
+    if exclude_zero {
+        ignore_keys.insert(BACKGROUND_KEY);
+    }
+
+    let key_map: Raster<i32> = read_raster_band(key_fn, 1)?;
+    let parameter_map: Raster<f64> = read_raster_band(parameter_fn, band_indx)?;
+    check_dimensions(key_fn, &key_map, parameter_fn, &parameter_map)?;
+
+    Ok(zonal::cv_by_key(&key_map, &parameter_map, None, channel_modulus, &ignore_keys)
+        .into_iter()
+        .map(|(key, cv)| (key.to_string(), cv))
+        .collect())
+}
+
+
+/// Compute the hypsometric integral of each hillslope from a DEM.
+///
+/// The integral is approximated by the elevation-relief ratio `(mean - min) / (max - min)`
+/// of the DEM cells of each key, a single shape metric per hillslope in `[0, 1]`.
+///
+/// # Arguments
+///
+/// * `subwta_fn: &str` - The file path to the TOPAZ `SUBWTA` raster used as keys.
+/// * `dem_fn: &str` - The file path to the DEM.
+/// * `ignore_channels: bool` - If `true`, channel keys (see `channel_modulus`) are skipped.
+/// * `band_indx: isize` - The band of `dem_fn` to read.
+/// * `exclude_zero: bool` - If `true`, key `0` (the TOPAZ background) is skipped. Defaults
+///    to `false`.
+/// * `channel_modulus: Option<(i32, i32)>` - The `(divisor, remainder)` identifying channel
+///    keys as `key % divisor == remainder`. Defaults to `None`, which keeps the TOPAZ
+///    convention `(10, 4)` (keys that end in 4).
+///
+/// # Returns
+///
+/// `PyResult<HashMap<String, f64>>` - The hypsometric integral of each key. "No data"
+/// elevations are skipped, and keys without relief (e.g. a single cell) are omitted.
+///
+/// # Errors
+///
+/// Returns `Err` if a raster cannot be read (`IOError`) or the rasters differ in size
+/// (`ValueError`).
+#[pyfunction(exclude_zero = "false", channel_modulus = "None")]
+fn identify_hypsometric_integral(
+    subwta_fn: &str,
+    dem_fn: &str,
+    ignore_channels: bool,
+    band_indx: isize,
+    exclude_zero: bool,
+    channel_modulus: Option<(i32, i32)>
+) -> PyResult<HashMap<String, f64>> {
+    let channel_modulus = resolve_channel_filter(ignore_channels, channel_modulus)?;
+    let mut ignore_keys: HashSet<i32> = HashSet::new();
+    if exclude_zero {
+        ignore_keys.insert(BACKGROUND_KEY);
+    }
+
+    let key_map: Raster<i32> = read_raster_band(subwta_fn, 1)?;
+    let dem: Raster<f64> = read_raster_band(dem_fn, band_indx)?;
+    check_dimensions(subwta_fn, &key_map, dem_fn, &dem)?;
+
+    Ok(zonal::hypsometric_integral_by_key(&key_map, &dem, None, channel_modulus, &ignore_keys)
+        .into_iter()
+        .map(|(key, hi)| (key.to_string(), hi))
+        .collect())
+}
+
+
+/// Compute the Pearson correlation between two parameter rasters for each key.
+///
+/// The sums needed for Pearson's r are accumulated per key in one pass, e.g. to relate
+/// slope to soil loss per hillslope.
+///
+/// # Arguments
+///
+/// * `key_fn: &str` - The file path to the raster data to be used as keys.
+/// * `param1_fn: &str` - The file path to the first parameter raster.
+/// * `param2_fn: &str` - The file path to the second parameter raster.
+/// * `ignore_channels: bool` - If `true`, channel keys (see `channel_modulus`) are skipped.
+/// * `ignore_keys: HashSet<i32>` - Keys to skip. The key raster's "no data" value is always
+///    skipped.
+/// * `band_indx: isize` - The band of both parameter rasters to read.
+/// * `exclude_zero: bool` - If `true`, key `0` (the TOPAZ background) is skipped like an
+///    ignored key. Defaults to `false`.
+/// * `channel_modulus: Option<(i32, i32)>` - The `(divisor, remainder)` identifying channel
+///    keys as `key % divisor == remainder`. Defaults to `None`, which keeps the TOPAZ
+///    convention `(10, 4)` (keys that end in 4).
+///
+/// # Returns
+///
+/// `PyResult<HashMap<String, f64>>` - Pearson's r for each key. Cells that are "no data" in
+/// either parameter are skipped, and keys with fewer than two valid pairs or zero variance
+/// in either parameter are omitted.
+///
+/// # Errors
+///
+/// Returns `Err` if a raster cannot be read (`IOError`) or the rasters differ in size
+/// (`ValueError`).
+#[pyfunction(exclude_zero = "false", channel_modulus = "None")]
+fn identify_correlation_per_key(
+    key_fn: &str,
+    param1_fn: &str,
+    param2_fn: &str,
+    ignore_channels: bool,
+    mut ignore_keys: HashSet<i32>,
+    band_indx: isize,
+    exclude_zero: bool,
+    channel_modulus: Option<(i32, i32)>
+) -> PyResult<HashMap<String, f64>> {
+    let channel_modulus = resolve_channel_filter(ignore_channels, channel_modulus)?;
+    if exclude_zero {
+        ignore_keys.insert(BACKGROUND_KEY);
+    }
+
+    let key_map: Raster<i32> = read_raster_band(key_fn, 1)?;
+    let param1_map: Raster<f64> = read_raster_band(param1_fn, band_indx)?;
+    let param2_map: Raster<f64> = read_raster_band(param2_fn, band_indx)?;
+    check_dimensions(key_fn, &key_map, param1_fn, &param1_map)?;
+    check_dimensions(key_fn, &key_map, param2_fn, &param2_map)?;
+
+    Ok(zonal::correlation_by_key(&key_map, &param1_map, &param2_map, None, channel_modulus, &ignore_keys)
+        .into_iter()
+        .map(|(key, r)| (key.to_string(), r))
+        .collect())
+}
+
+
+/// Estimate the representative length and width of each hillslope for WEPP inputs.
+///
+/// The length is the longest flow path within the hillslope, following the D8 directions
+/// of `flowdir_fn` until they leave the hillslope (at least one cell); the width is the
+/// hillslope area divided by that length.
+///
+/// # Arguments
+///
+/// * `subwta_fn: &str` - The file path to the SUBWTA raster providing the TOPAZ ids.
+/// * `flowdir_fn: &str` - The file path to the TOPAZ `FLOVEC` flow direction raster.
+///
+/// # Returns
+///
+/// `PyResult<HashMap<String, (f64, f64)>>` - `(length, width)` in meters (map units) for
+/// each hillslope id. Channels (ids ending in 4) and the background id 0 are omitted.
+///
+/// # Errors
+///
+/// Returns `Err` if a raster cannot be read (`IOError`) or the rasters are not on the same
+/// grid (`ValueError`).
+#[pyfunction]
+fn hillslope_geometry(subwta_fn: &str, flowdir_fn: &str) -> PyResult<HashMap<String, (f64, f64)>> {
+    let subwta: Raster<i32> = read_raster_band(subwta_fn, 1)?;
+    let flowdir: Raster<i32> = read_raster_band(flowdir_fn, 1)?;
+
+    let geometry = hillslope_geometry_rs(&subwta, &flowdir)
+        .map_err(|e| to_py_err("Failed to compute hillslope geometry", e))?;
+    Ok(geometry.into_iter()
+        .map(|(id, length_width)| (id.to_string(), length_width))
+        .collect())
+}
+
+
+/// Find the outlet cell of each hillslope, where it drains into its channel.
+///
+/// The outlet is the cell of the hillslope with the smallest distance to the channel in
+/// `discha_fn`, i.e. the cell adjacent to the channel, giving the hillslope-channel
+/// connection points for routing.
+///
+/// # Arguments
+///
+/// * `subwta_fn: &str` - The file path to the SUBWTA raster providing the TOPAZ ids.
+/// * `discha_fn: &str` - The file path to the TOPAZ `DISCHA` distance-to-channel raster.
+///
+/// # Returns
+///
+/// `PyResult<HashMap<String, (usize, usize)>>` - The `(x, y)` pixel of the outlet of each
+/// hillslope id; ties go to the first cell in row-major order. Channels (ids ending in 4)
+/// and the background id 0 are omitted.
+///
+/// # Errors
+///
+/// Returns `Err` if a raster cannot be read (`IOError`) or the rasters are not on the same
+/// grid (`ValueError`).
+#[pyfunction]
+fn hillslope_outlets(subwta_fn: &str, discha_fn: &str) -> PyResult<HashMap<String, (usize, usize)>> {
+    let subwta: Raster<i32> = read_raster_band(subwta_fn, 1)?;
+    let discha: Raster<f64> = read_raster_band(discha_fn, 1)?;
+
+    let outlets = hillslope_outlets_rs(&subwta, &discha)
+        .map_err(|e| to_py_err("Failed to find hillslope outlets", e))?;
+    Ok(outlets.into_iter()
+        .map(|(id, px)| (id.to_string(), px))
+        .collect())
+}
+
+
+/// Identify the dominant compass aspect class of each hillslope.
+///
+/// Every cell's aspect is binned into one of eight 45° classes (`N`, `NE`, `E`, `SE`,
+/// `S`, `SW`, `W`, `NW`), each centered on its direction so that `N` spans 337.5° to
+/// 22.5°. The class with the most cells is returned per key; ties go to the class that
+/// comes first clockwise from `N`.
+///
+/// # Arguments
+///
+/// * `subwta_fn: &str` - The file path to the hillslope (key) raster.
+/// * `aspect_fn: &str` - The file path to the aspect raster in degrees clockwise from north.
+/// * `ignore_channels: bool` - If `true`, channel keys (see `channel_modulus`) are skipped.
+/// * `band_indx: isize` - The band of `aspect_fn` to read.
+/// * `exclude_zero: bool` - If `true`, key `0` (the TOPAZ background) is skipped like an
+///    ignored key. Defaults to `false`.
+/// * `channel_modulus: Option<(i32, i32)>` - The `(divisor, remainder)` identifying channel
+///    keys as `key % divisor == remainder`. Defaults to `None`, which keeps the TOPAZ
+///    convention `(10, 4)` (keys that end in 4).
+///
+/// # Returns
+///
+/// `PyResult<HashMap<String, String>>` - The dominant class label for each key. Aspect
+/// cells that are "no data" or outside 0° to 360° (e.g. `-1` for flat cells) are not
+/// counted, and keys without any classed cells are omitted.
+///
+/// # Errors
+///
+/// Returns `Err` if a raster cannot be read (`IOError`) or the rasters differ in size
+/// (`ValueError`).
+#[pyfunction(exclude_zero = "false", channel_modulus = "None")]
+fn identify_dominant_aspect_class(
+    subwta_fn: &str,
+    aspect_fn: &str,
+    ignore_channels: bool,
+    band_indx: isize,
+    exclude_zero: bool,
+    channel_modulus: Option<(i32, i32)>
+) -> PyResult<HashMap<String, String>> {
+    let channel_modulus = resolve_channel_filter(ignore_channels, channel_modulus)?;
+    let key_map: Raster<i32> = read_raster_band(subwta_fn, 1)?;
+    let aspect_map: Raster<f64> = read_raster_band(aspect_fn, band_indx)?;
+    check_dimensions(subwta_fn, &key_map, aspect_fn, &aspect_map)?;
+
+    let mut counts_d: HashMap<i32, [usize; 8]> = HashMap::new();
+
+    for (&key, &aspect) in key_map.data.iter().zip(aspect_map.data.iter()) {
+        if key_map.no_data == Some(key) || aspect_map.is_no_data(aspect) {
+            continue;
+        }
+
+        if let Some(modulus) = channel_modulus {
+            if zonal::is_channel_by(key, modulus) {
+                continue;
+            }
+        }
+
+        if exclude_zero && key == BACKGROUND_KEY {
+            continue;
+        }
+
+        if let Some(class) = aspect_class(aspect) {
+            counts_d.entry(key).or_insert([0; 8])[class] += 1;
+        }
+    }
+
+    let mut result: HashMap<String, String> = HashMap::new();
+    for (key, counts) in counts_d {
+        // the first maximum wins ties
+        let dominant = (0..8).fold(0, |best, class| if counts[class] > counts[best] { class } else { best });
+        result.insert(key.to_string(), ASPECT_CLASSES[dominant].to_string());
+    }
+
+    Ok(result)
+}
+
+
+/// Paint per-hillslope values back onto the hillslope raster and write a GeoTIFF.
+///
+/// The inverse of the per-key statistics: every cell of `subwta_fn` takes the value of
+/// its hillslope in `values`, e.g. to map modeled per-hillslope soil loss.
+///
+/// # Arguments
+///
+/// * `subwta_fn: &str` - The file path to the hillslope (key) raster.
+/// * `values: HashMap<i32, f64>` - The value of each hillslope id.
+/// * `out_fn: &str` - The file path of the output GeoTIFF.
+/// * `default: f64` - The value of cells whose id is missing from `values` or is "no data";
+///    also written as the output's "no data" value. Defaults to `-9999.0`.
+///
+/// # Errors
+///
+/// Returns `Err` if a raster cannot be read or written.
+#[pyfunction(default = "-9999.0")]
+fn paint_hillslope_values(subwta_fn: &str, values: HashMap<i32, f64>, out_fn: &str, default: f64) -> PyResult<()> {
+    let subwta: Raster<i32> = read_raster_band(subwta_fn, 1)?;
+    subwta.paint_values(&values, default).write(out_fn)
+        .map_err(|e| to_py_err(&format!("Failed to write raster {}", out_fn), e))
+}
+
+/// Compute a statistic per key and write it painted onto the key raster as a GeoTIFF.
+///
+/// Fuses the per-key statistics and `paint_hillslope_values` for visualization: every cell
+/// of `zone_fn` takes the statistic of its zone, without an intermediate Python dict.
+///
+/// # Arguments
+///
+/// * `zone_fn: &str` - The file path to the raster data to be used as keys (zones).
+/// * `value_fn: &str` - The file path to the raster data to summarize for each zone.
+/// * `stat: &str` - One of `"count"`, `"sum"`, `"mean"`, `"min"`, `"max"`, `"median"`,
+///    `"mode"`, and `"std"`.
+/// * `out_fn: &str` - The file path of the output GeoTIFF.
+/// * `band_indx: isize` - The band of `value_fn` to read. Defaults to `1`.
+/// * `ignore_channels: bool` - If `true`, channel keys (see `channel_modulus`) are skipped. Defaults to `false`.
+/// * `exclude_zero: bool` - If `true`, key `0` (the TOPAZ background) is skipped. Defaults
+///    to `false`.
+/// * `default: f64` - The value of skipped cells and of zones without valid values; also
+///    written as the output's "no data" value. Defaults to `-9999.0`.
+/// * `channel_modulus: Option<(i32, i32)>` - The `(divisor, remainder)` identifying channel
+///    keys as `key % divisor == remainder`. Defaults to `None`, which keeps the TOPAZ
+///    convention `(10, 4)` (keys that end in 4).
+///
+/// # Returns
+///
+/// `PyResult<usize>` - The number of zones painted.
+///
+/// # Errors
+///
+/// Returns `Err` if `stat` is unknown or the rasters differ in size (`ValueError`), or a
+/// raster cannot be read or written (`IOError`).
+#[pyfunction(band_indx = "1", ignore_channels = "false", exclude_zero = "false", default = "-9999.0", channel_modulus = "None")]
+fn zonal_stat_to_raster(
+    zone_fn: &str,
+    value_fn: &str,
+    stat: &str,
+    out_fn: &str,
+    band_indx: isize,
+    ignore_channels: bool,
+    exclude_zero: bool,
+    default: f64,
+    channel_modulus: Option<(i32, i32)>
+) -> PyResult<usize> {
+    let channel_modulus = resolve_channel_filter(ignore_channels, channel_modulus)?;
+    let zonal_stat = parse_zonal_stat(stat)?;
+
+    let key_map: Raster<i32> = read_raster_band(zone_fn, 1)?;
+    let parameter_map: Raster<f64> = read_raster_band(value_fn, band_indx)?;
+    check_dimensions(zone_fn, &key_map, value_fn, &parameter_map)?;
+
+    let mut ignore_keys: HashSet<i32> = HashSet::new();
+    if exclude_zero {
+        ignore_keys.insert(BACKGROUND_KEY);
+    }
+    let values = zonal::stat_by_key(&key_map, &parameter_map, zonal_stat, None, channel_modulus, &ignore_keys);
+
+    key_map.paint_values(&values, default).write(out_fn)
+        .map_err(|e| to_py_err(&format!("Failed to write raster {}", out_fn), e))?;
+    Ok(values.len())
+}
+
+/// The summary statistics of one zone, returned by `zonal_statistics`.
+#[pyclass]
+struct ZonalStats {
+    #[pyo3(get)]
+    count: usize,
+    #[pyo3(get)]
+    min: f64,
+    #[pyo3(get)]
+    max: f64,
+    #[pyo3(get)]
+    mean: f64,
+    /// The population standard deviation.
+    #[pyo3(get)]
+    std: f64,
+    /// `None` unless `compute_median` was set.
+    #[pyo3(get)]
+    median: Option<f64>,
+}
+
+#[pymethods]
+impl ZonalStats {
+    fn __repr__(&self) -> String {
+        format!("ZonalStats(count={}, min={}, max={}, mean={}, std={}, median={:?})",
+            self.count, self.min, self.max, self.mean, self.std, self.median)
+    }
+}
+
+/// Compute the count, min, max, mean, std, and optionally the median of each zone in one pass.
+///
+/// Replaces separate calls per statistic. The running statistics need no per-zone storage;
+/// the values of each zone are only collected when `compute_median` is set.
+///
+/// # Arguments
+///
+/// * `zone_fn: &str` - The file path to the zone (key) raster, e.g. a SUBWTA.
+/// * `value_fn: &str` - The file path to the raster to summarize for each zone.
+/// * `ignore_channels: bool` - If `true`, channel zones (see `channel_modulus`) are skipped.
+/// * `ignore_keys: HashSet<i32>` - Zones to skip. The zone raster's "no data" value is always
+///    skipped.
+/// * `band_indx: isize` - The band of `value_fn` to read.
+/// * `compute_median: bool` - If `true`, also compute the median of each zone. Defaults to
+///    `false`.
+/// * `exclude_zero: bool` - If `true`, zone `0` (the TOPAZ background) is skipped like an
+///    ignored key. Defaults to `false`.
+/// * `channel_modulus: Option<(i32, i32)>` - The `(divisor, remainder)` identifying channel
+///    keys as `key % divisor == remainder`. Defaults to `None`, which keeps the TOPAZ
+///    convention `(10, 4)` (keys that end in 4).
+///
+/// # Returns
+///
+/// `PyResult<HashMap<String, ZonalStats>>` - The statistics of each zone with at least one
+/// valid value.
+///
+/// # Errors
+///
+/// Returns `Err` (`ValueError`) if the rasters differ in size, and `Err` (`IOError`) if a
+/// raster cannot be read.
+#[pyfunction(compute_median = "false", exclude_zero = "false", channel_modulus = "None")]
+fn zonal_statistics(
+    zone_fn: &str,
+    value_fn: &str,
+    ignore_channels: bool,
+    mut ignore_keys: HashSet<i32>,
+    band_indx: isize,
+    compute_median: bool,
+    exclude_zero: bool,
+    channel_modulus: Option<(i32, i32)>
+) -> PyResult<HashMap<String, ZonalStats>> {
+    let channel_modulus = resolve_channel_filter(ignore_channels, channel_modulus)?;
+    if exclude_zero {
+        ignore_keys.insert(BACKGROUND_KEY);
+    }
+
+    let key_map: Raster<i32> = read_raster_band(zone_fn, 1)?;
+    let parameter_map: Raster<f64> = read_raster_band(value_fn, band_indx)?;
+    check_dimensions(zone_fn, &key_map, value_fn, &parameter_map)?;
+
+    Ok(zonal::summary_by_key(&key_map, &parameter_map, None, channel_modulus, &ignore_keys, compute_median)
+        .into_iter()
+        .map(|(key, s)| (key.to_string(), ZonalStats {
+            count: s.count,
+            min: s.min,
+            max: s.max,
+            mean: s.mean,
+            std: s.std,
+            median: s.median,
+        }))
+        .collect())
+}
+
+/// Write a SUBWTA raster keeping only its hillslope cells as a GeoTIFF.
+///
+/// Channel cells (ids ending in 4) and background cells (id 0) are written as "no data",
+/// giving a clean hillslope mask for zonal statistics. The output keeps the "no data" value
+/// of `src_fn`, else uses 0.
+///
+/// # Arguments
+///
+/// * `src_fn: &str` - The file path to the SUBWTA raster.
+/// * `dst_fn: &str` - The file path of the output GeoTIFF.
+///
+/// # Errors
+///
+/// Returns `Err` if the raster cannot be read or written.
+#[pyfunction]
+fn subwta_hillslopes_only(src_fn: &str, dst_fn: &str) -> PyResult<()> {
+    let subwta: Raster<i32> = read_raster_band(src_fn, 1)?;
+    subwta.hillslopes_only().write(dst_fn)
+        .map_err(|e| to_py_err(&format!("Failed to write raster {}", dst_fn), e))
+}
+
+/// Render a categorical raster such as SUBWTA as an RGBA PNG thumbnail for visual QA.
+///
+/// Each TOPAZ id is colored like the terminal `display_grid` output: channels (ids ending
+/// in 4) blue and the hillslopes beside them by their last digit, so neighbouring
+/// hillslopes are told apart. No-data cells are transparent.
+///
+/// # Arguments
+///
+/// * `subwta_fn: &str` - The file path to the categorical raster (first band).
+/// * `out_fn: &str` - The file path of the output PNG.
+///
+/// # Returns
+///
+/// `PyResult<HashMap<String, String>>` - The legend: the `#rrggbb` color of each id in the
+/// image.
+///
+/// # Errors
+///
+/// Returns `Err` if the raster cannot be read or the PNG cannot be written.
+#[pyfunction]
+fn render_categorical_png(subwta_fn: &str, out_fn: &str) -> PyResult<HashMap<String, String>> {
+    let subwta: Raster<i32> = read_raster_band(subwta_fn, 1)?;
+    let legend = subwta.render_categorical_png(out_fn)
+        .map_err(|e| to_py_err(&format!("Failed to write PNG {}", out_fn), e))?;
+
+    Ok(legend.into_iter()
+        .map(|(id, [r, g, b])| (id.to_string(), format!("#{:02x}{:02x}{:02x}", r, g, b)))
+        .collect())
+}
+
+/// Sample a parameter raster at the centroid of each hillslope.
+///
+/// For every TOPAZ id in `subwta_fn` the pixel centroid is computed with `centroid_of` and
+/// `parameter_fn` is sampled there. When the centroid falls outside the hillslope (concave
+/// shapes) or on a parameter no-data cell, the nearest valid cell of the hillslope is used
+/// instead. Hillslopes without any valid parameter cell are omitted.
+///
+/// # Arguments
+///
+/// * `subwta_fn: &str` - The file path to the SUBWTA raster providing the TOPAZ ids.
+/// * `parameter_fn: &str` - The file path to the raster to sample.
+/// * `band_indx: isize` - The band of `parameter_fn` to sample.
+///
+/// # Returns
+///
+/// `PyResult<HashMap<String, f64>>` - A HashMap of TOPAZ id to the sampled parameter value.
+///
+/// # Errors
+///
+/// Returns `Err` if either raster cannot be read or their dimensions differ.
+#[pyfunction]
+fn value_at_centroids(
+    subwta_fn: &str,
+    parameter_fn: &str,
+    band_indx: isize
+) -> PyResult<HashMap<String, f64>> {
+    let subwta: Raster<i32> = read_raster_band(subwta_fn, 1)?;
+    let parameter_map: Raster<f64> = read_raster_band(parameter_fn, band_indx)?;
+    check_dimensions(subwta_fn, &subwta, parameter_fn, &parameter_map)?;
+
+    let is_valid = |indx: usize| !parameter_map.is_no_data(parameter_map.data[indx]);
+
+    let mut result: HashMap<String, f64> = HashMap::new();
+    for topaz_id in subwta.unique_values() {
+        let members = subwta.indices_of(topaz_id);
+        let (x, y) = subwta.centroid_of(&members);
+        let centroid = subwta.xy_to_index(x, y);
+
+        let sample_indx = if members.contains(&centroid) && is_valid(centroid) {
+            Some(centroid)
+        } else {
+            // sort so ties between equidistant cells resolve deterministically
+            let mut indices: Vec<usize> = members.into_iter().filter(|&indx| is_valid(indx)).collect();
+            indices.sort();
+            indices.into_iter().min_by(|&a, &b| {
+                subwta.distance_between(centroid, a)
+                    .partial_cmp(&subwta.distance_between(centroid, b))
+                    .unwrap_or(std::cmp::Ordering::Equal)
+            })
+        };
+
+        if let Some(indx) = sample_indx {
+            result.insert(topaz_id.to_string(), parameter_map.data[indx]);
+        }
+    }
+
+    Ok(result)
+}
+
+/// Computes a histogram of the valid (non no-data) cells of a raster.
+///
+/// The `bins` equal-width bins span the minimum and maximum valid values.
+///
+/// # Arguments
+///
+/// * `path: &str` - The file path to the raster.
+/// * `bins: usize` - The number of bins.
+///
+/// # Returns
+///
+/// `PyResult<(Vec<f64>, Vec<u64>)>` - The `bins + 1` bin edges and the `bins` counts.
+///
+/// # Errors
+///
+/// Returns `Err` if the raster cannot be read or `bins` is 0.
+#[pyfunction]
+fn raster_histogram(path: &str, bins: usize) -> PyResult<(Vec<f64>, Vec<u64>)> {
+    if bins == 0 {
+        return Err(pyo3::exceptions::PyValueError::new_err("bins must be greater than 0"));
+    }
+
+    let raster: Raster<f64> = read_raster_band(path, 1)?;
+    Ok(raster.histogram(bins, None))
+}
+
+/// Lists the distinct valid values of a categorical raster in ascending order.
+///
+/// Unlike iterating a set, the order is stable across runs, e.g. for TOPAZ id manifests.
+///
+/// # Arguments
+///
+/// * `path: &str` - The file path to the raster.
+/// * `band_indx: isize` - The band to read. Defaults to `1`.
+///
+/// # Returns
+///
+/// `PyResult<Vec<i32>>` - The sorted distinct values, excluding no-data.
+///
+/// # Errors
+///
+/// Returns `Err` if the raster cannot be read.
+#[pyfunction(band_indx = "1")]
+fn raster_unique_sorted(path: &str, band_indx: isize) -> PyResult<Vec<i32>> {
+    let raster: Raster<i32> = read_raster_band(path, band_indx)?;
+    Ok(raster.sorted_unique_values())
+}
+
+/// Reads a raster band as a `numpy.ma.MaskedArray` with its no-data cells masked.
+///
+/// matplotlib and `numpy.ma` statistics skip masked cells, so callers do not need to
+/// rebuild the mask from the no-data value. The array has shape `(height, width)`, and
+/// its `fill_value` is the band's no-data value when it has one.
+///
+/// # Arguments
+///
+/// * `path: &str` - The file path to the raster.
+/// * `band_indx: isize` - The band to read. Defaults to `1`.
+///
+/// # Returns
+///
+/// `PyResult<PyObject>` - The `float64` masked array.
+///
+/// # Errors
+///
+/// Returns `Err` if the raster cannot be read or numpy cannot be imported.
+#[pyfunction(band_indx = "1")]
+fn raster_as_masked_numpy(py: Python, path: &str, band_indx: isize) -> PyResult<PyObject> {
+    let raster: Raster<f64> = read_raster_band(path, band_indx)?;
+    let shape = (raster.height, raster.width);
+
+    let numpy = py.import("numpy")?;
+    let data = numpy.call_method1("array", (raster.data.clone(),))?.call_method1("reshape", (shape,))?;
+    let mask = numpy.call_method1("array", (raster.mask(),))?.call_method1("reshape", (shape,))?;
+
+    let kwargs = pyo3::types::PyDict::new(py);
+    kwargs.set_item("mask", mask)?;
+    if let Some(no_data) = raster.no_data {
+        kwargs.set_item("fill_value", no_data)?;
+    }
+
+    let masked = py.import("numpy.ma")?.call_method("masked_array", (data,), Some(kwargs))?;
+    Ok(masked.into())
+}
+
+/// Writes a 2D numpy array as a raster, the inverse of `raster_as_masked_numpy`.
+///
+/// Lets Python persist a computed grid through the same GDAL writer as the other functions
+/// instead of rasterio. Row 0 of the array is the top row of the raster, and the cell size
+/// is taken from `geo_transform[1]`.
+///
+/// # Arguments
+///
+/// * `data: &PyAny` - A 2D, C-contiguous numpy array of shape `(height, width)`; it is
+///    converted to `float64`.
+/// * `geo_transform: [f64; 6]` - The GDAL geotransform of the raster.
+/// * `proj4: Option<String>` - The CRS as a proj4 string, or `None` to write no projection.
+/// * `no_data: Option<f64>` - The no-data value to set on the band, if any.
+/// * `path: &str` - The output path; the driver follows its extension.
+///
+/// # Errors
+///
+/// Returns `Err` (`ValueError`) if `data` is not a 2D C-contiguous array, and `Err` if the
+/// raster cannot be written.
+#[pyfunction]
+fn raster_from_numpy(
+    data: &PyAny,
+    geo_transform: [f64; 6],
+    proj4: Option<String>,
+    no_data: Option<f64>,
+    path: &str
+) -> PyResult<()> {
+    let ndim: usize = data.getattr("ndim")?.extract()?;
+    if ndim != 2 {
+        return Err(pyo3::exceptions::PyValueError::new_err(format!(
+            "Expected a 2D array, got {} dimensions", ndim)));
+    }
+    let c_contiguous: bool = data.getattr("flags")?.getattr("c_contiguous")?.extract()?;
+    if !c_contiguous {
+        return Err(pyo3::exceptions::PyValueError::new_err(
+            "Expected a C-contiguous array; use numpy.ascontiguousarray"));
+    }
+
+    let (height, width): (usize, usize) = data.getattr("shape")?.extract()?;
+    let values: Vec<f64> = data.call_method1("astype", ("float64",))?
+        .call_method1("reshape", (-1,))?
+        .call_method0("tolist")?
+        .extract()?;
+
+    let name = std::path::Path::new(path).file_stem()
+        .and_then(|stem| stem.to_str())
+        .unwrap_or("")
+        .to_string();
+    let map_type = MapType::from_str(&name).unwrap();
+
+    let raster = Raster::new(width, height, geo_transform[1], values, no_data,
+        geo_transform, proj4, path.to_string(), name, map_type);
+    raster.write(path)
+        .map_err(|e| to_py_err(&format!("Failed to write raster {}", path), e))
+}
+
+/// Counts the valid cells of a raster whose value lies in `lo..=hi` and the area they cover.
+///
+/// Both bounds are inclusive; no-data and NaN cells are never counted. Useful for reports
+/// such as the area above a soil loss tolerance without building a threshold raster.
+///
+/// # Arguments
+///
+/// * `path: &str` - The file path to the raster (first band).
+/// * `lo: f64` - The inclusive lower bound.
+/// * `hi: f64` - The inclusive upper bound.
+///
+/// # Returns
+///
+/// `PyResult<(usize, f64)>` - The cell count and its area (`count * cellsize²`) in the
+/// raster's squared map units.
+///
+/// # Errors
+///
+/// Returns `Err` if the raster cannot be read.
+#[pyfunction]
+fn raster_count_in_range(path: &str, lo: f64, hi: f64) -> PyResult<(usize, f64)> {
+    let raster: Raster<f64> = read_raster_band(path, 1)?;
+    let count = raster.count_in_range(lo, hi);
+    Ok((count, count as f64 * raster.cellsize * raster.cellsize))
+}
+
+/// Sums the valid cells of a raster.
+///
+/// No-data and NaN cells are skipped. For grids of an extensive quantity, e.g. a soil loss
+/// grid in kg per cell, this is the basin total used in mass-balance checks.
+///
+/// # Arguments
+///
+/// * `path: &str` - The file path to the raster.
+/// * `band_indx: isize` - The band to sum. Defaults to `1`.
+///
+/// # Returns
+///
+/// `PyResult<f64>` - The sum of the valid cells, `0.0` if there are none.
+///
+/// # Errors
+///
+/// Returns `Err` if the raster cannot be read.
+#[pyfunction(band_indx = "1")]
+fn raster_sum(path: &str, band_indx: isize) -> PyResult<f64> {
+    let raster: Raster<f64> = read_raster_band(path, band_indx)?;
+    Ok(raster.sum())
+}
+
+/// Counts the valid, non-zero cells of a raster.
+///
+/// No-data and NaN cells are never counted.
+///
+/// # Arguments
+///
+/// * `path: &str` - The file path to the raster.
+/// * `band_indx: isize` - The band to inspect. Defaults to `1`.
+///
+/// # Returns
+///
+/// `PyResult<usize>` - The number of valid cells that are not zero.
+///
+/// # Errors
+///
+/// Returns `Err` if the raster cannot be read.
+#[pyfunction(band_indx = "1")]
+fn raster_count_nonzero(path: &str, band_indx: isize) -> PyResult<usize> {
+    let raster: Raster<f64> = read_raster_band(path, band_indx)?;
+    Ok(raster.count_nonzero())
+}
+
+/// Computes the statistics of every band of a multiband raster.
+///
+/// Each band is read with its own no-data value and that value is applied only to that
+/// band, so e.g. a 12-band monthly stack whose bands use different sentinels is summarized
+/// correctly.
+///
+/// # Arguments
+///
+/// * `path: &str` - The file path to the raster.
+///
+/// # Returns
+///
+/// `PyResult<Vec<HashMap<String, f64>>>` - One dict per band, in band order, with keys
+/// `minimum`, `maximum`, `mean`, `std_dev`, `valid_percent`, `median`, `p2`, and `p98`, plus
+/// `no_data` when the band has a no-data value.
+///
+/// # Errors
+///
+/// Returns `Err` if the raster cannot be read.
+#[pyfunction]
+fn raster_band_statistics_all(path: &str) -> PyResult<Vec<HashMap<String, f64>>> {
+    let bands: Vec<Raster<f64>> = Raster::read_all_bands(path)
+        .map_err(|e| to_py_err(&format!("Failed to read raster {}", path), e))?;
+
+    let stats = Raster::band_statistics_all(&bands);
+    Ok(bands.iter().zip(stats.iter())
+        .map(|(band, stats)| {
+            let mut d: HashMap<String, f64> = HashMap::new();
+            d.insert("minimum".to_string(), stats.minimum);
+            d.insert("maximum".to_string(), stats.maximum);
+            d.insert("mean".to_string(), stats.mean);
+            d.insert("std_dev".to_string(), stats.std_dev);
+            d.insert("valid_percent".to_string(), stats.valid_percent);
+            d.insert("median".to_string(), stats.median);
+            d.insert("p2".to_string(), stats.p2);
+            d.insert("p98".to_string(), stats.p98);
+            if let Some(no_data) = band.no_data {
+                d.insert("no_data".to_string(), no_data);
+            }
+            d
+        })
+        .collect())
+}
+
+/// Diagnoses whether two rasters share a grid, and how they differ when they do not.
+///
+/// The per-key statistics silently produce garbage on "almost aligned" inputs; this
+/// reports which of the grid properties differ so the inputs can be fixed first.
+///
+/// # Arguments
+///
+/// * `a_fn: &str` - The reference raster, e.g. the key map.
+/// * `b_fn: &str` - The raster compared against `a_fn`.
+///
+/// # Returns
+///
+/// `PyResult<HashMap<String, String>>` - `width`, `height`, `cellsize`, `crs`, and `origin`
+/// map to `"match"` or a description of the mismatch (e.g. `"b is offset by 0.5 px in x"`,
+/// `"cellsizes differ 30 vs 10"`). `aligned` is `"true"` or `"false"`, and when the grids are
+/// not aligned `suggestion` describes a fix.
+///
+/// # Errors
+///
+/// Returns `Err` if either raster cannot be read.
+#[pyfunction]
+fn check_alignment(a_fn: &str, b_fn: &str) -> PyResult<HashMap<String, String>> {
+    let a: Raster<f64> = read_raster_band(a_fn, 1)?;
+    let b: Raster<f64> = read_raster_band(b_fn, 1)?;
+    let report = a.alignment_report(&b);
+
+    let mut result: HashMap<String, String> = HashMap::new();
+    for (key, mismatch) in [
+        ("width", &report.width),
+        ("height", &report.height),
+        ("cellsize", &report.cellsize),
+        ("crs", &report.crs),
+        ("origin", &report.origin),
+    ] {
+        result.insert(key.to_string(), mismatch.clone().unwrap_or_else(|| "match".to_string()));
+    }
+    result.insert("aligned".to_string(), report.is_aligned().to_string());
+    if let Some(suggestion) = report.suggestion() {
+        result.insert("suggestion".to_string(), suggestion);
+    }
+    Ok(result)
+}
+
+/// Estimates the memory needed to read a raster without reading its data.
+///
+/// Only the dataset header is opened, so footprints can be checked before loading a
+/// directory of rasters.
+///
+/// # Arguments
+///
+/// * `path: &str` - The file path to the raster.
+/// * `band_indx: Option<isize>` - The band to size. Defaults to `None`: all bands.
+///
+/// # Returns
+///
+/// `PyResult<u64>` - `width * height * bytes per cell` of the band's data type, summed
+/// over the bands.
+///
+/// # Errors
+///
+/// Returns `Err` if the raster or band cannot be opened.
+#[pyfunction(band_indx = "None")]
+fn raster_footprint(path: &str, band_indx: Option<isize>) -> PyResult<u64> {
+    band_footprint(path, band_indx)
+        .map_err(|e| to_py_err(&format!("Failed to read raster {}", path), e))
+}
+
+/// Reports the no-data value GDAL infers for a raster band.
+///
+/// Useful for auditing rasters for missing no-data metadata before computing statistics
+/// that would otherwise treat a sentinel value as data.
+///
+/// # Arguments
+///
+/// * `path: &str` - The file path to the raster.
+/// * `band_indx: isize` - The band to inspect.
+///
+/// # Returns
+///
+/// `PyResult<Option<f64>>` - The no-data value exactly as GDAL reports it, or `None`.
+#[pyfunction]
+fn raster_nodata(path: &str, band_indx: isize) -> PyResult<Option<f64>> {
+    band_no_data(path, band_indx)
+        .map_err(|e| to_py_err(&format!("Failed to read raster {}", path), e))
+}
+
+/// Mosaic aligned rasters into a single GeoTIFF.
+///
+/// The output covers the union extent of the inputs, which must share a projection and
+/// cell size and be offset from each other by whole cells. Cells covered by more than one
+/// input are resolved by `method`.
+///
+/// # Arguments
+///
+/// * `paths: Vec<String>` - The file paths of the rasters to stitch.
+/// * `out_fn: &str` - The file path of the output GeoTIFF.
+/// * `method: &str` - One of `"first"`, `"last"`, `"max"`, or `"mean"`.
+///
+/// # Errors
+///
+/// Returns `Err` if a raster cannot be read or written, `method` is unknown, or the inputs
+/// are not aligned.
+#[pyfunction]
+fn mosaic(paths: Vec<String>, out_fn: &str, method: &str) -> PyResult<()> {
+    let method = MosaicMethod::from_str(method).map_err(|_| pyo3::exceptions::PyValueError::new_err(
+        format!("Unknown mosaic method '{}', expected first, last, max, or mean", method)))?;
+
+    let mut rasters: Vec<Raster<f64>> = Vec::with_capacity(paths.len());
+    for path in &paths {
+        rasters.push(read_raster_band(path, 1)?);
+    }
+
+    let mosaicked = Raster::mosaic(&rasters, method)
+        .map_err(|e| to_py_err("Failed to mosaic rasters", e))?;
+    mosaicked.write(out_fn)
+        .map_err(|e| to_py_err(&format!("Failed to write raster {}", out_fn), e))
+}
+
+/// Interpolate point values onto the grid of a reference raster and write a GeoTIFF.
+///
+/// The counterpart to `sample_points_csv`: the reference raster supplies the grid and
+/// projection, and every cell is filled from the points by `method`.
+///
+/// # Arguments
+///
+/// * `points: Vec<(f64, f64, f64)>` - `(easting, northing, value)` triples in the reference
+///    raster's CRS. Points outside the reference extent are ignored.
+/// * `reference_fn: &str` - The file path to the raster defining the output grid.
+/// * `method: &str` - `"idw"` (inverse distance weighted, power 2) or `"nearest"`.
+/// * `out_fn: &str` - The file path of the output GeoTIFF. Cells are -9999 (no data) when
+///    no point lies within the extent.
+///
+/// # Errors
+///
+/// Returns `Err` if `method` is unknown (`ValueError`) or a raster cannot be read or
+/// written (`IOError`).
+#[pyfunction]
+fn rasterize_points(points: Vec<(f64, f64, f64)>, reference_fn: &str, method: &str, out_fn: &str) -> PyResult<()> {
+    let method = PointInterpolation::from_str(method).map_err(|_| pyo3::exceptions::PyValueError::new_err(
+        format!("Unknown interpolation method '{}', expected idw or nearest", method)))?;
+
+    let reference: Raster<f64> = read_raster_band(reference_fn, 1)?;
+    reference.rasterize_points(&points, method).write(out_fn)
+        .map_err(|e| to_py_err(&format!("Failed to write raster {}", out_fn), e))
+}
+
+/// Sample a raster at a projected easting/northing, e.g. the value at a gauge.
+///
+/// # Arguments
+///
+/// * `path: &str` - The file path to the raster.
+/// * `e: f64` - The easting, in the raster's CRS.
+/// * `n: f64` - The northing, in the raster's CRS.
+/// * `method: &str` - `"nearest"` for the value of the cell containing the point,
+///    `"bilinear"` to interpolate between the four surrounding cell centers, or `"cubic"` for
+///    cubic convolution over the sixteen surrounding cell centers.
+/// * `band_indx: isize` - The band to sample. Defaults to `1`.
+///
+/// # Returns
+///
+/// `PyResult<Option<f64>>` - The sampled value, or `None` when the point falls outside the
+/// grid or on no-data (for `"bilinear"` and `"cubic"`, when any contributing cell is no-data).
+///
+/// # Errors
+///
+/// Returns `Err` (`ValueError`) for an unknown `method`, and `Err` (`IOError`) if the
+/// raster cannot be read.
+#[pyfunction(band_indx = "1")]
+fn sample_raster(path: &str, e: f64, n: f64, method: &str, band_indx: isize) -> PyResult<Option<f64>> {
+    let method = ResampleMethod::from_str(method).map_err(|_| pyo3::exceptions::PyValueError::new_err(
+        format!("Unknown sampling method '{}', expected nearest, bilinear, or cubic", method)))?;
+
+    let raster: Raster<f64> = read_raster_band(path, band_indx)?;
+    Ok(raster.sample_at(e, n, method))
+}
+
+/// Sample a raster at many projected points, reading it once.
+///
+/// Like `sample_raster` for each of `coords`, without opening and reading the raster per
+/// point, e.g. for thousands of monitoring points.
+///
+/// # Arguments
+///
+/// * `path: &str` - The file path to the raster.
+/// * `coords: Vec<(f64, f64)>` - The `(easting, northing)` points, in the raster's CRS.
+/// * `method: &str` - `"nearest"`, `"bilinear"`, or `"cubic"`, as in `sample_raster`.
+/// * `band_indx: isize` - The band to sample. Defaults to `1`.
+///
+/// # Returns
+///
+/// `PyResult<Vec<Option<f64>>>` - One value per point, in input order; `None` for points
+/// outside the grid or on no-data.
+///
+/// # Errors
+///
+/// Returns `Err` (`ValueError`) for an unknown `method`, and `Err` (`IOError`) if the
+/// raster cannot be read.
+#[pyfunction(band_indx = "1")]
+fn sample_raster_points(path: &str, coords: Vec<(f64, f64)>, method: &str, band_indx: isize) -> PyResult<Vec<Option<f64>>> {
+    let method = ResampleMethod::from_str(method).map_err(|_| pyo3::exceptions::PyValueError::new_err(
+        format!("Unknown sampling method '{}', expected nearest, bilinear, or cubic", method)))?;
+
+    let raster: Raster<f64> = read_raster_band(path, band_indx)?;
+    Ok(coords.iter()
+        .map(|&(e, n)| raster.sample_at(e, n, method))
+        .collect())
+}
+
+/// Reproject a raster into another CRS and write the result.
+///
+/// Aligns inputs that arrive in different CRSs before the per-key functions, which assume
+/// identical grids; follow with `resample_if_needed` (or a resample onto the key grid) to
+/// match the key raster cell for cell. The output grid is north-up and covers the source
+/// extent, like `gdalwarp -t_srs`.
+///
+/// # Arguments
+///
+/// * `src_path: &str` - The file path to the raster (first band).
+/// * `dst_path: &str` - The output path; the driver follows its extension.
+/// * `target_proj4: &str` - The target CRS as a proj4 string.
+/// * `resampling: &str` - `"nearest"` (for categorical rasters), `"bilinear"`, or `"cubic"`.
+///
+/// # Errors
+///
+/// Returns `Err` (`ValueError`) for an unknown `resampling` or a source without a
+/// projection, and `Err` (`IOError`) if the raster cannot be read, reprojected, or written.
+#[pyfunction]
+fn reproject_raster(src_path: &str, dst_path: &str, target_proj4: &str, resampling: &str) -> PyResult<()> {
+    let method = ResampleMethod::from_str(resampling).map_err(|_| pyo3::exceptions::PyValueError::new_err(
+        format!("Unknown resampling method '{}', expected nearest, bilinear, or cubic", resampling)))?;
+
+    let raster: Raster<f64> = read_raster_band(src_path, 1)?;
+    let reprojected = raster.reproject(target_proj4, method)
+        .map_err(|e| to_py_err(&format!("Failed to reproject {}", src_path), e))?;
+    reprojected.write(dst_path)
+        .map_err(|e| to_py_err(&format!("Failed to write raster {}", dst_path), e))
+}
+
+/// Extract a transect of raster values along a line.
+///
+/// Samples `n` evenly spaced points from (`start_e`, `start_n`) to (`end_e`, `end_n`), in the
+/// raster's map units, using bilinear interpolation.
+///
+/// # Returns
+///
+/// `PyResult<Vec<f64>>` - The `n` sampled values; points outside the raster or adjacent to
+/// no-data cells are `NaN`.
+#[pyfunction]
+fn raster_profile(
+    path: &str,
+    start_e: f64,
+    start_n: f64,
+    end_e: f64,
+    end_n: f64,
+    n: usize
+) -> PyResult<Vec<f64>> {
+    let raster: Raster<f64> = read_raster_band(path, 1)?;
+    Ok(raster.extract_profile((start_e, start_n), (end_e, end_n), n))
+}
+
+/// Sample a raster at the points listed in a CSV file.
+///
+/// The CSV is streamed row by row: each point is read from the `x_col`/`y_col` columns,
+/// reprojected from `in_srs` into the raster's CRS (one transformer is built for the whole
+/// file), sampled with nearest neighbour, and written to `out_csv` with all of its original
+/// columns plus an appended `value` column. The value is left empty for points outside the
+/// raster, on no-data, or that fail to reproject.
+///
+/// Only the current row is held in memory, so memory stays flat for inputs of any size. The
+/// output is flushed every `flush_every` rows, after which `progress_fn` (if given) is called
+/// with the number of rows written so far.
+///
+/// # Arguments
+///
+/// * `raster_fn: &str` - The file path to the raster to sample.
+/// * `points_csv: &str` - The input CSV with a header row.
+/// * `out_csv: &str` - The output CSV path.
+/// * `x_col: &str` - The name of the x (easting or longitude) column.
+/// * `y_col: &str` - The name of the y (northing or latitude) column.
+/// * `in_srs: &str` - The CRS of the coordinates, e.g. `"EPSG:4326"` or a proj4 string.
+/// * `progress_fn: Option<PyObject>` - Called as `progress_fn(rows_written)` after each flush.
+///    Defaults to `None`.
+/// * `flush_every: usize` - The number of rows between flushes. Defaults to `10000`.
+///
+/// # Returns
+///
+/// `PyResult<usize>` - The number of points written.
+///
+/// # Errors
+///
+/// Returns `Err` (`ValueError`) if `flush_every` is 0, and propagates any exception raised
+/// by `progress_fn`; the rows written before it are kept.
+#[pyfunction(progress_fn = "None", flush_every = "10000")]
+fn sample_points_csv(
+    py: Python,
+    raster_fn: &str,
+    points_csv: &str,
+    out_csv: &str,
+    x_col: &str,
+    y_col: &str,
+    in_srs: &str,
+    progress_fn: Option<PyObject>,
+    flush_every: usize
+) -> PyResult<usize> {
+    if flush_every == 0 {
+        return Err(pyo3::exceptions::PyValueError::new_err("flush_every must be at least 1"));
+    }
+
+    let io_err = |path: &str, e: csv::Error| pyo3::exceptions::PyIOError::new_err(format!("{}: {}", path, e));
+
+    let raster: Raster<f64> = read_raster_band(raster_fn, 1)?;
+    let raster_srs = raster.proj4.as_ref().ok_or_else(|| pyo3::exceptions::PyValueError::new_err(
+        format!("{} has no projection to reproject points into", raster_fn)))?;
+    let transformer = Proj::new_known_crs(in_srs, raster_srs, None)
+        .map_err(|e| pyo3::exceptions::PyValueError::new_err(format!("Cannot transform from {}: {}", in_srs, e)))?;
+
+    let mut reader = csv::Reader::from_path(points_csv).map_err(|e| io_err(points_csv, e))?;
+    let mut writer = csv::Writer::from_path(out_csv).map_err(|e| io_err(out_csv, e))?;
+
+    let mut headers = reader.headers().map_err(|e| io_err(points_csv, e))?.clone();
+    let column = |name: &str| headers.iter().position(|h| h == name).ok_or_else(|| {
+        pyo3::exceptions::PyValueError::new_err(format!("{} has no column named '{}'", points_csv, name))
+    });
+    let (x_indx, y_indx) = (column(x_col)?, column(y_col)?);
+
+    headers.push_field("value");
+    writer.write_record(&headers).map_err(|e| io_err(out_csv, e))?;
+
+    let mut count: usize = 0;
+    for record in reader.records() {
+        let mut record = record.map_err(|e| io_err(points_csv, e))?;
+        let coord = |indx: usize| record[indx].trim().parse::<f64>().map_err(|_| {
+            pyo3::exceptions::PyValueError::new_err(format!(
+                "{}: invalid coordinate '{}' on data row {}", points_csv, &record[indx], count + 1))
+        });
+        let (x, y) = (coord(x_indx)?, coord(y_indx)?);
+
+        let value = transformer.convert((x, y)).ok()
+            .and_then(|(e, n)| raster.sample_nearest(e, n));
+        record.push_field(&value.map(|v| v.to_string()).unwrap_or_default());
+        writer.write_record(&record).map_err(|e| io_err(out_csv, e))?;
+        count += 1;
+
+        if count % flush_every == 0 {
+            writer.flush().map_err(|e| pyo3::exceptions::PyIOError::new_err(format!("{}: {}", out_csv, e)))?;
+            if let Some(progress_fn) = &progress_fn {
+                progress_fn.call1(py, (count,))?;
+            }
+        }
+    }
+
+    writer.flush().map_err(|e| pyo3::exceptions::PyIOError::new_err(format!("{}: {}", out_csv, e)))?;
+    Ok(count)
+}
+
+/// Find the pixel window enclosing a set of hillslopes.
+///
+/// # Arguments
+///
+/// * `subwta_fn: &str` - The file path to the SUBWTA raster providing the TOPAZ ids.
+/// * `ids: Vec<i32>` - The TOPAZ ids to enclose.
+///
+/// # Returns
+///
+/// `PyResult<(usize, usize, usize, usize)>` - The `(x0, y0, width, height)` window, in pixels,
+/// enclosing every cell of the given ids.
+///
+/// # Errors
+///
+/// Returns `Err` if the raster cannot be read or none of the ids occur in it.
+#[pyfunction]
+fn pixel_window_for_ids(subwta_fn: &str, ids: Vec<i32>) -> PyResult<(usize, usize, usize, usize)> {
+    let subwta: Raster<i32> = read_raster_band(subwta_fn, 1)?;
+
+    let mut indices: Vec<usize> = Vec::new();
+    for id in &ids {
+        indices.extend(subwta.indices_of(*id));
+    }
+
+    subwta.bounding_window(&indices).ok_or_else(|| pyo3::exceptions::PyValueError::new_err(
+        format!("None of the ids {:?} occur in {}", ids, subwta_fn)))
+}
+
+/// Clamp the valid cells of a raster to a range and write the result as a GeoTIFF.
+///
+/// # Arguments
+///
+/// * `src_fn: &str` - The file path to the raster to clamp (first band).
+/// * `min: Option<f64>` - The lower bound, or `None` for no lower bound.
+/// * `max: Option<f64>` - The upper bound, or `None` for no upper bound.
+/// * `dst_fn: &str` - The file path of the output GeoTIFF.
+///
+/// # Errors
+///
+/// Returns `Err` if `min > max` (`ValueError`) or a raster cannot be read or written (`IOError`).
+///
+/// # Note
+///
+/// No-data cells are written unchanged and keep the source's no-data value.
+#[pyfunction]
+fn raster_clamp(src_fn: &str, min: Option<f64>, max: Option<f64>, dst_fn: &str) -> PyResult<()> {
+    if let (Some(lo), Some(hi)) = (min, max) {
+        if lo > hi {
+            return Err(pyo3::exceptions::PyValueError::new_err(format!(
+                "min ({}) must not exceed max ({})", lo, hi)));
+        }
+    }
+
+    let raster: Raster<f64> = read_raster_band(src_fn, 1)?;
+    raster.clamp(min, max).write(dst_fn)
+        .map_err(|e| to_py_err(&format!("Failed to write raster {}", dst_fn), e))
+}
+
+/// Writes the cell-wise difference `a_fn - b_fn` as a GeoTIFF change map.
+///
+/// Differences whose magnitude does not exceed `threshold` are written as 0, so a change
+/// map between two loss scenarios shows only meaningful shifts rather than floating-point
+/// noise. Cells that are no-data in either raster are written as no-data (the no-data value
+/// of `a_fn`, else of `b_fn`, else -9999).
+///
+/// # Arguments
+///
+/// * `a_fn: &str` - The raster subtracted from (first band), e.g. the treatment scenario.
+/// * `b_fn: &str` - The raster subtracted (first band), e.g. the baseline.
+/// * `out_fn: &str` - The output GeoTIFF.
+/// * `threshold: f64` - The largest absolute difference treated as no change. Defaults to
+///    `0.0`, which keeps every nonzero difference.
+///
+/// # Returns
+///
+/// `PyResult<(usize, f64)>` - The number of cells with a significant difference and their
+/// area (`count * cellsize²`) in the raster's squared map units.
+///
+/// # Errors
+///
+/// Returns `Err` (`ValueError`) if `threshold` is negative or the rasters are not on the
+/// same grid, and `Err` if a raster cannot be read or the output cannot be written.
+#[pyfunction(threshold = "0.0")]
+fn raster_diff(a_fn: &str, b_fn: &str, out_fn: &str, threshold: f64) -> PyResult<(usize, f64)> {
+    let a: Raster<f64> = read_raster_band(a_fn, 1)?;
+    let b: Raster<f64> = read_raster_band(b_fn, 1)?;
+
+    let (diff, significant) = Raster::difference(&a, &b, threshold)
+        .map_err(|e| to_py_err("Failed to difference rasters", e))?;
+    diff.write(out_fn)
+        .map_err(|e| to_py_err(&format!("Failed to write raster {}", out_fn), e))?;
+
+    Ok((significant, significant as f64 * diff.cellsize * diff.cellsize))
+}
+
+/// Writes a GeoTIFF taking `a_fn` where `mask_fn` is nonzero and `b_fn` elsewhere.
+///
+/// Useful for scenario compositing, e.g. splicing a treated-hillslope loss surface into
+/// a baseline surface. Cells where the mask or the selected raster is no-data are written
+/// as no-data (the no-data value of `a_fn`, else of `b_fn`, else -9999).
+///
+/// # Arguments
+///
+/// * `mask_fn: &str` - The selection mask (first band).
+/// * `a_fn: &str` - The raster used where the mask is nonzero.
+/// * `b_fn: &str` - The raster used where the mask is zero.
+/// * `out_fn: &str` - The output GeoTIFF.
+///
+/// # Errors
+///
+/// Returns `Err` (`ValueError`) if the three rasters are not on the same grid, and `Err`
+/// if a raster cannot be read or the output cannot be written.
+#[pyfunction]
+fn raster_select(mask_fn: &str, a_fn: &str, b_fn: &str, out_fn: &str) -> PyResult<()> {
+    let mask: Raster<i32> = read_raster_band(mask_fn, 1)?;
+    let a: Raster<f64> = read_raster_band(a_fn, 1)?;
+    let b: Raster<f64> = read_raster_band(b_fn, 1)?;
+
+    let selected = Raster::select(&mask, &a, &b)
+        .map_err(|e| to_py_err("Failed to select rasters", e))?;
+    selected.write(out_fn)
+        .map_err(|e| to_py_err(&format!("Failed to write raster {}", out_fn), e))
+}
+
+/// Writes a GeoTIFF of `src_fn` restricted to the valid cells of `mask_fn`.
+///
+/// A preprocessing step before zonal statistics, e.g. clipping a continuous surface to the
+/// watershed delineated in a `SUBWTA`. Cells that are no-data in the mask are written as
+/// no-data (the no-data value of `src_fn`, else -9999).
+///
+/// # Arguments
+///
+/// * `src_fn: &str` - The raster to restrict (first band).
+/// * `mask_fn: &str` - The raster whose valid cells are kept (first band).
+/// * `dst_fn: &str` - The output GeoTIFF.
+///
+/// # Errors
+///
+/// Returns `Err` (`ValueError`) if the rasters are not on the same grid, and `Err` if a
+/// raster cannot be read or the output cannot be written.
+#[pyfunction]
+fn raster_masked_by_valid(src_fn: &str, mask_fn: &str, dst_fn: &str) -> PyResult<()> {
+    let raster: Raster<f64> = read_raster_band(src_fn, 1)?;
+    let mask: Raster<f64> = read_raster_band(mask_fn, 1)?;
+
+    let masked = raster.masked_by_valid(&mask)
+        .map_err(|e| to_py_err(&format!("Failed to mask {} by {}", src_fn, mask_fn), e))?;
+    masked.write(dst_fn)
+        .map_err(|e| to_py_err(&format!("Failed to write raster {}", dst_fn), e))
+}
+
+/// Replace NaN and infinite cells of a raster with no-data and write the result as a GeoTIFF.
+///
+/// A pre-processing step for float grids from upstream tools, whose stray NaN/inf cells
+/// otherwise turn statistics such as the mean into NaN.
+///
+/// # Arguments
+///
+/// * `src_fn: &str` - The file path to the raster to clean (first band).
+/// * `dst_fn: &str` - The file path of the output GeoTIFF.
+/// * `sentinel: Option<f64>` - The value written for NaN, infinite, and no-data cells, and the
+///    output's no-data value. Defaults to `None`: the source's no-data value, else -9999.
+///
+/// # Errors
+///
+/// Returns `Err` if the raster cannot be read or written.
+#[pyfunction(sentinel = "None")]
+fn raster_sanitize(src_fn: &str, dst_fn: &str, sentinel: Option<f64>) -> PyResult<()> {
+    let raster: Raster<f64> = read_raster_band(src_fn, 1)?;
+    let sanitized = match sentinel {
+        Some(sentinel) => raster.sanitize_with(sentinel),
+        None => raster.sanitize(),
+    };
+    sanitized.write(dst_fn)
+        .map_err(|e| to_py_err(&format!("Failed to write raster {}", dst_fn), e))
+}
+
+/// Assign a CRS to a raster that has none and write the result.
+///
+/// Raw TOPAZ `.ARC` grids carry no projection, which makes coordinate functions unusable
+/// on them; this georeferences a grid whose CRS is known, e.g. a specific UTM zone.
+///
+/// # Arguments
+///
+/// * `src_fn: &str` - The file path to the raster (first band).
+/// * `proj4: &str` - The CRS as a proj4 string, e.g. `"+proj=utm +zone=11 +datum=WGS84"`.
+/// * `dst_fn: &str` - The output path; the driver follows its extension.
+///
+/// # Errors
+///
+/// Returns `Err` if the raster cannot be read or written, or `proj4` cannot be transformed
+/// to WGS84.
+///
+/// # Note
+///
+/// The cells are written as 64-bit floats; integer ids such as TOPAZ keys are kept exactly.
+#[pyfunction]
+fn raster_set_projection(src_fn: &str, proj4: &str, dst_fn: &str) -> PyResult<()> {
+    let mut raster: Raster<f64> = read_raster_band(src_fn, 1)?;
+    raster.set_proj4(proj4)
+        .map_err(|e| to_py_err(&format!("Failed to assign {} to {}", proj4, src_fn), e))?;
+    raster.write(dst_fn)
+        .map_err(|e| to_py_err(&format!("Failed to write raster {}", dst_fn), e))
+}
+
+/// Returns the WGS84 `(west, south, east, north)` bounds of the valid (non no-data)
+/// cells of the first band of the raster at `path`, for fitting a web map to the
+/// data rather than the full raster extent.
+///
+/// # Returns
+///
+/// `PyResult<Option<(f64, f64, f64, f64)>>` - `None` when the raster has no valid
+/// cells or no projection.
+///
+/// # Errors
+///
+/// Returns `Err` (a Python `IOError` naming the file) if the raster cannot be read.
+#[pyfunction]
+fn raster_valid_bounds(path: &str) -> PyResult<Option<(f64, f64, f64, f64)>> {
+    let raster: Raster<f64> = read_raster_band(path, 1)?;
+    Ok(raster.valid_bounds_lnglat())
+}
+
+/// Sets how many rasters are kept in the in-process read cache.
+///
+/// Cached rasters are keyed by path, band, and modification time, so a file that is
+/// regenerated on disk is re-read. A size of 0 (the default) disables caching.
+#[pyfunction]
+fn set_raster_cache_size(n: usize) -> PyResult<()> {
+    cache::set_cache_size(n);
+    Ok(())
+}
+
+/// Drops every raster held in the in-process read cache.
+#[pyfunction]
+fn clear_raster_cache() -> PyResult<()> {
+    cache::clear_cache();
+    Ok(())
+}
+
+/// A raster band read into memory, for inspecting a raster from Python without going
+/// through the per-key aggregation functions.
+///
+/// ```python
+/// dem = Raster.read("dem.tif")
+/// height, width = dem.shape()
+/// print(dem.band_statistics()["mean"])
+/// ```
+#[pyclass(name = "Raster")]
+struct PyRaster {
+    raster: Raster<f64>,
+}
+
+#[pymethods]
+impl PyRaster {
+    /// Reads band `band_indx` (default 1) of the raster at `path`.
+    ///
+    /// Raises `IOError` naming the file if it cannot be read.
+    #[staticmethod]
+    #[args(band_indx = "1")]
+    fn read(path: &str, band_indx: isize) -> PyResult<PyRaster> {
+        Ok(PyRaster { raster: read_raster_band(path, band_indx)? })
+    }
+
+    /// Returns the distinct values of the valid (not no-data, not NaN) cells, ascending.
+    fn unique_values(&self) -> Vec<f64> {
+        let mut values: Vec<f64> = self.raster.data.iter()
+            .copied()
+            .filter(|&v| !v.is_nan() && !self.raster.is_no_data(v))
+            .collect();
+        values.sort_by(|a, b| a.partial_cmp(b).unwrap());
+        values.dedup();
+        values
+    }
+
+    /// Returns `minimum`, `maximum`, `mean`, `std_dev`, `valid_percent`, `median`, `p2`, and
+    /// `p98` (the 2nd and 98th percentiles) of the band.
+    fn band_statistics(&self) -> HashMap<String, f64> {
+        let stats = self.raster.compute_band_statistics();
+        let mut result = HashMap::new();
+        result.insert("minimum".to_string(), stats.minimum);
+        result.insert("maximum".to_string(), stats.maximum);
+        result.insert("mean".to_string(), stats.mean);
+        result.insert("std_dev".to_string(), stats.std_dev);
+        result.insert("valid_percent".to_string(), stats.valid_percent);
+        result.insert("median".to_string(), stats.median);
+        result.insert("p2".to_string(), stats.p2);
+        result.insert("p98".to_string(), stats.p98);
+        result
+    }
+
+    /// Returns `(height, width)`, the shape of the band as a numpy array.
+    fn shape(&self) -> (usize, usize) {
+        (self.raster.height, self.raster.width)
+    }
+
+    /// Returns the cell size in the units of the raster's CRS.
+    fn cellsize(&self) -> f64 {
+        self.raster.cellsize
+    }
+
+    fn __repr__(&self) -> String {
+        format!("Raster({:?}, {} x {}, cellsize {})",
+            self.raster.path, self.raster.width, self.raster.height, self.raster.cellsize)
     }
 }
 
@@ -427,10 +2837,62 @@ fn calculate_median(mut values: Vec<f64>) -> f64 {
 /// This module is a container for the Python-callable functions we define
 #[pymodule]
 fn raster_characteristics_rust(_py: Python, m: &PyModule) -> PyResult<()> {
+    m.add_class::<PyRaster>()?;
+    m.add_class::<ZonalStats>()?;
     m.add_function(wrap_pyfunction!(identify_mode_single_raster_key, m)?)?;
+    m.add_function(wrap_pyfunction!(identify_mode_with_counts_single_raster_key, m)?)?;
+    m.add_function(wrap_pyfunction!(identify_histogram_single_raster_key, m)?)?;
+    m.add_function(wrap_pyfunction!(identify_mode_multi_parameter, m)?)?;
+    m.add_function(wrap_pyfunction!(identify_mode_batch, m)?)?;
     m.add_function(wrap_pyfunction!(identify_mode_intersecting_raster_keys, m)?)?;
+    m.add_function(wrap_pyfunction!(identify_mode_three_raster_keys, m)?)?;
     m.add_function(wrap_pyfunction!(identify_median_single_raster_key, m)?)?;
+    m.add_function(wrap_pyfunction!(identify_stat_single_raster_key, m)?)?;
+    m.add_function(wrap_pyfunction!(identify_percentile_single_raster_key, m)?)?;
+    m.add_function(wrap_pyfunction!(identify_sum_area_weighted_single_raster_key, m)?)?;
     m.add_function(wrap_pyfunction!(identify_median_intersecting_raster_keys, m)?)?;
+    m.add_function(wrap_pyfunction!(identify_stats_single_raster_key_json, m)?)?;
+    m.add_function(wrap_pyfunction!(identify_stats_in_polygon, m)?)?;
+    m.add_function(wrap_pyfunction!(identify_dominant_aspect_class, m)?)?;
+    m.add_function(wrap_pyfunction!(identify_cv_single_raster_key, m)?)?;
+    m.add_function(wrap_pyfunction!(identify_hypsometric_integral, m)?)?;
+    m.add_function(wrap_pyfunction!(identify_correlation_per_key, m)?)?;
+    m.add_function(wrap_pyfunction!(hillslope_geometry, m)?)?;
+    m.add_function(wrap_pyfunction!(hillslope_outlets, m)?)?;
+    m.add_function(wrap_pyfunction!(raster_histogram, m)?)?;
+    m.add_function(wrap_pyfunction!(raster_count_in_range, m)?)?;
+    m.add_function(wrap_pyfunction!(raster_sum, m)?)?;
+    m.add_function(wrap_pyfunction!(raster_count_nonzero, m)?)?;
+    m.add_function(wrap_pyfunction!(raster_band_statistics_all, m)?)?;
+    m.add_function(wrap_pyfunction!(check_alignment, m)?)?;
+    m.add_function(wrap_pyfunction!(raster_diff, m)?)?;
+    m.add_function(wrap_pyfunction!(raster_select, m)?)?;
+    m.add_function(wrap_pyfunction!(raster_masked_by_valid, m)?)?;
+    m.add_function(wrap_pyfunction!(raster_unique_sorted, m)?)?;
+    m.add_function(wrap_pyfunction!(raster_as_masked_numpy, m)?)?;
+    m.add_function(wrap_pyfunction!(raster_from_numpy, m)?)?;
+    m.add_function(wrap_pyfunction!(raster_sanitize, m)?)?;
+    m.add_function(wrap_pyfunction!(raster_set_projection, m)?)?;
+    m.add_function(wrap_pyfunction!(raster_footprint, m)?)?;
+    m.add_function(wrap_pyfunction!(value_at_centroids, m)?)?;
+    m.add_function(wrap_pyfunction!(paint_hillslope_values, m)?)?;
+    m.add_function(wrap_pyfunction!(zonal_stat_to_raster, m)?)?;
+    m.add_function(wrap_pyfunction!(zonal_statistics, m)?)?;
+    m.add_function(wrap_pyfunction!(render_categorical_png, m)?)?;
+    m.add_function(wrap_pyfunction!(subwta_hillslopes_only, m)?)?;
+    m.add_function(wrap_pyfunction!(raster_nodata, m)?)?;
+    m.add_function(wrap_pyfunction!(mosaic, m)?)?;
+    m.add_function(wrap_pyfunction!(rasterize_points, m)?)?;
+    m.add_function(wrap_pyfunction!(sample_raster, m)?)?;
+    m.add_function(wrap_pyfunction!(sample_raster_points, m)?)?;
+    m.add_function(wrap_pyfunction!(reproject_raster, m)?)?;
+    m.add_function(wrap_pyfunction!(raster_profile, m)?)?;
+    m.add_function(wrap_pyfunction!(sample_points_csv, m)?)?;
+    m.add_function(wrap_pyfunction!(pixel_window_for_ids, m)?)?;
+    m.add_function(wrap_pyfunction!(raster_clamp, m)?)?;
+    m.add_function(wrap_pyfunction!(raster_valid_bounds, m)?)?;
+    m.add_function(wrap_pyfunction!(set_raster_cache_size, m)?)?;
+    m.add_function(wrap_pyfunction!(clear_raster_cache, m)?)?;
     Ok(())
 }
 
@@ -1,9 +1,14 @@
 use pyo3::prelude::*;
 use pyo3::wrap_pyfunction;
+use std::cell::RefCell;
+use std::collections::HashMap;
 use std::fs::File;
 use std::io::{self, BufRead};
 use std::path::Path;
+use std::str::FromStr;
 use raster::raster::Raster;
+use raster::error::RasterError;
+use raster::zonal::{is_channel, BACKGROUND_KEY};
 
 
 fn read_plot_fn(plot_fn: &Path) -> Result<(Vec<f64>, f64), io::Error> {
@@ -62,10 +67,57 @@ fn interp(x: f64, dx:f64, fp: &Vec<f64>) -> f64 {
 }
 
 
+/// Units of the soil-loss grid. The plot file values are written as-is in `Kg`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum LossUnits {
+    Kg,
+    KgPerM2,
+    TonnesPerHa,
+}
+
+impl FromStr for LossUnits {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "kg" => Ok(LossUnits::Kg),
+            "kg/m2" => Ok(LossUnits::KgPerM2),
+            "t/ha" => Ok(LossUnits::TonnesPerHa),
+            _ => Err(()),
+        }
+    }
+}
+
+impl LossUnits {
+    /// The label stamped as the `units` metadata of the output raster.
+    pub fn label(&self) -> &'static str {
+        match self {
+            LossUnits::Kg => "kg",
+            LossUnits::KgPerM2 => "kg/m2",
+            LossUnits::TonnesPerHa => "t/ha",
+        }
+    }
+
+    /// Factor converting a per-cell loss in kg to these units.
+    pub fn scale(&self, cellsize: f64) -> f64 {
+        let cell_area = cellsize * cellsize;
+        match self {
+            LossUnits::Kg => 1.0,
+            LossUnits::KgPerM2 => 1.0 / cell_area,
+            // kg -> t is / 1000, per m2 -> per ha is * 10000
+            LossUnits::TonnesPerHa => 10000.0 / (1000.0 * cell_area),
+        }
+    }
+}
+
+
 #[derive(Debug)]
 pub enum SoilLossError {
     IoError(std::io::Error),
     GdalError(gdal::errors::GdalError),
+    RasterError(RasterError),
+    /// the progress callback asked to stop
+    Cancelled,
     // Add other error types as needed
 }
 
@@ -81,34 +133,93 @@ impl From<gdal::errors::GdalError> for SoilLossError {
     }
 }
 
+impl From<RasterError> for SoilLossError {
+    fn from(err: RasterError) -> SoilLossError {
+        SoilLossError::RasterError(err)
+    }
+}
+
+
+/// sorted topaz hillslope ids; the i-th id (from 1) has the plot file H{i}.plot.dat
+fn hillslope_ids(subwta: &Raster<i32>, exclude_zero: bool) -> Vec<i32> {
+    subwta.sorted_unique_values()
+        .into_iter()
+        .filter(|&x| !(exclude_zero && x == BACKGROUND_KEY) && !is_channel(x))
+        .collect()
+}
+
+/// parses the wepp hillslope number n from a plot file name H{n}.plot.dat
+fn plot_file_number(file_name: &str) -> Option<usize> {
+    file_name.strip_prefix('H')?.strip_suffix(".plot.dat")?.parse().ok()
+}
+
+
+fn validate_plot_hillslope_mapping_rs(
+    subwta_fn: &str,
+    output_dir: &str,
+    exclude_zero: bool
+) -> Result<HashMap<String, String>, SoilLossError> {
+
+    let subwta: Raster<i32> = Raster::<i32>::read(subwta_fn)?;
+    let topaz_ids = hillslope_ids(&subwta, exclude_zero);
+
+    let mut plot_numbers: Vec<usize> = Vec::new();
+    for entry in std::fs::read_dir(output_dir)? {
+        if let Some(n) = entry?.file_name().to_str().and_then(plot_file_number) {
+            plot_numbers.push(n);
+        }
+    }
+
+    let mut problems: HashMap<String, String> = HashMap::new();
+
+    for (i, topaz_id) in topaz_ids.iter().enumerate() {
+        let n = i + 1;
+        if !plot_numbers.contains(&n) {
+            problems.insert(topaz_id.to_string(), format!("missing H{}.plot.dat", n));
+        }
+    }
+
+    for n in plot_numbers {
+        if n == 0 || n > topaz_ids.len() {
+            problems.insert(format!("H{}.plot.dat", n), "no topaz id in SUBWTA".to_string());
+        }
+    }
+
+    Ok(problems)
+}
+
 
 fn make_soil_loss_grid_rs(
     subwta_fn: &str,
     discha_fn: &str,
     output_dir: &str,
-    loss_fn: &str
-) -> Result<i32, SoilLossError> {
+    loss_fn: &str,
+    exclude_zero: bool,
+    units: LossUnits,
+    driver: Option<&str>,
+    max_plausible_loss: Option<f64>,
+    progress: Option<&dyn Fn(f64) -> bool>
+) -> Result<(i32, usize), SoilLossError> {
 
-    let discha: Raster<f64> = Raster::<f64>::read(discha_fn).unwrap();
-    let subwta: Raster<i32> = Raster::<i32>::read(subwta_fn).unwrap();
+    let discha: Raster<f64> = Raster::<f64>::read(discha_fn)?;
+    let subwta: Raster<i32> = Raster::<i32>::read(subwta_fn)?;
 
-    let mut topaz_ids: Vec<i32> = subwta.unique_values()
-        .into_iter()
-        .filter(|&x| x != 0 && x % 10 != 4)
-        .collect();
-    topaz_ids.sort();
+    let topaz_ids = hillslope_ids(&subwta, exclude_zero);
+    let groups = subwta.group_indices();
 
     let mut i: i32 = 1;
+    let mut clamped: usize = 0;
     let mut soil_loss_grid = discha.empty_clone();
+    let scale = units.scale(discha.cellsize);
 
     for topaz_id in &topaz_ids {
 //        println!("topaz_id: {}", topaz_id);
         let plot_fn = format!("{}/H{}.plot.dat", output_dir, i);
 
-        let indices = subwta.indices_of(*topaz_id);
+        let indices: &[usize] = groups.get(topaz_id).map(Vec::as_slice).unwrap_or(&[]);
 
         let mut max_discha: f64 = 0.0;
-        for indx in &indices {
+        for indx in indices {
             let _discha = discha.data[*indx];
             if _discha > max_discha {
                 max_discha = _discha;
@@ -126,31 +237,154 @@ fn make_soil_loss_grid_rs(
 
         let (soil_loss, dx) = read_plot_fn(&Path::new(&plot_fn))?;
 
-        for indx in &indices {
+        for indx in indices {
             let normed_discha = discha.data[*indx] / max_discha;
-            let loss = interp(normed_discha, dx, &soil_loss);
-            soil_loss_grid.data[*indx] = loss;
+            let mut loss = interp(normed_discha, dx, &soil_loss);
+            if let Some(max_loss) = max_plausible_loss {
+                if loss > max_loss {
+                    loss = max_loss;
+                    clamped += 1;
+                }
+            }
+            soil_loss_grid.data[*indx] = loss * scale;
+        }
+
+        if let Some(progress) = progress {
+            if !progress(i as f64 / topaz_ids.len() as f64) {
+                return Err(SoilLossError::Cancelled);
+            }
         }
 
         i += 1;
     }
 
-    soil_loss_grid.write(loss_fn)?;
+    soil_loss_grid.write_with_options(loss_fn, driver, &[("units", units.label())])?;
 
-    Ok(i)
+    Ok((i, clamped))
+}
+
+
+/// Runs `make_soil_loss_grid_rs` for the Python wrappers, returning
+/// `(count, clamped_cells)`.
+fn run_make_soil_loss_grid(
+    py: Python,
+    subwta_fn: &str,
+    discha_fn: &str,
+    output_dir: &str,
+    loss_fn: &str,
+    exclude_zero: bool,
+    units: &str,
+    driver: Option<&str>,
+    max_plausible_loss: Option<f64>,
+    progress_fn: Option<PyObject>
+) -> PyResult<(i32, usize)> {
+    let units = LossUnits::from_str(units).map_err(|_| pyo3::exceptions::PyValueError::new_err(format!(
+        "Invalid units '{}': expected 'kg', 'kg/m2', or 't/ha'", units)))?;
+
+    // an exception raised by progress_fn cancels the run and is re-raised
+    let callback_err: RefCell<Option<PyErr>> = RefCell::new(None);
+    let progress = |fraction: f64| -> bool {
+        let keep_going = progress_fn.as_ref()
+            .map(|f| f.call1(py, (fraction,)).and_then(|r| r.extract::<Option<bool>>(py)));
+        match keep_going {
+            Some(Ok(keep_going)) => keep_going.unwrap_or(true),
+            Some(Err(e)) => {
+                *callback_err.borrow_mut() = Some(e);
+                false
+            },
+            None => true,
+        }
+    };
+
+    let result = make_soil_loss_grid_rs(
+        subwta_fn, discha_fn, output_dir, loss_fn, exclude_zero, units, driver, max_plausible_loss,
+        progress_fn.as_ref().map(|_| &progress as &dyn Fn(f64) -> bool));
+    if let Some(e) = callback_err.into_inner() {
+        return Err(e);
+    }
+    result.map_err(|e| PyErr::new::<pyo3::exceptions::PyRuntimeError, _>(format!("{:?}", e)))
 }
 
 
 /// makes a soil-loss grid from topaz distance to channel map
 /// and wepp plot file outputs
-#[pyfunction]
+///
+/// exclude_zero (default true) skips the topaz background id 0; when false
+/// 0 is treated as a hillslope and needs its own plot file
+///
+/// units (default "kg") is one of "kg", "kg/m2", or "t/ha" and is stamped as
+/// the `units` metadata of the output raster
+///
+/// the output format follows the extension of loss_fn (.tif, .asc, or .img)
+/// unless driver names a GDAL driver, e.g. "GTiff"
+///
+/// max_plausible_loss (default None) clamps interpolated plot losses above it,
+/// in the plot file's units before conversion, to guard against corrupt plot
+/// files. the number of clamped cells, if any, is reported with a
+/// RuntimeWarning; use make_soil_loss_grid_guarded to get it as a value
+///
+/// progress_fn (default None) is called with the fraction of hillslopes done
+/// after each hillslope; returning False stops the run with a RuntimeError
+/// before anything is written
+#[pyfunction(exclude_zero = "true", units = "\"kg\"", driver = "None", max_plausible_loss = "None", progress_fn = "None")]
 fn make_soil_loss_grid(
+    py: Python,
     subwta_fn: &str,
     discha_fn: &str,
     output_dir: &str,
-    loss_fn: &str
+    loss_fn: &str,
+    exclude_zero: bool,
+    units: &str,
+    driver: Option<&str>,
+    max_plausible_loss: Option<f64>,
+    progress_fn: Option<PyObject>
 ) -> PyResult<i32> {
-    make_soil_loss_grid_rs(subwta_fn, discha_fn, output_dir, loss_fn)
+    let (i, clamped) = run_make_soil_loss_grid(py, subwta_fn, discha_fn, output_dir, loss_fn,
+        exclude_zero, units, driver, max_plausible_loss, progress_fn)?;
+
+    if clamped > 0 {
+        PyErr::warn(py, py.get_type::<pyo3::exceptions::PyRuntimeWarning>(),
+            &format!("{} cells exceeded max_plausible_loss and were clamped", clamped), 1)?;
+    }
+    Ok(i)
+}
+
+
+/// like make_soil_loss_grid, but max_plausible_loss is required and the
+/// result is always (count, clamped_cells), where clamped_cells is the number
+/// of cells whose interpolated loss was clamped to max_plausible_loss
+#[pyfunction(exclude_zero = "true", units = "\"kg\"", driver = "None", progress_fn = "None")]
+fn make_soil_loss_grid_guarded(
+    py: Python,
+    subwta_fn: &str,
+    discha_fn: &str,
+    output_dir: &str,
+    loss_fn: &str,
+    max_plausible_loss: f64,
+    exclude_zero: bool,
+    units: &str,
+    driver: Option<&str>,
+    progress_fn: Option<PyObject>
+) -> PyResult<(i32, usize)> {
+    run_make_soil_loss_grid(py, subwta_fn, discha_fn, output_dir, loss_fn,
+        exclude_zero, units, driver, Some(max_plausible_loss), progress_fn)
+}
+
+
+/// checks that the wepp plot files in output_dir line up with the topaz ids
+/// in subwta_fn before running make_soil_loss_grid
+///
+/// returns an empty dict when they do; otherwise each topaz id without a plot
+/// file maps to "missing H{n}.plot.dat" and each plot file without a topaz id
+/// maps to "no topaz id in SUBWTA". exclude_zero (default true) matches
+/// make_soil_loss_grid
+#[pyfunction(exclude_zero = "true")]
+fn validate_plot_hillslope_mapping(
+    subwta_fn: &str,
+    output_dir: &str,
+    exclude_zero: bool
+) -> PyResult<HashMap<String, String>> {
+    validate_plot_hillslope_mapping_rs(subwta_fn, output_dir, exclude_zero)
         .map_err(|e| PyErr::new::<pyo3::exceptions::PyRuntimeError, _>(format!("{:?}", e)))
 }
 
@@ -160,6 +394,8 @@ fn make_soil_loss_grid(
 #[pymodule]
 fn wepp_viz_rust(_py: Python, m: &PyModule) -> PyResult<()> {
     m.add_function(wrap_pyfunction!(make_soil_loss_grid, m)?)?;
+    m.add_function(wrap_pyfunction!(make_soil_loss_grid_guarded, m)?)?;
+    m.add_function(wrap_pyfunction!(validate_plot_hillslope_mapping, m)?)?;
     Ok(())
 }
 
@@ -167,7 +403,7 @@ fn wepp_viz_rust(_py: Python, m: &PyModule) -> PyResult<()> {
 #[cfg(test)]
 mod tests {
 
-    use crate::make_soil_loss_grid_rs;
+    use crate::{make_soil_loss_grid_rs, plot_file_number, LossUnits};
 
     #[test]
     fn test_make_soil_loss_grid() {
@@ -176,13 +412,32 @@ mod tests {
     "/geodata/weppcloud_runs/mdobre-womanly-ascot/dem/topaz/SUBWTA.ARC",
     "/geodata/weppcloud_runs/mdobre-womanly-ascot/dem/topaz/DISCHA.ARC", 
     "/geodata/weppcloud_runs/mdobre-womanly-ascot/wepp/output",
-    "/home/roger/loss.tif");
+    "/home/roger/loss.tif",
+    true,
+    LossUnits::Kg,
+    None,
+    None,
+    None);
 
 
         let result = 165;
         // Assert conditions on the result
         assert_eq!(result, 165); // replace ... with the expected value
     }
+
+    #[test]
+    fn test_plot_file_number() {
+        assert_eq!(plot_file_number("H12.plot.dat"), Some(12));
+        assert_eq!(plot_file_number("H12.loss.dat"), None);
+        assert_eq!(plot_file_number("pass_pw0.txt"), None);
+    }
+
+    #[test]
+    fn test_loss_units_scale() {
+        assert_eq!(LossUnits::Kg.scale(30.0), 1.0);
+        assert_eq!(LossUnits::KgPerM2.scale(10.0), 0.01);
+        assert_eq!(LossUnits::TonnesPerHa.scale(10.0), 0.1);
+    }
 }
 
 